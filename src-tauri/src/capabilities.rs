@@ -0,0 +1,249 @@
+use crate::gpu::{GpuInfo, GpuVendor};
+use crate::state::AppState;
+use ort::execution_providers::{
+    CPUExecutionProvider, CUDAExecutionProvider, CoreMLExecutionProvider,
+    DirectMLExecutionProvider, ExecutionProvider, OpenVINOExecutionProvider, ROCmExecutionProvider,
+};
+use serde::Serialize;
+use std::path::PathBuf;
+use sysinfo::System;
+
+/// Provider ids accepted as `execution_provider`, with their display names.
+pub const PROVIDERS: [(&str, &str); 6] = [
+    ("directml", "DirectML"),
+    ("cuda", "CUDA"),
+    ("rocm", "ROCm"),
+    ("openvino", "OpenVINO"),
+    ("coreml", "CoreML"),
+    ("cpu", "CPU"),
+];
+
+/// An execution provider as seen by the loaded ONNX Runtime build.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderInfo {
+    pub id: String,
+    pub label: String,
+    /// ORT's own name, e.g. "CUDAExecutionProvider"
+    pub ort_name: String,
+    /// Compiled into the ONNX Runtime library on disk
+    pub compiled: bool,
+    /// Compiled in, supported on this OS and backed by matching hardware
+    pub available: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeInfo {
+    /// e.g. "1.22.0", parsed from the build string
+    pub version: Option<String>,
+    pub build: String,
+    pub library: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CpuInfo {
+    pub brand: String,
+    pub arch: String,
+    pub physical_cores: Option<usize>,
+    pub logical_cores: usize,
+    pub simd: Vec<&'static str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryInfo {
+    pub total_mb: u64,
+    pub available_mb: u64,
+}
+
+/// A cached session and the provider it actually ended up on.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub model: String,
+    pub requested: String,
+    pub execution_provider: String,
+    pub sessions: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CapabilityReport {
+    pub runtime: RuntimeInfo,
+    pub providers: Vec<ProviderInfo>,
+    pub cpu: CpuInfo,
+    pub memory: MemoryInfo,
+    pub gpu: Option<GpuInfo>,
+    pub sessions: Vec<SessionInfo>,
+}
+
+/// (ORT name, compiled in, supported on this OS)
+fn probe(id: &str) -> Option<(&'static str, bool, bool)> {
+    fn check<E: ExecutionProvider>(ep: E) -> (&'static str, bool, bool) {
+        (
+            ep.name(),
+            ep.is_available().unwrap_or(false),
+            ep.supported_by_platform(),
+        )
+    }
+
+    Some(match id {
+        "directml" => check(DirectMLExecutionProvider::default()),
+        "cuda" => check(CUDAExecutionProvider::default()),
+        "rocm" => check(ROCmExecutionProvider::default()),
+        "openvino" => check(OpenVINOExecutionProvider::default()),
+        "coreml" => check(CoreMLExecutionProvider::default()),
+        "cpu" => check(CPUExecutionProvider::default()),
+        _ => return None,
+    })
+}
+
+/// Whether the loaded ONNX Runtime can register this provider at all.
+/// Cheap enough to call before every registration attempt.
+pub fn is_supported(id: &str) -> bool {
+    probe(id).is_some_and(|(_, compiled, platform)| compiled && platform)
+}
+
+/// "ORT Build Info: git-branch=rel-1.22.0, ..." -> "1.22.0"
+pub fn parse_version(build: &str) -> Option<String> {
+    let branch = build.split("git-branch=").nth(1)?;
+    let branch = branch.split([',', ' ']).next()?;
+    let version = branch.strip_prefix("rel-").unwrap_or(branch);
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+/// Where ort loads the runtime from: `ORT_DYLIB_PATH`, else the platform
+/// default next to the executable, else whatever the OS loader finds.
+fn library_path() -> String {
+    let path = match std::env::var("ORT_DYLIB_PATH") {
+        Ok(p) if !p.is_empty() => PathBuf::from(p),
+        _ if cfg!(target_os = "windows") => PathBuf::from("onnxruntime.dll"),
+        _ if cfg!(target_os = "macos") => PathBuf::from("libonnxruntime.dylib"),
+        _ => PathBuf::from("libonnxruntime.so"),
+    };
+    if path.is_absolute() {
+        return path.to_string_lossy().to_string();
+    }
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(&path)))
+        .filter(|p| p.exists())
+        .unwrap_or(path)
+        .to_string_lossy()
+        .to_string()
+}
+
+fn simd_features() -> Vec<&'static str> {
+    #[allow(unused_mut)]
+    let mut simd = Vec::new();
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        let detected = [
+            ("sse4.2", is_x86_feature_detected!("sse4.2")),
+            ("avx", is_x86_feature_detected!("avx")),
+            ("avx2", is_x86_feature_detected!("avx2")),
+            ("fma", is_x86_feature_detected!("fma")),
+            ("avx512f", is_x86_feature_detected!("avx512f")),
+            ("avx512vnni", is_x86_feature_detected!("avx512vnni")),
+        ];
+        simd.extend(detected.iter().filter(|(_, on)| *on).map(|(name, _)| *name));
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        let detected = [
+            ("neon", std::arch::is_aarch64_feature_detected!("neon")),
+            (
+                "dotprod",
+                std::arch::is_aarch64_feature_detected!("dotprod"),
+            ),
+            ("fp16", std::arch::is_aarch64_feature_detected!("fp16")),
+            ("i8mm", std::arch::is_aarch64_feature_detected!("i8mm")),
+        ];
+        simd.extend(detected.iter().filter(|(_, on)| *on).map(|(name, _)| *name));
+    }
+
+    simd
+}
+
+/// CUDA and ROCm builds report themselves as available even without the
+/// matching GPU, so those two are hidden when another vendor's GPU was
+/// detected. An unknown vendor (detection pending or failed) hides nothing.
+fn hardware_matches(id: &str, gpu: Option<&GpuInfo>) -> bool {
+    let Some(vendor) = gpu.map(|g| &g.vendor).filter(|&v| *v != GpuVendor::Unknown) else {
+        return true;
+    };
+    match id {
+        "cuda" => *vendor == GpuVendor::Nvidia,
+        "rocm" => *vendor == GpuVendor::Amd,
+        _ => true,
+    }
+}
+
+pub fn report(state: &AppState) -> CapabilityReport {
+    let gpu = state.gpu_info.lock().unwrap().clone();
+
+    let providers = PROVIDERS
+        .iter()
+        .filter_map(|&(id, label)| {
+            let (ort_name, compiled, platform) = probe(id)?;
+            Some(ProviderInfo {
+                id: id.to_string(),
+                label: label.to_string(),
+                ort_name: ort_name.to_string(),
+                compiled,
+                available: compiled && platform && hardware_matches(id, gpu.as_ref()),
+            })
+        })
+        .collect();
+
+    let build = ort::info().to_string();
+    let runtime = RuntimeInfo {
+        version: parse_version(&build),
+        build,
+        library: library_path(),
+    };
+
+    let mut sys = System::new();
+    sys.refresh_cpu_all();
+    sys.refresh_memory();
+    let cpu = CpuInfo {
+        brand: sys
+            .cpus()
+            .first()
+            .map(|c| c.brand().trim().to_string())
+            .unwrap_or_default(),
+        arch: System::cpu_arch(),
+        physical_cores: System::physical_core_count(),
+        logical_cores: sys.cpus().len(),
+        simd: simd_features(),
+    };
+    let memory = MemoryInfo {
+        total_mb: sys.total_memory() / 1024 / 1024,
+        available_mb: sys.available_memory() / 1024 / 1024,
+    };
+
+    let sessions = state
+        .model_cache
+        .lock()
+        .unwrap()
+        .entries()
+        .iter()
+        .map(|entry| SessionInfo {
+            model: entry
+                .path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            requested: entry.session.provider_id.clone(),
+            execution_provider: entry.session.execution_provider.clone(),
+            sessions: entry.session.sessions.len(),
+        })
+        .collect();
+
+    CapabilityReport {
+        runtime,
+        providers,
+        cpu,
+        memory,
+        gpu,
+        sessions,
+    }
+}
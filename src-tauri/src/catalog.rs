@@ -0,0 +1,442 @@
+use crate::error::{AppError, AppResult};
+use crate::onnx::OnnxInfo;
+use crate::tensor_format::TensorFormatOverride;
+use crate::tuning::hash_model;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use ureq::unversioned::resolver::DefaultResolver;
+use ureq::unversioned::transport::{
+    Buffers, ConnectionDetails, Connector, DefaultConnector, NextTimeout, Transport,
+};
+use ureq::Timeout;
+
+const SOURCE_FILE: &str = "catalog.json";
+
+/// The index is small; anything bigger is not an index.
+const MAX_INDEX_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Where the model index lives: an http(s) URL, a `file://` URL or a plain path.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CatalogSource {
+    pub url: Option<String>,
+}
+
+impl CatalogSource {
+    pub fn load(app_data_dir: &Path) -> Self {
+        let path = app_data_dir.join(SOURCE_FILE);
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring unreadable catalog source {:?}: {}", path, e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, app_data_dir: &Path) -> AppResult<()> {
+        std::fs::create_dir_all(app_data_dir)?;
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(app_data_dir.join(SOURCE_FILE), content)?;
+        Ok(())
+    }
+}
+
+/// A model as listed in the index. A relative `url` is resolved against the
+/// index location, so an index and its models can be served from one folder.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Name it is installed under in the user model folder
+    pub filename: String,
+    pub url: String,
+    pub scale: u32,
+    /// In bytes
+    pub size: u64,
+    pub sha256: String,
+    #[serde(default)]
+    pub license: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CatalogIndex {
+    models: Vec<CatalogEntry>,
+}
+
+/// An index entry and its state on this machine.
+#[derive(Debug, Clone, Serialize)]
+pub struct CatalogModel {
+    #[serde(flatten)]
+    pub entry: CatalogEntry,
+    pub installed: bool,
+    /// Bytes of an interrupted download the next attempt resumes from
+    pub downloaded: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelCatalog {
+    pub source: Option<String>,
+    pub models: Vec<CatalogModel>,
+}
+
+impl CatalogEntry {
+    // The index comes from the network: its filenames must stay inside the
+    // model folder and its hashes must be usable.
+    fn validate(&self) -> AppResult<()> {
+        let reject = |reason: String| {
+            Err(AppError::DownloadError(format!(
+                "Catalog entry '{}': {}",
+                self.name, reason
+            )))
+        };
+        let mut components = Path::new(&self.filename).components();
+        let plain =
+            matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+        if !plain || !self.filename.ends_with(".onnx") || self.filename.starts_with('.') {
+            return reject(format!("invalid filename {:?}", self.filename));
+        }
+        if self.sha256.len() != 64 || !self.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return reject(format!("invalid SHA-256 {:?}", self.sha256));
+        }
+        Ok(())
+    }
+
+    /// Interrupted downloads are kept under the expected hash, so a changed
+    /// index entry never resumes someone else's bytes.
+    pub fn partial_path(&self, partial_dir: &Path) -> PathBuf {
+        partial_dir.join(format!(
+            "{}-{}.partial",
+            &self.sha256[..16].to_lowercase(),
+            self.filename
+        ))
+    }
+}
+
+/// Reads the index at `source` and checks every entry.
+pub fn fetch_index(source: &str) -> AppResult<Vec<CatalogEntry>> {
+    let mut content = String::new();
+    open(source, 0)?
+        .reader
+        .take(MAX_INDEX_BYTES)
+        .read_to_string(&mut content)
+        .map_err(|e| AppError::DownloadError(format!("{}: {}", source, e)))?;
+    let index: CatalogIndex = serde_json::from_str(&content)
+        .map_err(|e| AppError::DownloadError(format!("Invalid catalog {}: {}", source, e)))?;
+
+    index
+        .models
+        .into_iter()
+        .map(|mut entry| {
+            entry.validate()?;
+            entry.url = resolve(source, &entry.url);
+            Ok(entry)
+        })
+        .collect()
+}
+
+/// `url` relative to the index at `index`, unless it is absolute already.
+pub fn resolve(index: &str, url: &str) -> String {
+    if url.contains("://") || Path::new(url).is_absolute() {
+        return url.to_string();
+    }
+    if index.contains("://") {
+        return match index.rfind('/') {
+            Some(slash) => format!("{}{}", &index[..=slash], url),
+            None => url.to_string(),
+        };
+    }
+    Path::new(index)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(url)
+        .to_string_lossy()
+        .to_string()
+}
+
+struct Stream {
+    reader: Box<dyn Read + Send>,
+    // False when the source ignored the offset and starts from byte 0
+    resumed: bool,
+}
+
+/// Longest wait for the next bytes of a download before giving up on the transfer.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Caps every socket wait of ureq's default connector chain at `IDLE_TIMEOUT`.
+// ureq's own body timeout covers the whole transfer, which would cut off large
+// models on slow links; this one only fires when no bytes arrive at all.
+#[derive(Debug)]
+struct IdleTimeoutConnector;
+
+#[derive(Debug)]
+struct IdleTimeoutTransport(Box<dyn Transport>);
+
+impl Connector<Box<dyn Transport>> for IdleTimeoutConnector {
+    type Out = IdleTimeoutTransport;
+
+    fn connect(
+        &self,
+        _details: &ConnectionDetails,
+        chained: Option<Box<dyn Transport>>,
+    ) -> Result<Option<Self::Out>, ureq::Error> {
+        Ok(chained.map(IdleTimeoutTransport))
+    }
+}
+
+impl Transport for IdleTimeoutTransport {
+    fn buffers(&mut self) -> &mut dyn Buffers {
+        self.0.buffers()
+    }
+
+    fn transmit_output(&mut self, amount: usize, timeout: NextTimeout) -> Result<(), ureq::Error> {
+        self.0.transmit_output(amount, timeout)
+    }
+
+    fn await_input(&mut self, timeout: NextTimeout) -> Result<bool, ureq::Error> {
+        let idle = IDLE_TIMEOUT.into();
+        let timeout = if timeout.after > idle {
+            NextTimeout {
+                after: idle,
+                reason: Timeout::RecvBody,
+            }
+        } else {
+            timeout
+        };
+        self.0.await_input(timeout)
+    }
+
+    fn is_open(&mut self) -> bool {
+        self.0.is_open()
+    }
+
+    fn is_tls(&self) -> bool {
+        self.0.is_tls()
+    }
+}
+
+// A read that failed because the transfer stalled (see `IdleTimeoutConnector`)
+fn is_stalled(error: &std::io::Error) -> bool {
+    error.kind() == std::io::ErrorKind::TimedOut
+        || matches!(
+            error
+                .get_ref()
+                .and_then(|e| e.downcast_ref::<ureq::Error>()),
+            Some(ureq::Error::Timeout(_))
+        )
+}
+
+fn open(url: &str, offset: u64) -> AppResult<Stream> {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        // No body timeout: large models on slow links are fine as long as bytes arrive
+        let config = ureq::Agent::config_builder()
+            .timeout_connect(Some(Duration::from_secs(15)))
+            .timeout_recv_response(Some(Duration::from_secs(30)))
+            .build();
+        let agent = ureq::Agent::with_parts(
+            config,
+            DefaultConnector::new().chain(IdleTimeoutConnector),
+            DefaultResolver::default(),
+        );
+        let mut request = agent.get(url);
+        if offset > 0 {
+            request = request.header("Range", format!("bytes={}-", offset));
+        }
+        let response = request
+            .call()
+            .map_err(|e| AppError::DownloadError(format!("{}: {}", url, e)))?;
+        let resumed = offset == 0 || response.status().as_u16() == 206;
+        return Ok(Stream {
+            reader: Box::new(response.into_body().into_reader()),
+            resumed,
+        });
+    }
+
+    let path = local_path(url);
+    let mut file =
+        File::open(&path).map_err(|e| AppError::DownloadError(format!("{:?}: {}", path, e)))?;
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(Stream {
+        reader: Box::new(file),
+        resumed: true,
+    })
+}
+
+/// "file:///C:/Models/x%20y.onnx" -> "C:/Models/x y.onnx"; plain paths pass through.
+fn local_path(url: &str) -> PathBuf {
+    let Some(rest) = url.strip_prefix("file://") else {
+        return PathBuf::from(url);
+    };
+    let path = percent_decode(rest.strip_prefix("localhost").unwrap_or(rest));
+    let bytes = path.as_bytes();
+    // Drive letters come after the leading slash of the URL path
+    if bytes.len() > 2 && bytes[0] == b'/' && bytes[1].is_ascii_alphabetic() && bytes[2] == b':' {
+        return PathBuf::from(&path[1..]);
+    }
+    PathBuf::from(path)
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Bytes already downloaded for `entry`.
+pub fn partial_len(entry: &CatalogEntry, partial_dir: &Path) -> u64 {
+    std::fs::metadata(entry.partial_path(partial_dir)).map_or(0, |m| m.len())
+}
+
+/// Downloads `entry` into `target_dir`, resuming a partial file kept in
+/// `partial_dir`. The model is moved into place only once its size and SHA-256
+/// match the index, and its graph does not contradict the indexed scale
+/// (`user_format` is the model's tensor format override, if any). A cancelled
+/// or interrupted download keeps its partial file.
+pub fn download(
+    entry: &CatalogEntry,
+    partial_dir: &Path,
+    target_dir: &Path,
+    user_format: Option<&TensorFormatOverride>,
+    cancel: &AtomicBool,
+    mut on_progress: impl FnMut(u64, u64),
+) -> AppResult<PathBuf> {
+    entry.validate()?;
+    let target = target_dir.join(&entry.filename);
+    if target.exists() {
+        if hash_model(&target)?.eq_ignore_ascii_case(&entry.sha256) {
+            return Ok(target);
+        }
+        return Err(AppError::DownloadError(format!(
+            "A different model named {} is already installed",
+            entry.filename
+        )));
+    }
+
+    std::fs::create_dir_all(partial_dir)?;
+    let partial = entry.partial_path(partial_dir);
+    let mut offset = partial_len(entry, partial_dir);
+    if offset > entry.size {
+        offset = 0;
+    }
+
+    if offset < entry.size {
+        let stream = open(&entry.url, offset)?;
+        if !stream.resumed {
+            tracing::info!("{} does not support resuming, restarting", entry.url);
+            offset = 0;
+        }
+        tracing::info!(
+            "Downloading {} from {} (from byte {})",
+            entry.filename,
+            entry.url,
+            offset
+        );
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&partial)?;
+        file.set_len(offset)?;
+        file.seek(SeekFrom::Start(offset))?;
+
+        // Never write past the declared size
+        let mut reader = stream.reader.take(entry.size - offset);
+        let mut buffer = vec![0u8; 256 * 1024];
+        let step = (entry.size / 200).max(1);
+        let mut reported = offset;
+        on_progress(offset, entry.size);
+        loop {
+            if cancel.load(Ordering::Relaxed) {
+                file.flush()?;
+                return Err(AppError::Cancelled);
+            }
+            let read = reader.read(&mut buffer).map_err(|e| {
+                if is_stalled(&e) {
+                    AppError::DownloadError(format!(
+                        "{} stalled after {} of {} bytes ({} s without data); retry to resume",
+                        entry.url,
+                        offset,
+                        entry.size,
+                        IDLE_TIMEOUT.as_secs()
+                    ))
+                } else {
+                    AppError::DownloadError(format!("{}: {}", entry.url, e))
+                }
+            })?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])?;
+            offset += read as u64;
+            if offset - reported >= step {
+                on_progress(offset, entry.size);
+                reported = offset;
+            }
+        }
+        file.sync_all()?;
+        on_progress(offset, entry.size);
+    }
+
+    if offset != entry.size {
+        return Err(AppError::DownloadError(format!(
+            "{} ended after {} of {} bytes; retry to resume",
+            entry.url, offset, entry.size
+        )));
+    }
+
+    let hash = hash_model(&partial)?;
+    if !hash.eq_ignore_ascii_case(&entry.sha256) {
+        let _ = std::fs::remove_file(&partial);
+        return Err(AppError::DownloadError(format!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            entry.filename, entry.sha256, hash
+        )));
+    }
+
+    // The bytes are the indexed ones, so a wrong scale is the index's mistake:
+    // the partial file stays for when the entry is fixed
+    if let Ok(info) = OnnxInfo::read(&partial) {
+        let graph_scale = info
+            .image_format(user_format)
+            .ok()
+            .and_then(|format| info.static_scale(&format));
+        if let Some(graph_scale) = graph_scale.filter(|&s| s != entry.scale) {
+            return Err(AppError::DownloadError(format!(
+                "{} is listed as {}x but its graph is {}x",
+                entry.filename, entry.scale, graph_scale
+            )));
+        }
+    }
+
+    std::fs::create_dir_all(target_dir)?;
+    if std::fs::rename(&partial, &target).is_err() {
+        // Different volume
+        std::fs::copy(&partial, &target)?;
+        let _ = std::fs::remove_file(&partial);
+    }
+    tracing::info!("Installed {} from the catalog", entry.filename);
+    Ok(target)
+}
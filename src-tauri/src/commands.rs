@@ -2,16 +2,21 @@ use crate::error::AppError;
 use crate::image_processing;
 use crate::state::AppState;
 use image::GenericImageView;
+use std::collections::HashMap;
 use std::fs::File;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tauri::{Emitter, Manager, State};
 use uuid::Uuid;
 
+use crate::catalog::{self, CatalogModel, CatalogSource, ModelCatalog};
 use crate::engine::{EngineCallbacks, UpscaleConfig, UpscaleEngine};
+use crate::inference::{CancelToken, CpuPool};
+use crate::model_cache::{CacheBudget, ModelCacheReport};
 
-use crate::models::{ModelManifest, ModelScanner, ModelUserConfig};
+use crate::model_roots::{ModelRoot, CONFIG_FILE};
+use crate::models::{CatalogOrigin, ModelManifest, ModelUserConfig};
+use crate::tuning::{BenchmarkReport, BenchmarkSample};
 
 #[derive(serde::Serialize, Clone)]
 struct ProgressPayload {
@@ -22,6 +27,29 @@ struct ProgressPayload {
     current_file: Option<String>,
 }
 
+#[derive(serde::Serialize, Clone)]
+struct StagePayload {
+    job_id: String,
+    stage: String,
+    current_file: Option<String>,
+}
+
+#[derive(serde::Serialize, Clone)]
+struct BenchmarkProgressPayload {
+    job_id: String,
+    progress: f32,
+    sample: BenchmarkSample,
+}
+
+#[derive(serde::Serialize, Clone)]
+struct DownloadProgressPayload {
+    job_id: String,
+    filename: String,
+    downloaded: u64,
+    total: u64,
+    progress: f32,
+}
+
 #[derive(serde::Serialize)]
 pub struct PreloadResponse {
     pub scale: u32,
@@ -38,51 +66,45 @@ pub struct BatchReport {
 pub async fn preload_model(
     app_handle: tauri::AppHandle,
     _state: State<'_, AppState>,
-    model_filename: String,
+    model_id: String,
     prefer_npu: bool,
     execution_provider: Option<String>,
+    cpu_pool: Option<CpuPool>,
 ) -> Result<PreloadResponse, AppError> {
     let app_handle_clone = app_handle.clone();
 
     // Spawn blocking to avoid freezing UI during heavy model load
     tauri::async_runtime::spawn_blocking(move || {
         let state = app_handle_clone.state::<AppState>();
-        let models_dir = std::env::current_dir()
-            .unwrap_or_else(|_| PathBuf::from("."))
-            .join("models");
-        let model_path = models_dir.join(&model_filename);
-
-        if !model_path.exists() {
-            return Err(AppError::Unknown("Model file not found".to_string()));
+        let model_path = state.resolve_model(&model_id)?.path;
+
+        // Load or Get from Cache; scale detection falls back to the filename.
+        // The CPU pool must be the one jobs will ask for, or they miss the cache.
+        let config = UpscaleConfig {
+            model: model_id.clone(),
+            prefer_npu: Some(prefer_npu),
+            execution_provider,
+            cpu_sessions: cpu_pool.and_then(|pool| pool.sessions),
+            cpu_threads: cpu_pool.and_then(|pool| pool.threads),
+            ..Default::default()
+        };
+        let model = UpscaleEngine::load_model(&config, Arc::new(state.inner().clone()))?;
+        let (scale, session) = (model.scale, model.session.clone());
+
+        // A slow first tile would otherwise land on the user's first job. A
+        // session taken from the cache has run before and is warm already.
+        if session.claim_warm_up() {
+            if let Err(e) = UpscaleEngine::warm_up(&model) {
+                tracing::warn!("Warm-up failed for {}: {}", model_id, e);
+            }
         }
 
-        // Load or Get from Cache
-        let session = state.get_or_load_model(&model_path, prefer_npu, execution_provider)?;
-
-        // Detect Scale (Cached)
-        let scale = match state.get_model_scale(&model_path, &session) {
-            Ok(s) => s,
-            Err(e) => {
-                tracing::warn!(
-                    "Failed to detect scale for {}: {}. Fallback to filename/manifest.",
-                    model_filename,
-                    e
-                );
-                // Fallback: Try to guess from filename
-                if model_filename.contains("x2") {
-                    2
-                } else if model_filename.contains("x3") {
-                    3
-                } else {
-                    4
-                }
-            }
-        };
+        // Benchmarked batch size, used by the frontend when the manifest has no override
+        let batch_size = state
+            .tuned_settings(&model_path, &session.execution_provider)
+            .map(|t| t.batch_size as u32);
 
-        Ok(PreloadResponse {
-            scale,
-            batch_size: None, // Frontend has manifest for this
-        })
+        Ok(PreloadResponse { scale, batch_size })
     })
     .await
     .map_err(|e| AppError::Unknown(e.to_string()))?
@@ -97,18 +119,20 @@ pub async fn upscale_image(
     id: Option<String>,
 ) -> Result<String, AppError> {
     let job_id = id.unwrap_or_else(|| Uuid::new_v4().to_string());
-    let cancel_flag = Arc::new(AtomicBool::new(false));
+    let cancel = Arc::new(CancelToken::new()?);
 
     tracing::info!("Starting upscale job {}: {}", job_id, path);
 
     // Register job for cancellation
     {
         let mut running_jobs = state.running_jobs.lock().unwrap();
-        running_jobs.insert(job_id.clone(), cancel_flag.clone());
+        running_jobs.insert(job_id.clone(), cancel.clone());
     }
 
     let app_handle_clone = app_handle.clone();
+    let app_handle_stage = app_handle.clone();
     let job_id_clone = job_id.clone();
+    let job_id_stage = job_id.clone();
 
     // Define Callbacks
     let callbacks = EngineCallbacks {
@@ -127,6 +151,16 @@ pub async fn upscale_image(
         on_warning: move |msg| {
             let _ = app_handle.emit("batch-size-warning", msg);
         },
+        on_stage: move |stage| {
+            let _ = app_handle_stage.emit(
+                "upscale-stage",
+                StagePayload {
+                    job_id: job_id_stage.clone(),
+                    stage,
+                    current_file: None,
+                },
+            );
+        },
     };
 
     let path_buf = PathBuf::from(path);
@@ -136,18 +170,18 @@ pub async fn upscale_image(
     // Spawn blocking task via the Engine
     // We use spawn_blocking here to keep the command async but run heavy work on thread pool
     let result = tauri::async_runtime::spawn_blocking(move || {
-        UpscaleEngine::run(config, path_buf, app_state, callbacks, cancel_flag)
+        UpscaleEngine::run(config, path_buf, app_state, callbacks, cancel)
     })
     .await
-    .map_err(|e| AppError::Unknown(e.to_string()))??;
+    .map_err(|e| AppError::Unknown(e.to_string()));
 
-    // Cleanup
+    // Cleanup, also for failed and cancelled jobs
     {
         let mut running_jobs = state.running_jobs.lock().unwrap();
         running_jobs.remove(&job_id);
     }
 
-    Ok(result)
+    result?
 }
 
 #[tauri::command]
@@ -193,7 +227,10 @@ fn is_supported_image(path: &PathBuf) -> bool {
     if let Some(ext) = path.extension() {
         if let Some(ext_str) = ext.to_str() {
             let ext_lower = ext_str.to_lowercase();
-            return matches!(ext_lower.as_str(), "png" | "jpg" | "jpeg" | "webp");
+            return matches!(
+                ext_lower.as_str(),
+                "png" | "jpg" | "jpeg" | "webp" | "tif" | "tiff" | "exr"
+            );
         }
     }
     false
@@ -208,14 +245,14 @@ pub async fn upscale_multiple(
     id: Option<String>,
 ) -> Result<BatchReport, AppError> {
     let job_id = id.unwrap_or_else(|| Uuid::new_v4().to_string());
-    let cancel_flag = Arc::new(AtomicBool::new(false));
+    let cancel = Arc::new(CancelToken::new()?);
 
     tracing::info!("Starting batch job {}: {} files", job_id, paths.len());
 
     // Register job for cancellation
     {
         let mut running_jobs = state.running_jobs.lock().unwrap();
-        running_jobs.insert(job_id.clone(), cancel_flag.clone());
+        running_jobs.insert(job_id.clone(), cancel.clone());
     }
 
     let mut save_handles = Vec::new();
@@ -224,14 +261,20 @@ pub async fn upscale_multiple(
     let total = paths.len();
 
     // 1. Load Model ONCE (Optimization)
-    let loaded_model = UpscaleEngine::load_model(&config, Arc::new(state.inner().clone()))
-        .map_err(|e| AppError::Unknown(e.to_string()))?;
+    let loaded_model = match UpscaleEngine::load_model(&config, Arc::new(state.inner().clone())) {
+        Ok(model) => model,
+        Err(e) => {
+            let mut running_jobs = state.running_jobs.lock().unwrap();
+            running_jobs.remove(&job_id);
+            return Err(AppError::Unknown(e.to_string()));
+        }
+    };
 
     tracing::info!("Batch Model Loaded: {}", loaded_model.filename);
 
     for (index, path_str) in paths.into_iter().enumerate() {
         // Check cancellation
-        if cancel_flag.load(Ordering::Relaxed) {
+        if cancel.is_cancelled() {
             tracing::info!("Batch job {} cancelled at index {}", job_id, index);
             break;
         }
@@ -262,12 +305,15 @@ pub async fn upscale_multiple(
         let config_for_save = file_config; // Move for save
         let path_clone = path_buf.clone();
         let path_for_save = path_buf;
-        let cancel_flag_clone = cancel_flag.clone();
+        let cancel_clone = cancel.clone();
         let job_id_clone = job_id.clone();
         let app_handle_clone_progress = app_handle.clone();
         let app_handle_clone_warning = app_handle.clone();
+        let app_handle_clone_stage = app_handle.clone();
+        let job_id_stage = job_id.clone();
 
         let current_file_path = path_str.clone();
+        let stage_file_path = path_str.clone();
         let callbacks = EngineCallbacks {
             on_progress: move |progress, provider| {
                 let _ = app_handle_clone_progress.emit(
@@ -284,6 +330,16 @@ pub async fn upscale_multiple(
             on_warning: move |msg| {
                 let _ = app_handle_clone_warning.emit("batch-size-warning", msg);
             },
+            on_stage: move |stage| {
+                let _ = app_handle_clone_stage.emit(
+                    "upscale-stage",
+                    StagePayload {
+                        job_id: job_id_stage.clone(),
+                        stage,
+                        current_file: Some(stage_file_path.clone()),
+                    },
+                );
+            },
         };
 
         // STEP 1: INFERENCE (Blocking/Awaited)
@@ -294,7 +350,7 @@ pub async fn upscale_multiple(
                 config_clone,
                 path_clone,
                 callbacks,
-                cancel_flag_clone,
+                cancel_clone,
             )
         })
         .await
@@ -338,6 +394,11 @@ pub async fn upscale_multiple(
                 });
                 save_handles.push((path_str.clone(), save_handle));
             }
+            Ok(Err(AppError::Cancelled)) => {
+                // Stopped mid-file: not a failure, and nothing left to run
+                tracing::info!("Batch job {} cancelled at index {}", job_id, index);
+                break;
+            }
             Ok(Err(e)) => {
                 // Inference failed
                 tracing::error!("Failed to process {}: {}", path_str, e);
@@ -384,11 +445,162 @@ pub async fn upscale_multiple(
     Ok(BatchReport { successful, failed })
 }
 
+/// Sweeps tile and batch sizes for a model on the chosen provider and stores
+/// the fastest setting for this machine. `load_model` picks it up from then on.
+#[tauri::command]
+pub async fn benchmark_model(
+    app_handle: tauri::AppHandle,
+    state: State<'_, AppState>,
+    model_id: String,
+    prefer_npu: bool,
+    execution_provider: Option<String>,
+    cpu_pool: Option<CpuPool>,
+    id: Option<String>,
+) -> Result<BenchmarkReport, AppError> {
+    let job_id = id.unwrap_or_else(|| Uuid::new_v4().to_string());
+    let cancel = Arc::new(CancelToken::new()?);
+    {
+        let mut running_jobs = state.running_jobs.lock().unwrap();
+        running_jobs.insert(job_id.clone(), cancel.clone());
+    }
+
+    let app_handle_clone = app_handle.clone();
+    let job_id_clone = job_id.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let state = app_handle_clone.state::<AppState>();
+        let location = state.resolve_model(&model_id)?;
+        let (models_dir, model_key) = (location.config_dir(), location.key.as_str());
+        let model_path = location.path.clone();
+
+        // Measured on the pool jobs use, and cached for them
+        let session = state.get_or_load_model(
+            &model_path,
+            prefer_npu,
+            execution_provider,
+            cpu_pool.unwrap_or_default(),
+            crate::models::user_session_options(models_dir, model_key),
+        )?;
+        tracing::info!(
+            "Benchmarking {} on {}",
+            model_id,
+            session.execution_provider
+        );
+
+        // Same padding and tensor conventions the engine uses
+        let padding = 32.max(crate::engine::DEFAULT_TILE_OVERLAP);
+        let format = match crate::models::user_tensor_format(models_dir, model_key) {
+            Some(declared) => declared.apply(session.format),
+            None => session.format,
+        };
+        format.validate()?;
+        // Extra model inputs are measured at their defaults
+        let params = session.bind_params(
+            &crate::models::user_params(models_dir, model_key),
+            &HashMap::new(),
+        )?;
+        let (best, samples) = crate::tuning::benchmark(
+            &session,
+            &format,
+            &params,
+            padding,
+            &cancel,
+            |progress, sample| {
+                let _ = app_handle_clone.emit(
+                    "benchmark-progress",
+                    BenchmarkProgressPayload {
+                        job_id: job_id_clone.clone(),
+                        progress,
+                        sample: sample.clone(),
+                    },
+                );
+            },
+        )?;
+
+        tracing::info!(
+            "Benchmark done: tile {}, batch {} ({:.2} MP/s)",
+            best.tile_size,
+            best.batch_size,
+            best.megapixels_per_second
+        );
+        state.save_tuned_settings(&model_path, best.clone())?;
+
+        let gpu = state.gpu_info.lock().unwrap().clone();
+        Ok(BenchmarkReport {
+            model_hash: state.model_hash(&model_path)?,
+            hardware: crate::tuning::hardware_fingerprint(
+                gpu.as_ref(),
+                &session.execution_provider,
+            ),
+            best,
+            samples,
+        })
+    })
+    .await
+    .map_err(|e| AppError::Unknown(e.to_string()));
+
+    {
+        let mut running_jobs = state.running_jobs.lock().unwrap();
+        running_jobs.remove(&job_id);
+    }
+
+    result?
+}
+
+/// Sessions held by the model cache, most recently used first, with the budget.
+#[tauri::command]
+pub async fn list_cached_models(state: State<'_, AppState>) -> Result<ModelCacheReport, AppError> {
+    let limits = state.cache_limits();
+    Ok(state.model_cache.lock().unwrap().report(limits))
+}
+
+/// Pinned sessions survive budget eviction until unpinned.
+#[tauri::command]
+pub async fn pin_cached_model(
+    state: State<'_, AppState>,
+    id: u64,
+    pinned: bool,
+) -> Result<(), AppError> {
+    if !state.model_cache.lock().unwrap().set_pinned(id, pinned) {
+        return Err(AppError::Unknown(format!("No cached session {}", id)));
+    }
+    if !pinned {
+        let limits = state.cache_limits();
+        state.model_cache.lock().unwrap().evict_to_fit(&limits, 0);
+    }
+    Ok(())
+}
+
+/// Evicts one session, or every unpinned one when `id` is None.
+/// Returns how many were evicted.
+#[tauri::command]
+pub async fn evict_cached_model(
+    state: State<'_, AppState>,
+    id: Option<u64>,
+) -> Result<usize, AppError> {
+    let mut cache = state.model_cache.lock().unwrap();
+    Ok(match id {
+        Some(id) => cache.evict(id) as usize,
+        None => cache.evict_unpinned(),
+    })
+}
+
+/// RAM/VRAM the model cache may use, in MB; None restores the automatic limit.
+#[tauri::command]
+pub async fn set_model_cache_budget(
+    state: State<'_, AppState>,
+    ram_mb: Option<u64>,
+    vram_mb: Option<u64>,
+) -> Result<ModelCacheReport, AppError> {
+    state.set_cache_budget(CacheBudget { ram_mb, vram_mb })?;
+    let limits = state.cache_limits();
+    Ok(state.model_cache.lock().unwrap().report(limits))
+}
+
 #[tauri::command]
 pub async fn cancel_job(state: State<'_, AppState>, job_id: String) -> Result<(), AppError> {
     let running_jobs = state.running_jobs.lock().unwrap();
-    if let Some(flag) = running_jobs.get(&job_id) {
-        flag.store(true, Ordering::Relaxed);
+    if let Some(cancel) = running_jobs.get(&job_id) {
+        cancel.cancel();
     }
     Ok(())
 }
@@ -409,6 +621,14 @@ pub async fn get_system_info() -> Result<serde_json::Value, AppError> {
     }))
 }
 
+/// ONNX Runtime build, usable providers, CPU/RAM and what each cached session runs on.
+#[tauri::command]
+pub async fn get_capabilities(
+    state: State<'_, AppState>,
+) -> Result<crate::capabilities::CapabilityReport, AppError> {
+    Ok(crate::capabilities::report(state.inner()))
+}
+
 #[tauri::command]
 pub async fn generate_preview(path: String) -> Result<String, AppError> {
     let img_path = PathBuf::from(&path);
@@ -431,18 +651,227 @@ pub async fn generate_preview(path: String) -> Result<String, AppError> {
 }
 
 #[tauri::command]
-pub async fn get_models() -> Result<Vec<crate::models::ModelManifest>, AppError> {
-    let models_dir = std::env::current_dir()
-        .unwrap_or_else(|_| PathBuf::from("."))
-        .join("models");
+pub async fn get_models(state: State<'_, AppState>) -> Result<crate::models::ModelScan, AppError> {
+    Ok(state.scan_models())
+}
+
+/// Validates a model and copies (or links) it into the user model folder.
+/// Rejections carry the reason, e.g. which check failed.
+#[tauri::command]
+pub async fn import_model(
+    app_handle: tauri::AppHandle,
+    path: String,
+    link: bool,
+) -> Result<ModelManifest, AppError> {
+    let app_handle_clone = app_handle.clone();
+    // Spawn blocking: validation loads the model and runs it once
+    let manifest = tauri::async_runtime::spawn_blocking(move || {
+        let state = app_handle_clone.state::<AppState>();
+        state.import_model(Path::new(&path), link)
+    })
+    .await
+    .map_err(|e| AppError::Unknown(e.to_string()))??;
+    let _ = app_handle.emit("models-changed", ());
+    Ok(manifest)
+}
+
+/// Deletes a model and its overrides, cached sessions and tuned settings.
+#[tauri::command]
+pub async fn remove_model(
+    app_handle: tauri::AppHandle,
+    state: State<'_, AppState>,
+    id: String,
+) -> Result<(), AppError> {
+    state.remove_model(&id)?;
+    let _ = app_handle.emit("models-changed", ());
+    Ok(())
+}
+
+/// The configured model index and the state of each entry on this machine.
+#[tauri::command]
+pub async fn get_catalog(app_handle: tauri::AppHandle) -> Result<ModelCatalog, AppError> {
+    // Spawn blocking: the index may be fetched over the network
+    tauri::async_runtime::spawn_blocking(move || {
+        let state = app_handle.state::<AppState>();
+        let source = CatalogSource::load(&state.app_data_dir).url;
+        let Some(url) = source.clone() else {
+            return Ok(ModelCatalog {
+                source,
+                models: Vec::new(),
+            });
+        };
+
+        let user_dir = state.model_roots.lock().unwrap().user_root().path.clone();
+        let partial_dir = state.app_data_dir.join("downloads");
+        let models = catalog::fetch_index(&url)?
+            .into_iter()
+            .map(|entry| {
+                let target = user_dir.join(&entry.filename);
+                let installed = target.exists()
+                    && state
+                        .model_hash(&target)
+                        .is_ok_and(|hash| hash.eq_ignore_ascii_case(&entry.sha256));
+                CatalogModel {
+                    installed,
+                    downloaded: catalog::partial_len(&entry, &partial_dir),
+                    entry,
+                }
+            })
+            .collect();
+        Ok(ModelCatalog { source, models })
+    })
+    .await
+    .map_err(|e| AppError::Unknown(e.to_string()))?
+}
+
+/// Sets the index location: an http(s) URL, a `file://` URL or a path. None clears it.
+#[tauri::command]
+pub async fn set_catalog_source(
+    state: State<'_, AppState>,
+    url: Option<String>,
+) -> Result<(), AppError> {
+    let url = url.map(|u| u.trim().to_string()).filter(|u| !u.is_empty());
+    CatalogSource { url }.save(&state.app_data_dir)
+}
+
+/// Downloads a catalog model into the user model folder, resuming an earlier
+/// attempt. Emits `model-download-progress`; cancel with `cancel_job`.
+#[tauri::command]
+pub async fn download_catalog_model(
+    app_handle: tauri::AppHandle,
+    state: State<'_, AppState>,
+    filename: String,
+    id: Option<String>,
+) -> Result<ModelManifest, AppError> {
+    let job_id = id.unwrap_or_else(|| Uuid::new_v4().to_string());
+    let cancel = Arc::new(CancelToken::new()?);
+    {
+        let mut running_jobs = state.running_jobs.lock().unwrap();
+        running_jobs.insert(job_id.clone(), cancel.clone());
+    }
+
+    let app_handle_clone = app_handle.clone();
+    let job_id_clone = job_id.clone();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let state = app_handle_clone.state::<AppState>();
+        let url = CatalogSource::load(&state.app_data_dir)
+            .url
+            .ok_or_else(|| AppError::DownloadError("No model catalog configured".to_string()))?;
+        let entry = catalog::fetch_index(&url)?
+            .into_iter()
+            .find(|e| e.filename == filename)
+            .ok_or_else(|| {
+                AppError::DownloadError(format!("{} is not in the catalog", filename))
+            })?;
+
+        let user_dir = state.model_roots.lock().unwrap().user_root().path.clone();
+        let location = state
+            .model_roots
+            .lock()
+            .unwrap()
+            .locate(&user_dir.join(&entry.filename))
+            .ok_or_else(|| {
+                AppError::Unknown(format!("{} is not in a model folder", entry.filename))
+            })?;
+        let config_path = location.config_dir().join(CONFIG_FILE);
+        // Read strictly up front: an unreadable config fails before the download
+        let user_format = ModelUserConfig::load_strict(&config_path)?
+            .overrides
+            .get(&location.key)
+            .and_then(|info| info.tensor_format);
+
+        catalog::download(
+            &entry,
+            &state.app_data_dir.join("downloads"),
+            &user_dir,
+            user_format.as_ref(),
+            cancel.flag(),
+            |downloaded, total| {
+                let _ = app_handle_clone.emit(
+                    "model-download-progress",
+                    DownloadProgressPayload {
+                        job_id: job_id_clone.clone(),
+                        filename: entry.filename.clone(),
+                        downloaded,
+                        total,
+                        progress: downloaded as f32 / total.max(1) as f32,
+                    },
+                );
+            },
+        )?;
+
+        // Provenance, not an override: the model's own metadata and the user's
+        // edits still win, and resetting the model's info keeps it
+        let mut user_config = ModelUserConfig::load_strict(&config_path)?;
+        user_config.catalog.insert(
+            location.key.clone(),
+            CatalogOrigin {
+                name: entry.name.clone(),
+                description: entry.description.clone(),
+                license: entry.license.clone(),
+                url: entry.url.clone(),
+                sha256: entry.sha256.clone(),
+            },
+        );
+        user_config.save(&config_path)?;
+
+        let manifest = state
+            .model_scanner
+            .lock()
+            .unwrap()
+            .scan_file(&location, &user_config);
+        manifest
+    })
+    .await
+    .map_err(|e| AppError::Unknown(e.to_string()));
+
+    {
+        let mut running_jobs = state.running_jobs.lock().unwrap();
+        running_jobs.remove(&job_id);
+    }
+
+    let manifest = result??;
+    let _ = app_handle.emit("models-changed", ());
+    Ok(manifest)
+}
+
+/// Model folders in lookup order.
+#[tauri::command]
+pub async fn get_model_roots(state: State<'_, AppState>) -> Result<Vec<ModelRoot>, AppError> {
+    Ok(state.model_roots.lock().unwrap().roots().to_vec())
+}
 
-    let manifests = ModelScanner::scan_directory(&models_dir)?;
-    Ok(manifests)
+/// Adds a folder to scan for models (or changes whether it is scanned recursively).
+#[tauri::command]
+pub async fn add_model_root(
+    app_handle: tauri::AppHandle,
+    state: State<'_, AppState>,
+    path: String,
+    recursive: bool,
+) -> Result<ModelRoot, AppError> {
+    let root = state
+        .model_roots
+        .lock()
+        .unwrap()
+        .add(Path::new(&path), recursive)?;
+    let _ = app_handle.emit("models-changed", ());
+    Ok(root)
+}
+
+#[tauri::command]
+pub async fn remove_model_root(
+    app_handle: tauri::AppHandle,
+    state: State<'_, AppState>,
+    id: String,
+) -> Result<(), AppError> {
+    state.model_roots.lock().unwrap().remove(&id)?;
+    let _ = app_handle.emit("models-changed", ());
+    Ok(())
 }
 
 #[tauri::command]
 pub async fn update_model_info(
-    _app: tauri::AppHandle, // AppHandle not needed for paths anymore
+    state: State<'_, AppState>,
     id: String,
     name: String,
     description: String,
@@ -451,26 +880,16 @@ pub async fn update_model_info(
     tracing::debug!("[Backend] update_model_info called for id: {}", id);
     tracing::debug!("[Backend] Received batch_size: {:?}", batch_size);
 
-    // FIX: Use the same directory as get_models (current_dir/models)
-    let models_dir = std::env::current_dir()
-        .unwrap_or_else(|_| PathBuf::from("."))
-        .join("models");
+    // Overrides live in the model_config.json of the model's own folder
+    let location = state.resolve_model(&id).map_err(|e| e.to_string())?;
+    let config_path = location.config_dir().join(CONFIG_FILE);
 
-    // Ensure directory exists
-    if !models_dir.exists() {
-        std::fs::create_dir_all(&models_dir).map_err(|e| e.to_string())?;
-    }
-
-    let config_path = models_dir.join("model_config.json");
+    let mut user_config = ModelUserConfig::load_strict(&config_path).map_err(|e| e.to_string())?;
 
-    let mut user_config = if config_path.exists() {
-        let file = File::open(&config_path).map_err(|e| e.to_string())?;
-        serde_json::from_reader(file).unwrap_or_default()
-    } else {
-        ModelUserConfig::default()
-    };
-
-    let info = user_config.overrides.entry(id.clone()).or_default();
+    let info = user_config
+        .overrides
+        .entry(location.key.clone())
+        .or_default();
     info.name = name;
     info.description = description;
     info.batch_size = batch_size;
@@ -486,13 +905,16 @@ pub async fn update_model_info(
 
     tracing::debug!("[Backend] Config saved. Rescanning model...");
 
-    let model_path = models_dir.join(&id);
-    if model_path.exists() {
+    if location.path.exists() {
         // CRITICAL FIX: Pass the in-memory 'config' we just updated!
         // This ensures the returned manifest reflects the changes immediately,
         // even if the file system hasn't fully synced or if 'load' would hit a race condition.
-        let manifest =
-            ModelScanner::scan_file(&model_path, &user_config).map_err(|e| e.to_string())?;
+        let manifest = state
+            .model_scanner
+            .lock()
+            .unwrap()
+            .scan_file(&location, &user_config)
+            .map_err(|e| e.to_string())?;
         tracing::debug!(
             "[Backend] Scan complete. Returning manifest with batch_size: {:?}",
             manifest.batch_size
@@ -505,22 +927,13 @@ pub async fn update_model_info(
 }
 
 #[tauri::command]
-pub async fn reset_model_info(_app: tauri::AppHandle, id: String) -> Result<(), String> {
-    // FIX: Use the same directory as get_models (current_dir/models)
-    let models_dir = std::env::current_dir()
-        .unwrap_or_else(|_| PathBuf::from("."))
-        .join("models");
-
-    let config_path = models_dir.join("model_config.json");
+pub async fn reset_model_info(state: State<'_, AppState>, id: String) -> Result<(), String> {
+    let location = state.resolve_model(&id).map_err(|e| e.to_string())?;
+    let config_path = location.config_dir().join(CONFIG_FILE);
 
-    let mut config = if config_path.exists() {
-        let file = File::open(&config_path).map_err(|e| e.to_string())?;
-        serde_json::from_reader(file).unwrap_or_default()
-    } else {
-        ModelUserConfig::default()
-    };
+    let mut config = ModelUserConfig::load_strict(&config_path).map_err(|e| e.to_string())?;
 
-    if config.overrides.remove(&id).is_some() {
+    if config.overrides.remove(&location.key).is_some() {
         let file = File::create(&config_path).map_err(|e| e.to_string())?;
         serde_json::to_writer_pretty(file, &config).map_err(|e| e.to_string())?;
     }
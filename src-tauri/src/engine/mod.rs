@@ -1,37 +1,87 @@
+pub mod plan;
+pub mod recovery;
+pub mod stream;
+pub mod tta;
+
 use crate::error::{AppError, AppResult};
-use crate::image_processing::{self, TilingConfig};
-use crate::inference::OrtSession;
+use crate::image_processing::{
+    self, AlphaMode, BlendMode, DiskImage, TileBatch, TileInput, TilingConfig,
+};
+use crate::inference::{BoundParam, CancelToken, CpuPool, OrtSession};
 use crate::metadata;
 use crate::state::AppState;
+use crate::tensor_format::TensorFormat;
 use image::GenericImageView;
+use plan::{FillMode, OutputSize, ScalePlan};
+use recovery::{Attempt, Limits};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use tta::Dihedral;
 use uuid::Uuid;
 
-#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Default)]
 pub struct UpscaleConfig {
     pub model: String,
-    pub scale: u32,
+    // Uniform scale factor, fractional allowed. Ignored when `output_size` is set.
+    pub scale: f32,
+    pub output_size: Option<OutputSize>,
+    // Shrink the input instead of the output when the model chain overshoots.
+    pub pre_downscale: Option<bool>,
     pub batch_size: Option<u32>,
     pub format: Option<String>,
     pub compression: Option<String>,
     pub prefer_npu: Option<bool>,
     pub output_dir: Option<String>,
     pub execution_provider: Option<String>,
+    // Tile overlap in source pixels (cross-faded). None = DEFAULT_TILE_OVERLAP.
+    pub tile_overlap: Option<u32>,
+    pub blend_mode: Option<BlendMode>,
+    // How transparent inputs get their alpha upscaled. None = AlphaMode::Resize.
+    pub alpha_mode: Option<AlphaMode>,
+    // Keep 16-bit/float sources at full depth through the pipeline. None = false.
+    pub high_precision: Option<bool>,
+    // Tone-map HDR (>1.0) inputs into 0..1 before inference and invert afterwards.
+    pub tone_map: Option<bool>,
+    // Write the output through a disk-backed canvas. None = only when it won't fit in RAM.
+    pub streaming: Option<bool>,
+    // Self-ensemble size: 1 (off), 2, 4 or 8 flipped/rotated runs per tile. None = 1.
+    pub tta: Option<u32>,
+    // CPU session pool: concurrent sessions and intra-op threads each. None = autodetect.
+    pub cpu_sessions: Option<usize>,
+    pub cpu_threads: Option<usize>,
+    // Values for the extra inputs of multi-input models, by input name.
+    // Unset ones use the default from the model's manifest entry.
+    pub params: Option<HashMap<String, f32>>,
 }
 
-pub struct EngineCallbacks<P, W>
+/// Default overlap band (source pixels) blended between neighbouring tiles.
+pub const DEFAULT_TILE_OVERLAP: u32 = 16;
+
+pub struct EngineCallbacks<P, W, S>
 where
     P: Fn(f32, String) + Send + Sync + 'static,
     W: Fn(String) + Send + Sync + 'static,
+    S: Fn(String) + Send + Sync + 'static,
 {
     pub on_progress: P,
     pub on_warning: W,
+    // Pipeline stage label at each pass boundary, e.g. "Pass 2/3"
+    pub on_stage: S,
 }
 
 pub struct UpscaleEngine;
 
+/// Result of `process_with_session`: an in-memory image, or for out-of-core jobs
+/// a disk-backed canvas that `save_result` encodes without loading it.
+pub enum UpscaleOutput {
+    Image(image::DynamicImage),
+    Disk(image_processing::DiskImage),
+}
+
 #[derive(Clone)]
 pub struct LoadedModel {
     pub session: Arc<OrtSession>,
@@ -39,168 +89,294 @@ pub struct LoadedModel {
     pub path: PathBuf,
     pub filename: String,
     pub recommended_tile_size: u32,
+    pub recommended_batch_size: usize,
+    // Dynamic tile inputs are padded to a multiple of this (window-attention models)
+    pub alignment: u32,
+    // Detected tensor conventions with the user's override applied
+    pub format: TensorFormat,
+    // Values for the model's extra inputs, bound on every run
+    pub params: Vec<BoundParam>,
+    // Settings after an OOM downshift, shared by every file of a batch job
+    pub recovery: Arc<std::sync::Mutex<Option<Attempt>>>,
+}
+
+// The alpha plane as a grey image for `AlphaMode::Model`, at the precision it has
+fn alpha_model_input(alpha: &image::DynamicImage) -> image::DynamicImage {
+    match alpha {
+        image::DynamicImage::ImageLuma16(_) => image::DynamicImage::ImageRgb32F(alpha.to_rgb32f()),
+        _ => image::DynamicImage::ImageRgb8(alpha.to_rgb8()),
+    }
 }
 
 impl UpscaleEngine {
     pub fn load_model(config: &UpscaleConfig, app_state: Arc<AppState>) -> AppResult<LoadedModel> {
-        let models_dir = std::env::current_dir()
-            .unwrap_or_else(|_| PathBuf::from("."))
-            .join("models");
-
-        // 1. Resolve Model Path (Fast)
-        let model_filename = if config.model.ends_with(".onnx") {
-            config.model.clone()
-        } else {
-            format!("{}.onnx", config.model)
-        };
-
-        let model_path = models_dir.join(&model_filename);
-        if !model_path.exists() {
-            return Err(AppError::Unknown(format!(
-                "Model file not found: {:?}",
-                model_path
-            )));
-        }
+        // 1. Resolve Model Path (Fast): any model folder, by stable id
+        let location = app_state.resolve_model(&config.model)?;
+        let (models_dir, model_key) = (location.config_dir(), location.key.as_str());
+        let model_filename = location.filename();
+        let model_path = location.path.clone();
 
         // 2. Load Session (Cached)
         tracing::info!("Loading model session: {}", model_filename);
         let prefer_npu = config.prefer_npu.unwrap_or(false);
+        let cpu_pool = CpuPool {
+            sessions: config.cpu_sessions,
+            threads: config.cpu_threads,
+        };
         let session = app_state.get_or_load_model(
             &model_path,
             prefer_npu,
             config.execution_provider.clone(),
+            cpu_pool,
+            crate::models::user_session_options(models_dir, model_key),
+        )?;
+
+        // Tensor conventions; unsupported channel counts fail here with a clear reason
+        let format = match crate::models::user_tensor_format(models_dir, model_key) {
+            Some(declared) => declared.apply(session.format),
+            None => session.format,
+        };
+        format.validate()?;
+        let params = session.bind_params(
+            &crate::models::user_params(models_dir, model_key),
+            &config.params.clone().unwrap_or_default(),
         )?;
 
+        // Author-declared scale, tile size, alignment and architecture (ONNX metadata_props)
+        let declared = crate::models::ModelMetadata::read(&model_path);
+
         // Detect Model Scale (Source of Truth - Cached)
         let scale = app_state
-            .get_model_scale(&model_path, &session)
-            .unwrap_or_else(|_| if model_filename.contains("x2") { 2 } else { 4 });
-
-        // Get Recommended Tile Size
-        let gpu_cache = app_state.gpu_info.lock().unwrap();
-        let recommended_tile_size = if let Some(info) = &*gpu_cache {
-            info.recommended_tile_size()
+            .get_model_scale(&model_path, &session, &format)
+            .unwrap_or_else(|_| {
+                declared.scale.unwrap_or(if model_filename.contains("x2") {
+                    2
+                } else if model_filename.contains("x3") {
+                    3
+                } else {
+                    4
+                })
+            });
+
+        // Recommended Tile/Batch Size: benchmarked values, then the model's own
+        // recommendation, then the VRAM table
+        let tuned = app_state.tuned_settings(&model_path, &session.execution_provider);
+        let (recommended_tile_size, recommended_batch_size) = if let Some(tuned) = tuned {
+            tracing::info!(
+                "Using tuned settings: tile {}, batch {} ({:.2} MP/s)",
+                tuned.tile_size,
+                tuned.batch_size,
+                tuned.megapixels_per_second
+            );
+            (tuned.tile_size, tuned.batch_size)
+        } else if let Some(tile_size) = declared.tile_size {
+            let batch_size = app_state
+                .gpu_info
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map_or(1, |info| info.recommended_batch_size());
+            (tile_size, batch_size)
+        } else if let Some(info) = &*app_state.gpu_info.lock().unwrap() {
+            (info.recommended_tile_size(), info.recommended_batch_size())
         } else {
-            256
+            (256, 1)
         };
 
+        let architecture = crate::models::model_architecture(models_dir, model_key, &declared);
+        let alignment = crate::models::model_alignment(
+            models_dir,
+            model_key,
+            &declared,
+            architecture.as_deref().unwrap_or(&model_filename),
+        );
+
         Ok(LoadedModel {
             session,
             scale,
             path: model_path,
             filename: model_filename,
             recommended_tile_size,
+            recommended_batch_size,
+            alignment,
+            format,
+            params,
+            recovery: Arc::new(std::sync::Mutex::new(None)),
         })
     }
 
-    pub fn process_with_session<P, W>(
+    /// Runs one batch at the tile shape jobs will use, so the first real tile
+    /// doesn't pay for arena growth and kernel selection.
+    pub fn warm_up(model: &LoadedModel) -> AppResult<()> {
+        let input_dims = model.session.static_input_dims(model.format.layout)?;
+        let padding = 32.max(DEFAULT_TILE_OVERLAP);
+        let side = |fixed: Option<u32>| {
+            fixed.unwrap_or_else(|| {
+                (model.recommended_tile_size + 2 * padding).next_multiple_of(model.alignment.max(1))
+            })
+        };
+        let size = (side(input_dims.width), side(input_dims.height));
+        let batch_size = input_dims
+            .batch
+            .unwrap_or(model.recommended_batch_size.clamp(1, 8));
+
+        let start = std::time::Instant::now();
+        crate::tuning::warm_up(
+            &model.session,
+            size,
+            batch_size,
+            &model.format,
+            &model.params,
+        )?;
+        tracing::info!(
+            "Warmed up {} at {}x{} x{} in {:.2}s",
+            model.filename,
+            size.0,
+            size.1,
+            batch_size,
+            start.elapsed().as_secs_f32()
+        );
+        Ok(())
+    }
+
+    pub fn process_with_session<P, W, S>(
         model: &LoadedModel,
         config: UpscaleConfig,
         path: PathBuf,
-        callbacks: EngineCallbacks<P, W>,
-        cancel_flag: Arc<AtomicBool>,
-    ) -> AppResult<image::DynamicImage>
+        callbacks: EngineCallbacks<P, W, S>,
+        cancel: Arc<CancelToken>,
+    ) -> AppResult<UpscaleOutput>
     where
         P: Fn(f32, String) + Send + Sync + 'static,
         W: Fn(String) + Send + Sync + 'static,
+        S: Fn(String) + Send + Sync + 'static,
     {
         let job_id = Uuid::new_v4().to_string();
         tracing::info!("Starting upscale job {}: {:?}", job_id, path);
+        // The job's first tiles warm the session; a later preload needn't
+        model.session.claim_warm_up();
 
         let img_path = path.clone();
         // Load image early to fail fast
-        let image = Arc::new(image_processing::load_image(&img_path)?);
+        let high_precision = config.high_precision.unwrap_or(false);
+        let image = Arc::new(image_processing::load_image_with_precision(
+            &img_path,
+            high_precision,
+        )?);
+        let source_color = image.color();
 
         let model_scale = model.scale;
-        let target_scale = config.scale;
+        let plan = ScalePlan::new(
+            image.dimensions(),
+            model_scale,
+            config.scale,
+            config.output_size,
+            config.pre_downscale.unwrap_or(false),
+        )?;
+        let (target_w, target_h) = plan.output_size(image.dimensions());
 
         tracing::info!(
-            "Model Scale: {}x | Target Scale: {}x",
+            "Model Scale: {}x | Target: {}x{} | Plan: {:?}",
             model_scale,
-            target_scale
+            target_w,
+            target_h,
+            plan
         );
 
         // 3. Just-In-Time Constraints Check
-        let (fixed_input_size, is_static_batch) = {
-            let s = model
-                .session
-                .session
-                .lock()
-                .map_err(|_| AppError::Unknown("Failed to lock session".to_string()))?;
-
-            // Check Input
-            let input = &s.inputs[0];
-            let (fixed_size, static_batch) =
-                if let ort::value::ValueType::Tensor { shape, .. } = &input.input_type {
-                    let f_size = if shape.len() >= 3 && shape[2] > 0 {
-                        Some(shape[2] as u32)
-                    } else {
-                        None
-                    };
-
-                    let s_batch = if !shape.is_empty() && shape[0] > 0 {
-                        Some(shape[0] as usize)
-                    } else {
-                        None
-                    };
-
-                    (f_size, s_batch)
-                } else {
-                    (None, None)
-                };
-
-            (fixed_size, static_batch)
-        };
+        let input_dims = model.session.static_input_dims(model.format.layout)?;
+        if input_dims.height.is_some() || input_dims.width.is_some() {
+            tracing::info!(
+                "Model Constraint: Fixed input {:?}x{:?} (width x height)",
+                input_dims.width,
+                input_dims.height
+            );
+        }
 
         // 4. Configuration
-        let mut batch_size = config.batch_size.unwrap_or(1).clamp(1, 8) as usize;
-
-        if let Some(static_b) = is_static_batch {
-            if static_b == 1 && batch_size > 1 {
+        let mut batch_size = config
+            .batch_size
+            .map(|b| b as usize)
+            .unwrap_or(model.recommended_batch_size)
+            .clamp(1, 8);
+
+        if let Some(static_b) = input_dims.batch {
+            if batch_size != static_b {
                 tracing::warn!(
-                    "Model has static batch size of 1. Overriding requested batch size {} to 1.",
+                    "Model has static batch size of {}. Overriding requested batch size {}.",
+                    static_b,
                     batch_size
                 );
-                // TRIGGER WARNING CALLBACK
-                (callbacks.on_warning)(format!(
-                    "Model requires Batch Size 1. Overriding your setting of {}.",
-                    batch_size
-                ));
-                batch_size = 1;
+                // TRIGGER WARNING CALLBACK (only for the user's own setting, not a recommendation)
+                if config.batch_size.is_some() {
+                    (callbacks.on_warning)(format!(
+                        "Model requires Batch Size {}. Overriding your setting of {}.",
+                        static_b, batch_size
+                    ));
+                }
+                batch_size = static_b;
             }
         }
 
-        let rec_tile = model.recommended_tile_size;
-
-        let padding = 32;
-        let tile_size = if let Some(fixed_size) = fixed_input_size {
-            tracing::info!("Model Constraint: Fixed input size {}", fixed_size);
-            if fixed_size <= padding * 2 {
-                return Err(AppError::Unknown(format!(
-                    "Model input size {} is too small for padding {}",
-                    fixed_size, padding
-                )));
-            }
-            fixed_size - (padding * 2)
+        // Overlap lives inside the padding, so bigger overlaps widen the padding
+        // (more context per tile, slightly slower) rather than shrinking content.
+        let blend_mode = config.blend_mode.unwrap_or_default();
+        let overlap = if blend_mode == BlendMode::None {
+            0
         } else {
-            rec_tile
+            config.tile_overlap.unwrap_or(DEFAULT_TILE_OVERLAP)
         };
+        let padding = 32.max(overlap);
+        let tile_size = model.recommended_tile_size;
 
+        // Fixed axes, static batch padding and alignment are applied by the tiler
         let tiling_config = TilingConfig {
             tile_size,
             padding,
             batch_size,
+            overlap,
+            blend_mode,
+            input_size: (input_dims.width, input_dims.height),
+            static_batch: input_dims.batch,
+            alignment: model.alignment,
+            workers: 1,
         };
 
+        // --- OOM RECOVERY ---
+        // Out-of-memory failures step down batch size, then tile size, then move to CPU.
+        // Batch jobs share `model.recovery`, so a downshift sticks for the remaining files.
+        let limits = Limits {
+            fixed_batch: input_dims.batch.is_some(),
+            fixed_tile: input_dims.height.is_some() && input_dims.width.is_some(),
+        };
+        let remembered = model.recovery.lock().ok().and_then(|r| r.clone());
+        let attempt = RefCell::new(match remembered {
+            Some(remembered) => {
+                tracing::info!(
+                    "Using downshifted settings from an earlier OOM: batch {}, tile {}, {}",
+                    remembered.batch_size,
+                    remembered.tile_size,
+                    remembered.session.execution_provider
+                );
+                remembered
+            }
+            None => Attempt {
+                batch_size,
+                tile_size,
+                session: model.session.clone(),
+            },
+        });
+
         // Progress Handler
         let mut last_update = std::time::Instant::now();
-        let provider_str = model.session.execution_provider.clone();
+        let attempt_ref = &attempt;
 
         // We wrap the generic callback to handle throttling
         let mut progress_callback = move |progress: f32| {
             let now = std::time::Instant::now();
             if progress >= 1.0 || now.duration_since(last_update).as_millis() >= 100 {
-                (callbacks.on_progress)(progress, provider_str.clone());
+                let provider = attempt_ref.borrow().session.execution_provider.clone();
+                (callbacks.on_progress)(progress, provider);
                 last_update = now;
             }
         };
@@ -211,15 +387,27 @@ impl UpscaleEngine {
         let batches_counter_clone = batches_counter.clone();
         let inference_start_time = std::time::Instant::now();
 
+        // Self-ensemble: every tile runs once per transform and the results are averaged
+        let tta_transforms = Dihedral::set(config.tta.unwrap_or(1))?;
+        if tta_transforms.len() > 1 {
+            tracing::info!(
+                "Self-ensemble enabled: {} transforms per tile",
+                tta_transforms.len()
+            );
+        }
+
         // Inference Callback Factory
         let create_inference_callback = |session: Arc<OrtSession>| {
             let buffer_pool = buffer_pool.clone();
+            let cancel = cancel.clone();
+            let format = model.format;
+            let params = model.params.clone();
             let batches_counter_clone = batches_counter_clone.clone();
             let inference_start_time = inference_start_time.clone();
 
-            move |tiles: Vec<image::DynamicImage>| -> AppResult<Vec<image::DynamicImage>> {
-                if tiles.is_empty() {
-                    return Ok(Vec::new());
+            move |batch: TileBatch| -> AppResult<TileBatch> {
+                if batch.len == 0 {
+                    return Ok(TileBatch::default());
                 }
 
                 let current_batch_idx = batches_counter_clone.fetch_add(1, Ordering::Relaxed) + 1;
@@ -235,148 +423,357 @@ impl UpscaleEngine {
                     );
                 }
 
-                let target_type = {
-                    let s = session
-                        .session
-                        .lock()
-                        .map_err(|_| AppError::Unknown("Lock fail".to_string()))?;
-                    match &s.inputs[0].input_type {
-                        ort::value::ValueType::Tensor { ty, .. } => ty.clone(),
-                        _ => ort::tensor::TensorElementType::Float32,
-                    }
-                };
-
-                // Buffer Management
-                let est_size = tiles[0].width() as usize * tiles[0].height() as usize * 3;
-                let mut input_buffer = match target_type {
-                    ort::tensor::TensorElementType::Float16 => {
-                        crate::inference::TensorData::Float16(buffer_pool.get_f16(est_size))
-                    }
-                    _ => crate::inference::TensorData::Float32(buffer_pool.get_f32(est_size)),
-                };
-                let mut output_buffer = match target_type {
-                    ort::tensor::TensorElementType::Float16 => {
-                        crate::inference::TensorData::Float16(buffer_pool.get_f16(est_size * 16))
-                    }
-                    _ => crate::inference::TensorData::Float32(buffer_pool.get_f32(est_size * 16)),
-                };
-
-                let shape =
-                    crate::inference::images_to_buffer(&tiles, target_type, &mut input_buffer)?;
-
-                // Standard Run (Safe - No I/O Binding)
-                let result = session.run_with_binding(shape, &input_buffer, &mut output_buffer);
-
-                let (out_shape, valid_len) = match result {
-                    Ok(res) => res,
-                    Err(e) => {
-                        // Check for OOM
-                        let err_str = e.to_string().to_lowercase();
-                        if err_str.contains("memory")
-                            || err_str.contains("allocate")
-                            || err_str.contains("vram")
-                        {
-                            return Err(AppError::Unknown(format!(
-                                "Out of Memory. Try closing other apps. (Technical: {})",
+                // One model run over a batch of tiles. The input goes to ORT as a
+                // borrowed view; its Vec is pooled afterwards for the next output.
+                let run_batch = |batch: TileBatch| -> AppResult<TileBatch> {
+                    let mut output = buffer_pool.get_f32(batch.data.len());
+                    let result = session.run_planar(
+                        &batch,
+                        &mut output,
+                        &buffer_pool,
+                        &format,
+                        &params,
+                        &cancel,
+                    );
+                    buffer_pool.return_f32(batch.data);
+
+                    result.map_err(|e| {
+                        // OOM and a lost device get their own variants so the
+                        // recovery ladder can retry
+                        let message = e.to_string();
+                        if crate::inference::is_device_lost(&message) {
+                            AppError::DeviceLost(message)
+                        } else if crate::inference::is_out_of_memory(&message) {
+                            AppError::OutOfMemory(format!(
+                                "Try closing other apps. (Technical: {})",
                                 e
-                            )));
+                            ))
                         } else {
-                            return Err(e);
+                            e
                         }
+                    })
+                };
+
+                if tta_transforms.len() > 1 {
+                    tta::self_ensemble(batch, tta_transforms, run_batch)
+                } else {
+                    run_batch(batch)
+                }
+            }
+        };
+
+        // --- SMART SCALING LOGIC ---
+        // Follows the plan: pre-resize, N model passes, resample to the exact size.
+        // Progress is weighted by the pixels each pass produces, so a 2x -> 8x chain
+        // spends ~94% of the bar on its last pass.
+        // An alpha plane (4-channel models) is tiled along and returned upscaled.
+        let pass_weights = plan.pass_weights(image.dimensions());
+        let run_scaling =
+            |input: &image::DynamicImage,
+             alpha: Option<&image::DynamicImage>,
+             stage_prefix: &str,
+             progress: &mut dyn FnMut(f32)|
+             -> AppResult<(image::DynamicImage, Option<image::DynamicImage>)> {
+                let (mut current, mut alpha) = match plan.pre_resize {
+                    Some((w, h)) => {
+                        tracing::info!("Pre-downscaling input to {}x{}", w, h);
+                        (
+                            Cow::Owned(image_processing::resize_image(input, w, h)?),
+                            alpha
+                                .map(|alpha| image_processing::resize_image(alpha, w, h))
+                                .transpose()?
+                                .map(Cow::Owned),
+                        )
                     }
+                    None => (Cow::Borrowed(input), alpha.map(Cow::Borrowed)),
                 };
 
-                let result_images = crate::inference::batch_buffer_to_images(
-                    &out_shape,
-                    &output_buffer,
-                    valid_len,
-                )?;
+                let mut base = 0.0;
+                for (pass, &weight) in pass_weights.iter().enumerate() {
+                    tracing::info!(
+                        "{}Pass {}/{}: Upscaling {}x...",
+                        stage_prefix,
+                        pass + 1,
+                        plan.passes,
+                        model_scale
+                    );
+                    if plan.passes > 1 || !stage_prefix.is_empty() {
+                        (callbacks.on_stage)(format!(
+                            "{}Pass {}/{}",
+                            stage_prefix,
+                            pass + 1,
+                            plan.passes
+                        ));
+                    }
+                    let (upscaled, upscaled_alpha) = recovery::run_with_recovery(
+                        &attempt,
+                        &model.recovery,
+                        &model.path,
+                        limits,
+                        &callbacks.on_warning,
+                        |settings| {
+                            let config = TilingConfig {
+                                tile_size: settings.tile_size,
+                                batch_size: settings.batch_size,
+                                workers: settings.session.pool_size(),
+                                ..tiling_config
+                            };
+                            let infer = create_inference_callback(settings.session.clone());
+                            let report = |p| progress(base + p * weight);
+                            match &alpha {
+                                Some(alpha) => image_processing::process_tiled_with_alpha(
+                                    &current,
+                                    alpha,
+                                    config,
+                                    model_scale,
+                                    cancel.flag(),
+                                    report,
+                                    infer,
+                                )
+                                .map(|(color, alpha)| (color, Some(alpha))),
+                                None => image_processing::process_tiled(
+                                    &current,
+                                    config,
+                                    model_scale,
+                                    cancel.flag(),
+                                    report,
+                                    infer,
+                                )
+                                .map(|color| (color, None)),
+                            }
+                        },
+                    )?;
+                    current = Cow::Owned(upscaled);
+                    alpha = upscaled_alpha.map(Cow::Owned);
+                    base += weight;
+                }
 
-                // Return buffers to pool
-                match input_buffer {
-                    crate::inference::TensorData::Float32(b) => buffer_pool.return_f32(b),
-                    crate::inference::TensorData::Float16(b) => buffer_pool.return_f16(b),
+                if let Some((w, h)) = plan.resample {
+                    let (from_w, from_h) = current.dimensions();
+                    tracing::info!("Resizing output from {}x{} to {}x{}", from_w, from_h, w, h);
+                    current = Cow::Owned(image_processing::resize_image(&current, w, h)?);
+                    if let Some(plane) = &alpha {
+                        alpha = Some(Cow::Owned(image_processing::resize_image(plane, w, h)?));
+                    }
                 }
-                match output_buffer {
-                    crate::inference::TensorData::Float32(b) => buffer_pool.return_f32(b),
-                    crate::inference::TensorData::Float16(b) => buffer_pool.return_f16(b),
+
+                Ok((current.into_owned(), alpha.map(Cow::into_owned)))
+            };
+
+        // --- ALPHA HANDLING ---
+        // Transparent inputs are split and the color is upscaled as usual. The alpha
+        // plane follows separately, or rides along as the fourth plane of the tiles
+        // for models that take and return RGBA.
+        let alpha_mode = config.alpha_mode.unwrap_or_default();
+        let tiled_alpha = model.format.carries_alpha();
+        let (color_input, alpha_plane) = match image_processing::split_alpha(&image) {
+            Some((rgb, alpha)) => {
+                if tiled_alpha {
+                    tracing::info!("Input has transparency. Tiling it through the RGBA model.");
+                } else {
+                    tracing::info!("Input has transparency. Alpha mode: {:?}", alpha_mode);
                 }
-                Ok(result_images)
+                (Cow::Owned(rgb), Some(alpha))
             }
+            // Opaque inputs are read in place, no copy of the decoded source
+            None => (Cow::Borrowed(image.as_ref()), None),
         };
 
-        // --- SMART SCALING LOGIC ---
-        let final_image = if model_scale == target_scale {
-            // CASE A: Match (e.g. 4x -> 4x)
-            tracing::info!("Scaling Match. Running single pass.");
-            image_processing::process_tiled(
-                &image,
-                tiling_config,
-                model_scale,
-                &cancel_flag,
-                &mut progress_callback,
-                create_inference_callback(model.session.clone()),
-            )?
-        } else if model_scale > target_scale {
-            // CASE B: Downscale (e.g. 4x -> 2x)
-            tracing::info!(
-                "Downscaling required (Model {}x -> Target {}x).",
-                model_scale,
-                target_scale
-            );
-            let upscaled = image_processing::process_tiled(
-                &image,
-                tiling_config,
-                model_scale,
-                &cancel_flag,
-                &mut progress_callback,
-                create_inference_callback(model.session.clone()),
-            )?;
+        // High-depth inputs run through the model as float
+        let mut color_input = if image_processing::is_high_depth(color_input.color())
+            && color_input.color() != image::ColorType::Rgb32F
+        {
+            Cow::Owned(image::DynamicImage::ImageRgb32F(color_input.to_rgb32f()))
+        } else {
+            color_input
+        };
 
-            let (w, h) = upscaled.dimensions();
-            let target_w = (w as f32 * (target_scale as f32 / model_scale as f32)) as u32;
-            let target_h = (h as f32 * (target_scale as f32 / model_scale as f32)) as u32;
+        let tone_mapped =
+            config.tone_map.unwrap_or(false) && image_processing::has_hdr_values(&color_input);
+        if tone_mapped {
+            tracing::info!("Input has HDR values. Tone-mapping before inference.");
+            image_processing::tone_map(color_input.to_mut());
+        }
+
+        // Share of the progress bar reserved for a second (alpha) model run
+        let alpha_share = if alpha_plane.is_some() && alpha_mode == AlphaMode::Model && !tiled_alpha
+        {
+            0.5
+        } else {
+            0.0
+        };
+        let tiled_alpha_plane = alpha_plane.as_ref().filter(|_| tiled_alpha);
+
+        // --- OUT-OF-CORE ---
+        // Outputs that would not fit in RAM stream through disk canvases instead:
+        // every model pass, the resample and crop/pad, and the alpha plane.
+        let (in_w, in_h) = color_input.dimensions();
+        let float_input = color_input.color() == image::ColorType::Rgb32F;
+        let (peak_w, peak_h) = plan.largest_intermediate((in_w, in_h));
+        let streaming = config.streaming.unwrap_or_else(|| {
+            !image_processing::fits_in_memory(peak_w, peak_h, float_input, alpha_plane.is_some())
+        });
+
+        let output = if streaming {
+            let out_path = Self::output_path(&config, &path)?;
+            let format =
+                image::ImageFormat::from_path(&out_path).unwrap_or(image::ImageFormat::Png);
+            // Kept out of the output folder, which may be synced or watched
+            let scratch_dir = std::env::temp_dir().join("rustscale_canvas");
+            std::fs::create_dir_all(&scratch_dir)?;
 
             tracing::info!(
-                "Resizing output from {}x{} to {}x{}",
-                w,
-                h,
+                "Streaming {}x{} output through disk canvases in {:?}",
                 target_w,
-                target_h
+                target_h,
+                scratch_dir
             );
-            // Log the color type of the image being resized
-            tracing::info!("Image Color Type before resize: {:?}", upscaled.color());
-            image_processing::resize_image(&upscaled, target_w, target_h)?
-        } else {
-            // CASE C: Double Upscale (e.g. 2x -> 4x)
-            tracing::info!(
-                "Double Upscale required (Model {}x -> Target {}x).",
-                model_scale,
-                target_scale
+            let color = image_processing::disk_color(
+                format,
+                float_input,
+                model.format.output_channels == 1,
             );
 
-            // Pass 1
-            tracing::info!("Pass 1/2: Upscaling {}x...", model_scale);
-            let pass1 = image_processing::process_tiled(
-                &image,
-                tiling_config.clone(), // Clone for first pass
-                model_scale,
-                &cancel_flag,
-                // We should probably wrap progress to show 0-50%
-                |_| { /* ignore for now or implement split progress */ },
-                create_inference_callback(model.session.clone()),
+            // One model pass into a disk canvas, on the same OOM ladder as in memory
+            let stream_pass = |stage_prefix: &str,
+                               pass: usize,
+                               input: TileInput,
+                               canvas: &mut DiskImage,
+                               progress: &mut dyn FnMut(f32)|
+             -> AppResult<()> {
+                tracing::info!(
+                    "{}Pass {}/{}: Streaming {}x...",
+                    stage_prefix,
+                    pass + 1,
+                    plan.passes,
+                    model_scale
+                );
+                if plan.passes > 1 || !stage_prefix.is_empty() {
+                    (callbacks.on_stage)(format!(
+                        "{}Pass {}/{}",
+                        stage_prefix,
+                        pass + 1,
+                        plan.passes
+                    ));
+                }
+                recovery::run_with_recovery(
+                    &attempt,
+                    &model.recovery,
+                    &model.path,
+                    limits,
+                    &callbacks.on_warning,
+                    |settings| {
+                        image_processing::process_tiled_to_disk(
+                            input,
+                            TilingConfig {
+                                tile_size: settings.tile_size,
+                                batch_size: settings.batch_size,
+                                workers: settings.session.pool_size(),
+                                ..tiling_config
+                            },
+                            model_scale,
+                            cancel.flag(),
+                            &mut *progress,
+                            create_inference_callback(settings.session.clone()),
+                            &mut *canvas,
+                        )
+                    },
+                )
+            };
+
+            let mut canvas = stream::stream_scaling(
+                &color_input,
+                tiled_alpha_plane,
+                &plan,
+                stream::DiskTarget {
+                    dir: &scratch_dir,
+                    color,
+                    inverse_tone_map: tone_mapped,
+                },
+                &mut |p| progress_callback(p * (1.0 - alpha_share)),
+                &mut |pass, input, canvas, progress| stream_pass("", pass, input, canvas, progress),
             )?;
 
-            // Pass 2
-            tracing::info!("Pass 2/2: Upscaling {}x again...", model_scale);
-            image_processing::process_tiled(
-                &pass1,        // Use output of pass 1
-                tiling_config, // Move original here
-                model_scale,
-                &cancel_flag,
-                &mut progress_callback, // Show 50-100%? Or just let it jump.
-                create_inference_callback(model.session.clone()),
-            )?
+            // The alpha plane gets its own canvas, interleaved when the output is encoded
+            if let (Some(alpha), false) = (&alpha_plane, tiled_alpha) {
+                let alpha_color = image_processing::disk_alpha_color(color);
+                let alpha_canvas = match alpha_mode {
+                    AlphaMode::Model => {
+                        tracing::info!("Streaming alpha channel through the model...");
+                        stream::stream_scaling(
+                            &alpha_model_input(alpha),
+                            None,
+                            &plan,
+                            stream::DiskTarget {
+                                dir: &scratch_dir,
+                                color: alpha_color,
+                                inverse_tone_map: false,
+                            },
+                            &mut |p| progress_callback((1.0 - alpha_share) + p * alpha_share),
+                            &mut |pass, input, canvas, progress| {
+                                stream_pass("Alpha: ", pass, input, canvas, progress)
+                            },
+                        )?
+                    }
+                    AlphaMode::Resize => stream::stream_alpha_resize(
+                        alpha,
+                        &plan,
+                        (in_w, in_h),
+                        &scratch_dir,
+                        alpha_color,
+                    )?,
+                };
+                canvas.set_alpha(alpha_canvas)?;
+            }
+            UpscaleOutput::Disk(canvas)
+        } else {
+            // Fail before the first pass rather than after the second
+            image_processing::check_memory(peak_w, peak_h, float_input, alpha_plane.is_some())?;
+
+            let (mut color_output, tiled_alpha_output) =
+                run_scaling(&color_input, tiled_alpha_plane, "", &mut |p| {
+                    progress_callback(p * (1.0 - alpha_share))
+                })?;
+            if tone_mapped {
+                image_processing::inverse_tone_map(&mut color_output);
+            }
+
+            let final_image = match alpha_plane {
+                Some(alpha) => {
+                    let alpha_output = match (tiled_alpha_output, alpha_mode) {
+                        (Some(tiled), _) => tiled,
+                        (None, AlphaMode::Model) => {
+                            tracing::info!("Upscaling alpha channel through the model...");
+                            let input = alpha_model_input(&alpha);
+                            run_scaling(&input, None, "Alpha: ", &mut |p| {
+                                progress_callback((1.0 - alpha_share) + p * alpha_share)
+                            })?
+                            .0
+                        }
+                        (None, AlphaMode::Resize) => {
+                            let (w, h) = color_output.dimensions();
+                            image_processing::resize_image(&alpha, w, h)?
+                        }
+                    };
+                    image_processing::merge_alpha(&color_output, &alpha_output)?
+                }
+                None => color_output,
+            };
+
+            // Exact output sizes with a different aspect ratio
+            let final_image = match plan.canvas {
+                Some((w, h, FillMode::Crop)) => image_processing::crop_center(&final_image, w, h),
+                Some((w, h, FillMode::Pad)) => image_processing::pad_center(&final_image, w, h),
+                None => final_image,
+            };
+
+            let final_image = image_processing::match_source_depth(final_image, source_color);
+            // Grayscale models fill all three channels with the same values
+            UpscaleOutput::Image(if model.format.output_channels == 1 {
+                image_processing::to_grayscale(final_image)
+            } else {
+                final_image
+            })
         };
 
         let total_batches = batches_counter.load(Ordering::Relaxed);
@@ -390,15 +787,11 @@ impl UpscaleEngine {
             avg_tps
         );
 
-        Ok(final_image)
+        Ok(output)
     }
 
-    pub fn save_result(
-        image: image::DynamicImage,
-        config: UpscaleConfig,
-        source_path: PathBuf,
-    ) -> AppResult<String> {
-        // Save Result
+    // Output location: `<stem>_upscaled.<ext>` next to the source or in `output_dir`
+    fn output_path(config: &UpscaleConfig, source_path: &std::path::Path) -> AppResult<PathBuf> {
         let file_stem = source_path.file_stem().unwrap().to_string_lossy();
         let ext = source_path.extension().unwrap().to_string_lossy();
         let target_ext = config.format.clone().unwrap_or(ext.to_string());
@@ -412,6 +805,16 @@ impl UpscaleEngine {
         } else {
             source_path.with_file_name(format!("{}_upscaled.{}", file_stem, target_ext))
         };
+        Ok(out_path)
+    }
+
+    pub fn save_result(
+        output: UpscaleOutput,
+        config: UpscaleConfig,
+        source_path: PathBuf,
+    ) -> AppResult<String> {
+        // Save Result
+        let out_path = Self::output_path(&config, &source_path)?;
 
         let compression = config.compression.clone().unwrap_or("lossy".to_string());
 
@@ -419,6 +822,26 @@ impl UpscaleEngine {
         let format = image::ImageFormat::from_path(&out_path).unwrap_or(image::ImageFormat::Png);
 
         let pp_start = std::time::Instant::now();
+        let image = match output {
+            UpscaleOutput::Image(image) => image,
+            UpscaleOutput::Disk(canvas) => {
+                // Encoded straight to disk, with the metadata written by the encoder
+                // (the grafting step needs the whole file in RAM)
+                let source_metadata = metadata::SourceMetadata::read(&source_path);
+                image_processing::save_disk_image(
+                    &canvas,
+                    &out_path,
+                    format,
+                    &compression,
+                    &source_metadata,
+                )?;
+                tracing::info!(
+                    "Post-Processing | Streamed encode: {:.2}s",
+                    pp_start.elapsed().as_secs_f32()
+                );
+                return Ok(out_path.to_string_lossy().to_string());
+            }
+        };
         let image_data = image_processing::encode_image(&image, format, &compression)?;
         let encode_duration = pp_start.elapsed().as_secs_f32();
 
@@ -442,30 +865,31 @@ impl UpscaleEngine {
         Ok(out_path.to_string_lossy().to_string())
     }
 
-    pub fn run<P, W>(
+    pub fn run<P, W, S>(
         config: UpscaleConfig,
         path: PathBuf,
         app_state: Arc<AppState>,
-        callbacks: EngineCallbacks<P, W>,
-        cancel_flag: Arc<AtomicBool>,
+        callbacks: EngineCallbacks<P, W, S>,
+        cancel: Arc<CancelToken>,
     ) -> AppResult<String>
     where
         P: Fn(f32, String) + Send + Sync + 'static,
         W: Fn(String) + Send + Sync + 'static,
+        S: Fn(String) + Send + Sync + 'static,
     {
         // 1. Load Model
         let loaded_model = Self::load_model(&config, app_state)?;
 
         // 2. Process (Inference)
-        let final_image = Self::process_with_session(
+        let output = Self::process_with_session(
             &loaded_model,
             config.clone(),
             path.clone(),
             callbacks,
-            cancel_flag,
+            cancel,
         )?;
 
         // 3. Save (Synchronous for Single File)
-        Self::save_result(final_image, config, path)
+        Self::save_result(output, config, path)
     }
 }
@@ -0,0 +1,202 @@
+use crate::error::{AppError, AppResult};
+use serde::{Deserialize, Serialize};
+
+/// Upper bound on chained model passes (4x model: 256x, 2x model: 16x).
+pub const MAX_PASSES: u32 = 4;
+
+/// Requested output size, as an alternative to a plain scale factor.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum OutputSize {
+    /// Fixed width, height follows the aspect ratio.
+    Width { width: u32 },
+    /// Fixed height, width follows the aspect ratio.
+    Height { height: u32 },
+    /// Largest size that fits inside the box, keeping the aspect ratio.
+    Fit { width: u32, height: u32 },
+    /// Exactly this size. The aspect mismatch is cropped or padded away.
+    Exact {
+        width: u32,
+        height: u32,
+        #[serde(default)]
+        fill: FillMode,
+    },
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FillMode {
+    /// Scale to cover the target and trim the overflow (centered).
+    #[default]
+    Crop,
+    /// Scale to fit inside the target and pad the rest (black or transparent).
+    Pad,
+}
+
+/// How a job gets from the input size to the requested output size.
+///
+/// Steps run in order: optional pre-resize, `passes` model runs at the model's
+/// native scale, optional resample, optional crop/pad to the final canvas.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScalePlan {
+    pub pre_resize: Option<(u32, u32)>,
+    pub passes: u32,
+    pub model_scale: u32,
+    pub resample: Option<(u32, u32)>,
+    pub canvas: Option<(u32, u32, FillMode)>,
+}
+
+impl ScalePlan {
+    /// Plans the minimal number of model passes that reach the target, followed by
+    /// a downsample to the exact size.
+    ///
+    /// With `pre_downscale`, an overshooting model chain is compensated by shrinking
+    /// the input first instead of the output. Cheaper, and often cleaner for noisy
+    /// high-resolution sources since the model sees averaged pixels.
+    pub fn new(
+        (width, height): (u32, u32),
+        model_scale: u32,
+        scale: f32,
+        output_size: Option<OutputSize>,
+        pre_downscale: bool,
+    ) -> AppResult<Self> {
+        if width == 0 || height == 0 || model_scale == 0 {
+            return Err(AppError::Unknown(format!(
+                "Cannot plan scaling for a {}x{} image with a {}x model",
+                width, height, model_scale
+            )));
+        }
+
+        let (w, h) = (width as f64, height as f64);
+        let (factor, canvas) = match output_size {
+            None => (scale as f64, None),
+            Some(OutputSize::Width { width: tw }) => (tw as f64 / w, None),
+            Some(OutputSize::Height { height: th }) => (th as f64 / h, None),
+            Some(OutputSize::Fit {
+                width: tw,
+                height: th,
+            }) => ((tw as f64 / w).min(th as f64 / h), None),
+            Some(OutputSize::Exact {
+                width: tw,
+                height: th,
+                fill,
+            }) => {
+                let factor = match fill {
+                    FillMode::Crop => (tw as f64 / w).max(th as f64 / h),
+                    FillMode::Pad => (tw as f64 / w).min(th as f64 / h),
+                };
+                (factor, Some((tw, th, fill)))
+            }
+        };
+
+        if !factor.is_finite() || factor <= 0.0 {
+            return Err(AppError::Unknown(format!(
+                "Invalid output scale {:.3}x",
+                factor
+            )));
+        }
+
+        let scaled = (
+            ((w * factor).round() as u32).max(1),
+            ((h * factor).round() as u32).max(1),
+        );
+
+        // Smallest chain that reaches the factor. A 1x (restoration) model runs once.
+        let mut passes = 1;
+        if model_scale > 1 {
+            while (model_scale as f64).powi(passes as i32) < factor * (1.0 - 1e-6) {
+                passes += 1;
+                if passes > MAX_PASSES {
+                    return Err(AppError::Unknown(format!(
+                        "Scale {:.2}x needs more than {} passes of a {}x model",
+                        factor, MAX_PASSES, model_scale
+                    )));
+                }
+            }
+        }
+        let total = (model_scale as f64).powi(passes as i32);
+
+        let pre_resize = if pre_downscale && total > factor {
+            let shrink = factor / total;
+            Some((
+                ((w * shrink).round() as u32).max(1),
+                ((h * shrink).round() as u32).max(1),
+            ))
+        } else {
+            None
+        };
+
+        let mut plan = Self {
+            pre_resize,
+            passes,
+            model_scale,
+            resample: None,
+            canvas: None,
+        };
+        if plan.model_output((width, height)) != scaled {
+            plan.resample = Some(scaled);
+        }
+        if let Some((tw, th, fill)) = canvas {
+            if (tw, th) != scaled {
+                plan.canvas = Some((tw, th, fill));
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// Size after the model passes, before any resample.
+    pub fn model_output(&self, input: (u32, u32)) -> (u32, u32) {
+        let (w, h) = self.pre_resize.unwrap_or(input);
+        let total = self.model_scale.pow(self.passes);
+        (w * total, h * total)
+    }
+
+    /// Final output size.
+    pub fn output_size(&self, input: (u32, u32)) -> (u32, u32) {
+        match (self.canvas, self.resample) {
+            (Some((w, h, _)), _) | (None, Some((w, h))) => (w, h),
+            (None, None) => self.model_output(input),
+        }
+    }
+
+    /// Share of the total model work done by each pass, weighted by the pixels
+    /// it produces. Sums to 1.
+    pub fn pass_weights(&self, input: (u32, u32)) -> Vec<f32> {
+        let (w, h) = self.pre_resize.unwrap_or(input);
+        let mut pixels = w as f64 * h as f64;
+        let step = (self.model_scale * self.model_scale) as f64;
+        let per_pass: Vec<f64> = (0..self.passes)
+            .map(|_| {
+                pixels *= step;
+                pixels
+            })
+            .collect();
+        let total: f64 = per_pass.iter().sum();
+        per_pass.iter().map(|p| (p / total) as f32).collect()
+    }
+
+    /// Largest image held at any step (model passes, resample, final canvas).
+    /// This is what has to fit in memory.
+    pub fn largest_intermediate(&self, input: (u32, u32)) -> (u32, u32) {
+        let model_output = self.model_output(input);
+        let candidates = [
+            Some(model_output),
+            self.resample,
+            self.canvas.map(|(w, h, _)| (w, h)),
+        ];
+        candidates
+            .into_iter()
+            .flatten()
+            .max_by_key(|&(w, h)| w as u64 * h as u64)
+            .unwrap_or(model_output)
+    }
+
+    /// True if the job is one model pass with nothing before or after it.
+    pub fn is_single_pass(&self) -> bool {
+        self.passes == 1
+            && self.pre_resize.is_none()
+            && self.resample.is_none()
+            && self.canvas.is_none()
+    }
+}
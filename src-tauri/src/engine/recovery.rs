@@ -0,0 +1,128 @@
+use crate::error::{AppError, AppResult};
+use crate::inference::OrtSession;
+use std::cell::RefCell;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Smallest tile the ladder will shrink to.
+pub const MIN_TILE_SIZE: u32 = 64;
+
+/// Settings a job runs with. Starts from the config and steps down on OOM.
+#[derive(Clone)]
+pub struct Attempt {
+    pub batch_size: usize,
+    pub tile_size: u32,
+    pub session: Arc<OrtSession>,
+}
+
+/// Which ladder steps the model allows.
+#[derive(Clone, Copy)]
+pub struct Limits {
+    pub fixed_batch: bool, // Static batch dimension in the graph
+    pub fixed_tile: bool,  // Static spatial dimensions in the graph
+}
+
+impl Attempt {
+    /// Next rung of the OOM ladder: halve the batch, then shrink the tile,
+    /// then move to a CPU session. Returns the new settings and a user-facing
+    /// message, or None when there is nothing left to try.
+    pub fn downshift(
+        &self,
+        model_path: &Path,
+        limits: Limits,
+    ) -> AppResult<Option<(Self, String)>> {
+        if !limits.fixed_batch && self.batch_size > 1 {
+            let batch_size = self.batch_size / 2;
+            return Ok(Some((
+                Self {
+                    batch_size,
+                    ..self.clone()
+                },
+                format!("Out of memory. Retrying with batch size {}.", batch_size),
+            )));
+        }
+
+        if !limits.fixed_tile && self.tile_size > MIN_TILE_SIZE {
+            // Keep tiles on a multiple of 16, models often need aligned inputs
+            let tile_size = (self.tile_size / 2 / 16 * 16).max(MIN_TILE_SIZE);
+            return Ok(Some((
+                Self {
+                    tile_size,
+                    ..self.clone()
+                },
+                format!("Out of memory. Retrying with tile size {}.", tile_size),
+            )));
+        }
+
+        self.on_cpu(
+            model_path,
+            "Out of GPU memory. Falling back to CPU (this will be slower).",
+        )
+    }
+
+    /// The same settings on a fresh CPU session, or None if already on CPU.
+    pub fn on_cpu(&self, model_path: &Path, message: &str) -> AppResult<Option<(Self, String)>> {
+        if self.session.execution_provider == "CPU" {
+            return Ok(None);
+        }
+        tracing::warn!(
+            "Leaving {}. Loading CPU session.",
+            self.session.execution_provider
+        );
+        let session = OrtSession::new(
+            model_path,
+            false,
+            Some("cpu".to_string()),
+            self.session.cpu_pool,
+            self.session.options,
+            self.session.optimized_cache.clone(),
+        )?;
+        Ok(Some((
+            Self {
+                session: Arc::new(session),
+                ..self.clone()
+            },
+            message.to_string(),
+        )))
+    }
+}
+
+/// Runs `job` with the current attempt, stepping down the ladder on out-of-memory
+/// errors until it succeeds or the ladder is exhausted. A lost GPU skips straight
+/// to the CPU rung, as smaller batches can't help. Each step is reported through
+/// `on_warning` and stored in `attempt` (and `remember`) for later runs.
+pub fn run_with_recovery<T>(
+    attempt: &RefCell<Attempt>,
+    remember: &std::sync::Mutex<Option<Attempt>>,
+    model_path: &Path,
+    limits: Limits,
+    on_warning: &dyn Fn(String),
+    mut job: impl FnMut(&Attempt) -> AppResult<T>,
+) -> AppResult<T> {
+    loop {
+        let current = attempt.borrow().clone();
+        let (error, step) = match job(&current) {
+            Err(AppError::OutOfMemory(message)) => (
+                AppError::OutOfMemory(message),
+                current.downshift(model_path, limits)?,
+            ),
+            Err(AppError::DeviceLost(message)) => (
+                AppError::DeviceLost(message),
+                current.on_cpu(
+                    model_path,
+                    "The GPU was reset or removed. Continuing on CPU (this will be slower).",
+                )?,
+            ),
+            result => return result,
+        };
+        let Some((next, warning)) = step else {
+            return Err(error);
+        };
+        tracing::warn!("{} (Technical: {})", warning, error);
+        on_warning(warning);
+        if let Ok(mut remembered) = remember.lock() {
+            *remembered = Some(next.clone());
+        }
+        *attempt.borrow_mut() = next;
+    }
+}
@@ -0,0 +1,129 @@
+use super::plan::ScalePlan;
+use crate::error::{AppError, AppResult};
+use crate::image_processing::{self, DiskImage, TileInput};
+use image::GenericImageView;
+use std::borrow::Cow;
+use std::path::Path;
+
+/// Final canvas of a streamed job.
+#[derive(Clone, Copy)]
+pub struct DiskTarget<'a> {
+    /// Scratch folder for the canvases of every step.
+    pub dir: &'a Path,
+    /// Layout of the last pass and everything after it (see `disk_color`).
+    pub color: image::ColorType,
+    /// Undo `tone_map` as the last pass writes its tiles.
+    pub inverse_tone_map: bool,
+}
+
+/// Tiles one model pass (by index) from its input into a disk canvas, reporting 0..1.
+pub type PassRunner<'a> =
+    dyn FnMut(usize, TileInput, &mut DiskImage, &mut dyn FnMut(f32)) -> AppResult<()> + 'a;
+
+/// Out-of-core counterpart of the engine's in-memory scaling: runs every step of
+/// `plan` with each model pass writing into its own disk canvas and reading the
+/// previous one, so no intermediate of the chain has to fit in RAM.
+///
+/// The pre-resize runs in memory, as it only shrinks the (already loaded) input.
+/// `run_pass` tiles one model pass into the canvas it is given, reporting 0..1;
+/// the pass index is passed along for stage labels. Resample and crop/pad work
+/// on the disk canvas of the last pass.
+///
+/// `alpha` (the `split_alpha` plane, for 4-channel models) is tiled along with
+/// the color: every canvas gets an alpha canvas the model's alpha goes into.
+pub fn stream_scaling(
+    input: &image::DynamicImage,
+    alpha: Option<&image::DynamicImage>,
+    plan: &ScalePlan,
+    target: DiskTarget,
+    progress: &mut dyn FnMut(f32),
+    run_pass: &mut PassRunner,
+) -> AppResult<DiskImage> {
+    let (current, alpha) = match plan.pre_resize {
+        Some((w, h)) => {
+            tracing::info!("Pre-downscaling input to {}x{}", w, h);
+            (
+                Cow::Owned(image_processing::resize_image(input, w, h)?),
+                alpha
+                    .map(|alpha| image_processing::resize_image(alpha, w, h))
+                    .transpose()?
+                    .map(Cow::Owned),
+            )
+        }
+        None => (Cow::Borrowed(input), alpha.map(Cow::Borrowed)),
+    };
+
+    // Earlier passes keep the precision the tiler works at
+    let intermediate = if current.color() == image::ColorType::Rgb32F {
+        image::ColorType::Rgb32F
+    } else {
+        image::ColorType::Rgb8
+    };
+
+    let (mut width, mut height) = current.dimensions();
+    let mut previous: Option<DiskImage> = None;
+    let mut base = 0.0;
+    for (pass, &weight) in plan.pass_weights(input.dimensions()).iter().enumerate() {
+        let last = pass + 1 == plan.passes as usize;
+        (width, height) = (width * plan.model_scale, height * plan.model_scale);
+        let color = if last { target.color } else { intermediate };
+        let mut canvas = DiskImage::create(target.dir, width, height, color)?;
+        canvas.set_inverse_tone_map(last && target.inverse_tone_map);
+        if alpha.is_some() {
+            let alpha_color = image_processing::disk_alpha_color(color);
+            canvas.set_alpha(DiskImage::create(target.dir, width, height, alpha_color)?)?;
+        }
+
+        let source = match (&previous, &alpha) {
+            (Some(previous), _) => TileInput::Disk(previous),
+            (None, Some(alpha)) => TileInput::ImageAlpha(&current, alpha),
+            (None, None) => TileInput::Image(&current),
+        };
+        run_pass(pass, source, &mut canvas, &mut |p| {
+            progress(base + p * weight)
+        })?;
+        previous = Some(canvas);
+        base += weight;
+    }
+    let canvas =
+        previous.ok_or_else(|| AppError::Unknown("Scale plan has no model passes".to_string()))?;
+
+    let canvas = match plan.resample {
+        Some((w, h)) => {
+            tracing::info!(
+                "Resizing streamed output from {}x{} to {}x{}",
+                width,
+                height,
+                w,
+                h
+            );
+            image_processing::resize_disk_image(&canvas, w, h)?
+        }
+        None => canvas,
+    };
+    reframe(canvas, plan)
+}
+
+/// Resamples the source alpha plane to the size `stream_scaling` produces for
+/// `input_size` and frames it the same way, for `AlphaMode::Resize`.
+pub fn stream_alpha_resize(
+    alpha: &image::DynamicImage,
+    plan: &ScalePlan,
+    input_size: (u32, u32),
+    dir: &Path,
+    color: image::ColorType,
+) -> AppResult<DiskImage> {
+    let size = plan
+        .resample
+        .unwrap_or_else(|| plan.model_output(input_size));
+    let canvas = image_processing::resize_alpha_to_disk(alpha, dir, size, color)?;
+    reframe(canvas, plan)
+}
+
+// Exact output sizes with a different aspect ratio
+fn reframe(canvas: DiskImage, plan: &ScalePlan) -> AppResult<DiskImage> {
+    match plan.canvas {
+        Some((w, h, _)) => image_processing::reframe_disk_image(&canvas, w, h),
+        None => Ok(canvas),
+    }
+}
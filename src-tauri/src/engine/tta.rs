@@ -0,0 +1,165 @@
+use crate::error::{AppError, AppResult};
+use crate::image_processing::TileBatch;
+
+/// The 8 symmetries of a square: rotations and flips.
+///
+/// Ordered so that the first 2 and first 4 are useful subsets on their own,
+/// and so that those keep the tile shape (no axis swap).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dihedral {
+    Identity,
+    FlipH,
+    FlipV,
+    Rotate180,
+    Rotate90,
+    Rotate270,
+    Transpose,
+    AntiTranspose,
+}
+
+impl Dihedral {
+    pub const ALL: [Dihedral; 8] = [
+        Dihedral::Identity,
+        Dihedral::FlipH,
+        Dihedral::FlipV,
+        Dihedral::Rotate180,
+        Dihedral::Rotate90,
+        Dihedral::Rotate270,
+        Dihedral::Transpose,
+        Dihedral::AntiTranspose,
+    ];
+
+    /// Transforms for a self-ensemble of `count` runs (1, 2, 4 or 8).
+    pub fn set(count: u32) -> AppResult<&'static [Dihedral]> {
+        match count {
+            1 | 2 | 4 | 8 => Ok(&Self::ALL[..count as usize]),
+            _ => Err(AppError::Unknown(format!(
+                "Self-ensemble size must be 1, 2, 4 or 8 (got {})",
+                count
+            ))),
+        }
+    }
+
+    /// True if the transform exchanges width and height.
+    pub fn swaps_axes(self) -> bool {
+        matches!(
+            self,
+            Dihedral::Rotate90
+                | Dihedral::Rotate270
+                | Dihedral::Transpose
+                | Dihedral::AntiTranspose
+        )
+    }
+
+    pub fn inverse(self) -> Self {
+        match self {
+            Dihedral::Rotate90 => Dihedral::Rotate270,
+            Dihedral::Rotate270 => Dihedral::Rotate90,
+            other => other,
+        }
+    }
+
+    /// Source position of destination pixel (x, y) for a `w`x`h` source.
+    fn source(self, x: u32, y: u32, (w, h): (u32, u32)) -> (u32, u32) {
+        match self {
+            Dihedral::Identity => (x, y),
+            Dihedral::FlipH => (w - 1 - x, y),
+            Dihedral::FlipV => (x, h - 1 - y),
+            Dihedral::Rotate180 => (w - 1 - x, h - 1 - y),
+            Dihedral::Rotate90 => (y, h - 1 - x),
+            Dihedral::Rotate270 => (w - 1 - y, x),
+            Dihedral::Transpose => (y, x),
+            Dihedral::AntiTranspose => (w - 1 - y, h - 1 - x),
+        }
+    }
+
+    /// Applies the transform to every plane of a batch.
+    pub fn apply(self, batch: &TileBatch) -> TileBatch {
+        if self == Dihedral::Identity {
+            return batch.clone();
+        }
+        let (w, h) = (batch.width, batch.height);
+        let (out_w, out_h) = if self.swaps_axes() { (h, w) } else { (w, h) };
+
+        // Same gather for every plane, so build the index map once
+        let map: Vec<usize> = (0..out_h)
+            .flat_map(|y| {
+                (0..out_w).map(move |x| {
+                    let (sx, sy) = self.source(x, y, (w, h));
+                    sy as usize * w as usize + sx as usize
+                })
+            })
+            .collect();
+
+        let plane = w as usize * h as usize;
+        let mut data = vec![0.0; batch.data.len()];
+        for (dst, src) in data
+            .chunks_exact_mut(plane)
+            .zip(batch.data.chunks_exact(plane))
+        {
+            for (d, &i) in dst.iter_mut().zip(&map) {
+                *d = src[i];
+            }
+        }
+
+        TileBatch {
+            data,
+            len: batch.len,
+            channels: batch.channels,
+            width: out_w,
+            height: out_h,
+        }
+    }
+}
+
+/// Runs `infer` once per transform on the whole batch (so batching still applies),
+/// undoes each transform on the outputs and averages them per tile.
+///
+/// Axis-swapping transforms are skipped for non-square tiles, since the model
+/// input shape must not change between runs.
+pub fn self_ensemble<F>(
+    batch: TileBatch,
+    transforms: &[Dihedral],
+    mut infer: F,
+) -> AppResult<TileBatch>
+where
+    F: FnMut(TileBatch) -> AppResult<TileBatch>,
+{
+    if batch.len == 0 {
+        return Ok(batch);
+    }
+    let transforms: Vec<Dihedral> = transforms
+        .iter()
+        .copied()
+        .filter(|t| batch.width == batch.height || !t.swaps_axes())
+        .collect();
+
+    let mut sum: Option<TileBatch> = None;
+    for &transform in &transforms {
+        let output = infer(transform.apply(&batch))?;
+        if output.len != batch.len {
+            return Err(AppError::Unknown("Batch size mismatch".to_string()));
+        }
+
+        let restored = transform.inverse().apply(&output);
+        let Some(acc) = sum.as_mut() else {
+            sum = Some(restored);
+            continue;
+        };
+        if (restored.width, restored.height) != (acc.width, acc.height) {
+            return Err(AppError::InferenceError(format!(
+                "Self-ensemble output {}x{} does not match {}x{}",
+                restored.width, restored.height, acc.width, acc.height
+            )));
+        }
+        for (a, v) in acc.data.iter_mut().zip(&restored.data) {
+            *a += v;
+        }
+    }
+
+    let mut sum =
+        sum.ok_or_else(|| AppError::Unknown("No self-ensemble transforms".to_string()))?;
+    let inv = 1.0 / transforms.len() as f32;
+    sum.data.iter_mut().for_each(|v| *v *= inv);
+    Ok(sum)
+}
@@ -25,6 +25,20 @@ pub enum AppError {
     #[error("Inference Error: {0}")]
     InferenceError(String),
 
+    #[error("Download Error: {0}")]
+    DownloadError(String),
+
+    #[error("Out of Memory: {0}")]
+    OutOfMemory(String),
+
+    // The GPU was reset or removed while running
+    #[error("GPU Device Lost: {0}")]
+    DeviceLost(String),
+
+    // Stopped by the user, not a failure
+    #[error("Operation cancelled")]
+    Cancelled,
+
     #[error("Unknown Error: {0}")]
     Unknown(String),
 }
@@ -1,20 +1,62 @@
 use crate::error::{AppError, AppResult};
+use crate::metadata::SourceMetadata;
 // FIX: Import Image from the 'images' submodule for newer versions of fast_image_resize
 use fast_image_resize::images::Image;
 use fast_image_resize::Resizer;
-use image::{DynamicImage, GenericImageView, ImageBuffer, Rgb};
+use image::{
+    DynamicImage, GenericImageView, GrayImage, ImageBuffer, ImageEncoder, Luma, Pixel, Rgb32FImage,
+    RgbImage, RgbaImage,
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use sysinfo::System;
 
 pub fn load_image(path: &Path) -> AppResult<DynamicImage> {
+    load_image_with_precision(path, false)
+}
+
+/// Loads an image for processing.
+///
+/// Standard mode normalizes to 8-bit RGB (or RGBA when the source has transparency).
+/// With `high_precision`, 16-bit and float sources (16-bit PNG/TIFF, EXR) keep their
+/// depth as `Rgb16`/`Rgba16` or `Rgb32F`/`Rgba32F`. 8-bit sources are unaffected.
+pub fn load_image_with_precision(path: &Path, high_precision: bool) -> AppResult<DynamicImage> {
     let file = File::open(path).map_err(|e| AppError::Unknown(e.to_string()))?;
     let mmap = unsafe { memmap2::Mmap::map(&file).map_err(|e| AppError::Unknown(e.to_string()))? };
     let mut image = image::load_from_memory(&mmap).map_err(AppError::from)?;
 
-    if image.color() != image::ColorType::Rgb8 {
+    let color = image.color();
+    if high_precision && is_high_depth(color) {
+        // Keep the precision, only collapse grey/grey-alpha into RGB(A)
+        image = match (is_float(color), color.has_alpha()) {
+            (true, true) if color != image::ColorType::Rgba32F => {
+                DynamicImage::ImageRgba32F(image.to_rgba32f())
+            }
+            (true, false) if color != image::ColorType::Rgb32F => {
+                DynamicImage::ImageRgb32F(image.to_rgb32f())
+            }
+            (false, true) if color != image::ColorType::Rgba16 => {
+                DynamicImage::ImageRgba16(image.to_rgba16())
+            }
+            (false, false) if color != image::ColorType::Rgb16 => {
+                DynamicImage::ImageRgb16(image.to_rgb16())
+            }
+            _ => image,
+        };
+        return Ok(image);
+    }
+
+    // Normalize to 8-bit RGB, or RGBA when the source carries transparency.
+    // Alpha is split off again by the engine (see `split_alpha`).
+    if color.has_alpha() {
+        if color != image::ColorType::Rgba8 {
+            image = DynamicImage::ImageRgba8(image.to_rgba8());
+        }
+    } else if color != image::ColorType::Rgb8 {
         image = DynamicImage::ImageRgb8(image.to_rgb8());
     }
 
@@ -25,6 +67,82 @@ pub fn load_image(path: &Path) -> AppResult<DynamicImage> {
     Ok(image)
 }
 
+/// True for color types with more than 8 bits per channel.
+pub fn is_high_depth(color: image::ColorType) -> bool {
+    color.bits_per_pixel() / color.channel_count() as u16 > 8
+}
+
+fn is_float(color: image::ColorType) -> bool {
+    matches!(color, image::ColorType::Rgb32F | image::ColorType::Rgba32F)
+}
+
+/// Converts a processed image back to the depth class of its source:
+/// float sources stay float, 16-bit sources become 16-bit, 8-bit stays 8-bit.
+pub fn match_source_depth(image: DynamicImage, source: image::ColorType) -> DynamicImage {
+    if !is_high_depth(source) {
+        return image;
+    }
+    match (is_float(source), image.color().has_alpha()) {
+        (true, true) => DynamicImage::ImageRgba32F(image.into_rgba32f()),
+        (true, false) => DynamicImage::ImageRgb32F(image.into_rgb32f()),
+        (false, true) => DynamicImage::ImageRgba16(image.into_rgba16()),
+        (false, false) => DynamicImage::ImageRgb16(image.into_rgb16()),
+    }
+}
+
+/// Grayscale copy of an image at the same depth, keeping alpha. Float images are
+/// returned unchanged since there is no float luma type.
+pub fn to_grayscale(image: DynamicImage) -> DynamicImage {
+    let color = image.color();
+    if is_float(color) {
+        return image;
+    }
+    match (is_high_depth(color), color.has_alpha()) {
+        (true, true) => DynamicImage::ImageLumaA16(image.into_luma_alpha16()),
+        (true, false) => DynamicImage::ImageLuma16(image.into_luma16()),
+        (false, true) => DynamicImage::ImageLumaA8(image.into_luma_alpha8()),
+        (false, false) => DynamicImage::ImageLuma8(image.into_luma8()),
+    }
+}
+
+/// True if a float image has values outside the displayable 0..1 range.
+pub fn has_hdr_values(image: &DynamicImage) -> bool {
+    match image {
+        DynamicImage::ImageRgb32F(buf) => buf.as_raw().par_iter().any(|&v| v > 1.0),
+        DynamicImage::ImageRgba32F(buf) => buf
+            .as_raw()
+            .par_chunks(4)
+            .any(|p| p[0] > 1.0 || p[1] > 1.0 || p[2] > 1.0),
+        _ => false,
+    }
+}
+
+/// Compresses HDR values into 0..1 (Reinhard, `x / (1 + x)`) so the model sees
+/// the range it was trained on. Only affects float images.
+pub fn tone_map(image: &mut DynamicImage) {
+    if let DynamicImage::ImageRgb32F(buf) = image {
+        buf.as_mut().par_iter_mut().for_each(|v| {
+            let x = v.max(0.0);
+            *v = x / (1.0 + x);
+        });
+    }
+}
+
+/// Inverse of `tone_map`, applied to the model output to restore the HDR range.
+pub fn inverse_tone_map(image: &mut DynamicImage) {
+    if let DynamicImage::ImageRgb32F(buf) = image {
+        buf.as_mut()
+            .par_iter_mut()
+            .for_each(|v| *v = inverse_tone_map_value(*v));
+    }
+}
+
+fn inverse_tone_map_value(v: f32) -> f32 {
+    // Keep away from the pole at 1.0; the model may slightly overshoot
+    let y = v.clamp(0.0, 0.999);
+    y / (1.0 - y)
+}
+
 pub fn encode_image(
     image: &DynamicImage,
     format: image::ImageFormat,
@@ -33,6 +151,29 @@ pub fn encode_image(
     let mut buffer = Vec::new();
     let mut w = std::io::Cursor::new(&mut buffer);
 
+    // Only PNG (16-bit), TIFF and EXR (float) can carry high-precision output
+    let converted;
+    let image = match (format, is_high_depth(image.color())) {
+        (image::ImageFormat::Png, true) if is_float(image.color()) => {
+            converted = match_source_depth(image.clone(), image::ColorType::Rgb16);
+            &converted
+        }
+        (image::ImageFormat::OpenExr, _) if !is_float(image.color()) => {
+            converted = match_source_depth(image.clone(), image::ColorType::Rgb32F);
+            &converted
+        }
+        (image::ImageFormat::Png | image::ImageFormat::Tiff | image::ImageFormat::OpenExr, _)
+        | (_, false) => image,
+        (_, true) => {
+            converted = if image.color().has_alpha() {
+                DynamicImage::ImageRgba8(image.to_rgba8())
+            } else {
+                DynamicImage::ImageRgb8(image.to_rgb8())
+            };
+            &converted
+        }
+    };
+
     if format == image::ImageFormat::Png {
         // OPTIMIZATION: Use Fast compression for speed.
         // Default is Best/Slow, which takes 20s+ for large images. Fast takes ~2-5s.
@@ -68,7 +209,14 @@ pub fn encode_image(
         }
     } else if format == image::ImageFormat::Jpeg {
         let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(w, 90);
-        image.write_with_encoder(encoder).map_err(AppError::from)?;
+        if image.color().has_alpha() {
+            // JPEG has no alpha. Transparent areas keep their bled colors (see `split_alpha`).
+            DynamicImage::ImageRgb8(image.to_rgb8())
+                .write_with_encoder(encoder)
+                .map_err(AppError::from)?;
+        } else {
+            image.write_with_encoder(encoder).map_err(AppError::from)?;
+        }
     } else {
         image.write_to(&mut w, format).map_err(AppError::from)?;
     }
@@ -83,16 +231,220 @@ pub fn save_image(image: &DynamicImage, path: &Path, compression: &str) -> AppRe
     Ok(())
 }
 
+/// Pixel layout a disk canvas should use for a given output format, mirroring the
+/// depth rules of `encode_image`. Grayscale output stays luma where the format
+/// can store it, like `to_grayscale` does in memory.
+pub fn disk_color(
+    format: image::ImageFormat,
+    high_precision: bool,
+    grayscale: bool,
+) -> image::ColorType {
+    let luma = grayscale
+        && matches!(
+            format,
+            image::ImageFormat::Png | image::ImageFormat::Tiff | image::ImageFormat::Jpeg
+        );
+    match format {
+        image::ImageFormat::OpenExr => image::ColorType::Rgb32F,
+        image::ImageFormat::Png | image::ImageFormat::Tiff if high_precision => {
+            if luma {
+                image::ColorType::L16
+            } else {
+                image::ColorType::Rgb16
+            }
+        }
+        _ if luma => image::ColorType::L8,
+        _ => image::ColorType::Rgb8,
+    }
+}
+
+/// Encodes a disk canvas straight to `path` without loading it into RAM.
+/// PNG is written row by row, with the alpha plane interleaved as it goes; other
+/// formats read directly from the mapping. JPEG drops the alpha plane.
+pub fn save_disk_image(
+    image: &DiskImage,
+    path: &Path,
+    format: image::ImageFormat,
+    compression: &str,
+    metadata: &SourceMetadata,
+) -> AppResult<()> {
+    // Encoders that take every row at once get a merged copy of color and alpha
+    if image.alpha().is_some()
+        && !matches!(format, image::ImageFormat::Png | image::ImageFormat::Jpeg)
+    {
+        return save_disk_image(&image.merged()?, path, format, compression, metadata);
+    }
+
+    let (width, height) = image.dimensions();
+    let color = if format == image::ImageFormat::Jpeg {
+        image.color()
+    } else {
+        image.output_color()
+    };
+    let writer = std::io::BufWriter::new(File::create(path)?);
+
+    let unsupported =
+        || AppError::ImageError(format!("Cannot stream {:?} output as {:?}", color, format));
+
+    match format {
+        image::ImageFormat::Png => {
+            let (png_color, depth) = match color {
+                image::ColorType::Rgb8 => (png::ColorType::Rgb, png::BitDepth::Eight),
+                image::ColorType::Rgb16 => (png::ColorType::Rgb, png::BitDepth::Sixteen),
+                image::ColorType::L8 => (png::ColorType::Grayscale, png::BitDepth::Eight),
+                image::ColorType::L16 => (png::ColorType::Grayscale, png::BitDepth::Sixteen),
+                image::ColorType::Rgba8 => (png::ColorType::Rgba, png::BitDepth::Eight),
+                image::ColorType::Rgba16 => (png::ColorType::Rgba, png::BitDepth::Sixteen),
+                image::ColorType::La8 => (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight),
+                image::ColorType::La16 => (png::ColorType::GrayscaleAlpha, png::BitDepth::Sixteen),
+                _ => return Err(unsupported()),
+            };
+            let mut info = png::Info::with_size(width, height);
+            info.icc_profile = metadata.icc_profile.as_deref().map(Into::into);
+            info.exif_metadata = metadata.exif.as_deref().map(Into::into);
+            let mut encoder = png::Encoder::with_info(writer, info)
+                .map_err(|e| AppError::ImageError(e.to_string()))?;
+            encoder.set_color(png_color);
+            encoder.set_depth(depth);
+            encoder.set_compression(png::Compression::Fast);
+            let mut png_writer = encoder
+                .write_header()
+                .map_err(|e| AppError::ImageError(e.to_string()))?;
+            let mut stream = png_writer
+                .stream_writer()
+                .map_err(|e| AppError::ImageError(e.to_string()))?;
+
+            let row_bytes = width as usize * color.bytes_per_pixel() as usize;
+            let mut big_endian = Vec::with_capacity(row_bytes);
+            let mut merged = Vec::with_capacity(row_bytes);
+            for y in 0..height {
+                let row = image.output_row(y, &mut merged);
+                if depth == png::BitDepth::Sixteen {
+                    // PNG stores 16-bit samples big endian
+                    big_endian.clear();
+                    for sample in row.chunks_exact(2) {
+                        let v = u16::from_ne_bytes([sample[0], sample[1]]);
+                        big_endian.extend_from_slice(&v.to_be_bytes());
+                    }
+                    std::io::Write::write_all(&mut stream, &big_endian)?;
+                } else {
+                    std::io::Write::write_all(&mut stream, row)?;
+                }
+            }
+            stream
+                .finish()
+                .map_err(|e| AppError::ImageError(e.to_string()))?;
+        }
+        image::ImageFormat::WebP if compression == "lossy" => {
+            let memory = match color {
+                image::ColorType::Rgb8 => webp::Encoder::from_rgb(image.as_bytes(), width, height),
+                image::ColorType::Rgba8 => {
+                    webp::Encoder::from_rgba(image.as_bytes(), width, height)
+                }
+                _ => return Err(unsupported()),
+            }
+            .encode(75.0);
+            use img_parts::{ImageEXIF, ImageICC};
+            let mut webp = img_parts::webp::WebP::from_bytes(memory.to_vec().into())
+                .map_err(|e| AppError::ImageError(e.to_string()))?;
+            webp.set_icc_profile(metadata.icc_profile.clone().map(Into::into));
+            webp.set_exif(metadata.exif.clone().map(Into::into));
+            webp.encoder().write_to(writer)?;
+        }
+        _ => {
+            let extended = match color {
+                image::ColorType::Rgb8 => image::ExtendedColorType::Rgb8,
+                image::ColorType::Rgb16 => image::ExtendedColorType::Rgb16,
+                image::ColorType::Rgb32F => image::ExtendedColorType::Rgb32F,
+                image::ColorType::L8 => image::ExtendedColorType::L8,
+                image::ColorType::L16 => image::ExtendedColorType::L16,
+                image::ColorType::Rgba8 => image::ExtendedColorType::Rgba8,
+                image::ColorType::Rgba16 => image::ExtendedColorType::Rgba16,
+                image::ColorType::Rgba32F => image::ExtendedColorType::Rgba32F,
+                image::ColorType::La8 => image::ExtendedColorType::La8,
+                image::ColorType::La16 => image::ExtendedColorType::La16,
+                _ => return Err(unsupported()),
+            };
+            let bytes = image.as_bytes();
+            match format {
+                image::ImageFormat::Jpeg => encode_with_metadata(
+                    image::codecs::jpeg::JpegEncoder::new_with_quality(writer, 90),
+                    metadata,
+                    bytes,
+                    width,
+                    height,
+                    extended,
+                )?,
+                image::ImageFormat::WebP => encode_with_metadata(
+                    image::codecs::webp::WebPEncoder::new_lossless(writer),
+                    metadata,
+                    bytes,
+                    width,
+                    height,
+                    extended,
+                )?,
+                image::ImageFormat::Tiff => encode_with_metadata(
+                    image::codecs::tiff::TiffEncoder::new(writer),
+                    metadata,
+                    bytes,
+                    width,
+                    height,
+                    extended,
+                )?,
+                image::ImageFormat::OpenExr => encode_with_metadata(
+                    image::codecs::openexr::OpenExrEncoder::new(writer),
+                    metadata,
+                    bytes,
+                    width,
+                    height,
+                    extended,
+                )?,
+                _ => return Err(unsupported()),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Formats that can't carry a profile or EXIF (EXR, EXIF in TIFF) skip it
+fn encode_with_metadata<E: ImageEncoder>(
+    mut encoder: E,
+    metadata: &SourceMetadata,
+    bytes: &[u8],
+    width: u32,
+    height: u32,
+    color: image::ExtendedColorType,
+) -> AppResult<()> {
+    if let Some(icc) = &metadata.icc_profile {
+        let _ = encoder.set_icc_profile(icc.clone());
+    }
+    if let Some(exif) = &metadata.exif {
+        let _ = encoder.set_exif_metadata(exif.clone());
+    }
+    Ok(encoder.write_image(bytes, width, height, color)?)
+}
+
 pub fn resize_image(image: &DynamicImage, width: u32, height: u32) -> AppResult<DynamicImage> {
     let src_width = std::num::NonZeroU32::new(image.width()).unwrap();
     let src_height = std::num::NonZeroU32::new(image.height()).unwrap();
-    let src_image = Image::from_vec_u8(
-        src_width.into(),
-        src_height.into(),
-        image.to_rgb8().into_raw(),
-        fast_image_resize::PixelType::U8x3,
-    )
-    .map_err(|e| AppError::ImageError(e.to_string()))?;
+
+    // Keep grey (alpha planes), RGBA and high-depth layouts as-is;
+    // everything else goes through 8-bit RGB.
+    use fast_image_resize::PixelType;
+    let (raw, pixel_type) = match image {
+        DynamicImage::ImageLuma8(buf) => (buf.as_raw().clone(), PixelType::U8),
+        DynamicImage::ImageLuma16(buf) => (u16_to_bytes(buf.as_raw()), PixelType::U16),
+        DynamicImage::ImageRgba8(buf) => (buf.as_raw().clone(), PixelType::U8x4),
+        DynamicImage::ImageRgb16(buf) => (u16_to_bytes(buf.as_raw()), PixelType::U16x3),
+        DynamicImage::ImageRgba16(buf) => (u16_to_bytes(buf.as_raw()), PixelType::U16x4),
+        DynamicImage::ImageRgb32F(buf) => (f32_to_bytes(buf.as_raw()), PixelType::F32x3),
+        DynamicImage::ImageRgba32F(buf) => (f32_to_bytes(buf.as_raw()), PixelType::F32x4),
+        _ => (image.to_rgb8().into_raw(), PixelType::U8x3),
+    };
+
+    let src_image = Image::from_vec_u8(src_width.into(), src_height.into(), raw, pixel_type)
+        .map_err(|e| AppError::ImageError(e.to_string()))?;
 
     let dst_width = std::num::NonZeroU32::new(width).unwrap();
     let dst_height = std::num::NonZeroU32::new(height).unwrap();
@@ -111,260 +463,2017 @@ pub fn resize_image(image: &DynamicImage, width: u32, height: u32) -> AppResult<
         )
         .map_err(|e| AppError::ImageError(e.to_string()))?;
 
-    let buffer = ImageBuffer::<Rgb<u8>, Vec<u8>>::from_raw(width, height, dst_image.into_vec())
-        .ok_or_else(|| AppError::ImageError("Failed to create image buffer".to_string()))?;
-    Ok(DynamicImage::ImageRgb8(buffer))
+    let raw = dst_image.into_vec();
+    let buffer_error = || AppError::ImageError("Failed to create image buffer".to_string());
+    Ok(match pixel_type {
+        PixelType::U8 => DynamicImage::ImageLuma8(
+            GrayImage::from_raw(width, height, raw).ok_or_else(buffer_error)?,
+        ),
+        PixelType::U16 => DynamicImage::ImageLuma16(
+            ImageBuffer::from_raw(width, height, bytes_to_u16(&raw)).ok_or_else(buffer_error)?,
+        ),
+        PixelType::U8x4 => DynamicImage::ImageRgba8(
+            RgbaImage::from_raw(width, height, raw).ok_or_else(buffer_error)?,
+        ),
+        PixelType::U16x3 => DynamicImage::ImageRgb16(
+            ImageBuffer::from_raw(width, height, bytes_to_u16(&raw)).ok_or_else(buffer_error)?,
+        ),
+        PixelType::U16x4 => DynamicImage::ImageRgba16(
+            ImageBuffer::from_raw(width, height, bytes_to_u16(&raw)).ok_or_else(buffer_error)?,
+        ),
+        PixelType::F32x3 => DynamicImage::ImageRgb32F(
+            ImageBuffer::from_raw(width, height, bytes_to_f32(&raw)).ok_or_else(buffer_error)?,
+        ),
+        PixelType::F32x4 => DynamicImage::ImageRgba32F(
+            ImageBuffer::from_raw(width, height, bytes_to_f32(&raw)).ok_or_else(buffer_error)?,
+        ),
+        _ => DynamicImage::ImageRgb8(
+            RgbImage::from_raw(width, height, raw).ok_or_else(buffer_error)?,
+        ),
+    })
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct TilingConfig {
-    pub tile_size: u32,
-    pub padding: u32,
-    pub batch_size: usize,
+/// Center-crops an image to `width` x `height` (clamped to the image size).
+pub fn crop_center(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let (w, h) = image.dimensions();
+    let (width, height) = (width.min(w), height.min(h));
+    image.crop_imm((w - width) / 2, (h - height) / 2, width, height)
 }
 
-// Tile metadata to track actual dimensions
-#[derive(Clone, Copy, Debug)]
-struct TileMetadata {
-    x_index: u32,
-    y_index: u32,
-    content_width: u32,  // Actual content width (valid part of the tile)
-    content_height: u32, // Actual content height (valid part of the tile)
+/// Centers an image on a `width` x `height` canvas of the same color type.
+/// The border is black, or fully transparent for images with alpha.
+pub fn pad_center(image: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let (w, h) = image.dimensions();
+    let mut canvas = DynamicImage::new(width.max(w), height.max(h), image.color());
+    let x = (width.saturating_sub(w) / 2) as i64;
+    let y = (height.saturating_sub(h) / 2) as i64;
+    image::imageops::replace(&mut canvas, image, x, y);
+    canvas
 }
 
-pub fn process_tiled<F, I>(
-    image: &DynamicImage,
-    config: TilingConfig,
-    scale: u32,
-    cancel_flag: &Arc<AtomicBool>,
-    mut progress_callback: F,
-    inference_callback: I,
-) -> AppResult<DynamicImage>
-where
-    F: FnMut(f32),
-    I: Fn(Vec<DynamicImage>) -> AppResult<Vec<DynamicImage>>,
-{
+// fast_image_resize works on byte buffers; these reinterpret wider samples in native order.
+fn u16_to_bytes(data: &[u16]) -> Vec<u8> {
+    data.iter().flat_map(|v| v.to_ne_bytes()).collect()
+}
+
+fn bytes_to_u16(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+        .collect()
+}
+
+fn f32_to_bytes(data: &[f32]) -> Vec<u8> {
+    data.iter().flat_map(|v| v.to_ne_bytes()).collect()
+}
+
+fn bytes_to_f32(data: &[u8]) -> Vec<f32> {
+    data.chunks_exact(4)
+        .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+/// How the alpha plane of a transparent input is upscaled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlphaMode {
+    /// High-quality resample of the alpha plane (fast, soft edges).
+    #[default]
+    Resize,
+    /// Run the alpha plane through the model as a grey image (slower, sharper edges).
+    Model,
+}
+
+// How far (in pixels) opaque colors are bled into transparent regions.
+// Only needs to cover the model's receptive field around edges.
+const ALPHA_BLEED_PASSES: usize = 32;
+
+/// Splits an RGBA image into a color image safe to feed to the model, and its alpha plane.
+///
+/// Returns `None` for images without alpha (or fully opaque ones).
+/// Fully transparent pixels often hold black or garbage RGB. If the model sees those,
+/// it smears them across the edge and the recombined result gets a dark halo.
+/// We therefore bleed neighbouring opaque colors outward before upscaling.
+///
+/// 8-bit inputs yield `Rgb8` + `Luma8`; high-depth inputs yield `Rgb32F` + `Luma16`.
+pub fn split_alpha(image: &DynamicImage) -> Option<(DynamicImage, DynamicImage)> {
+    if !image.color().has_alpha() {
+        return None;
+    }
     let (width, height) = image.dimensions();
-    let tile_size = config.tile_size;
-    let batch_size = config.batch_size;
-    let out_width = width * scale;
-    let out_height = height * scale;
 
-    // Memory check
-    let required_memory = (out_width as u64 * out_height as u64 * 3) + (1024 * 1024 * 100);
-    let mut sys = System::new_all();
-    sys.refresh_memory();
-    if sys.available_memory() < required_memory / 1024 {
-        return Err(AppError::Unknown(format!(
-            "Insufficient memory. Need ~{} MB, Available {} MB",
-            required_memory / 1024 / 1024,
-            sys.available_memory() / 1024
-        )));
+    if is_high_depth(image.color()) {
+        let rgba = image.to_rgba32f();
+        if rgba.pixels().all(|p| p[3] >= 1.0) {
+            return None;
+        }
+
+        let alpha: Vec<u16> = rgba
+            .pixels()
+            .map(|p| (p[3].clamp(0.0, 1.0) * 65535.0).round() as u16)
+            .collect();
+        let known = rgba.pixels().map(|p| p[3] > 0.0).collect();
+        let mut rgb: Vec<f32> = rgba.pixels().flat_map(|p| [p[0], p[1], p[2]]).collect();
+        bleed_transparent_colors(&mut rgb, known, width, height, |v| v);
+
+        return Some((
+            DynamicImage::ImageRgb32F(ImageBuffer::from_raw(width, height, rgb)?),
+            DynamicImage::ImageLuma16(ImageBuffer::from_raw(width, height, alpha)?),
+        ));
     }
 
-    let mut output_image = ImageBuffer::<Rgb<u8>, Vec<u8>>::new(out_width, out_height);
-    let tiles_x = (width as f32 / tile_size as f32).ceil() as u32;
-    let tiles_y = (height as f32 / tile_size as f32).ceil() as u32;
-    let total_tiles = tiles_x * tiles_y;
-    let mut processed_tiles = 0;
+    let rgba = image.to_rgba8();
+    if rgba.pixels().all(|p| p[3] == 255) {
+        return None;
+    }
 
-    // Force strict padding to ensure overlap blending works
-    let padding = if total_tiles == 1 {
-        0
+    let alpha = GrayImage::from_raw(width, height, rgba.pixels().map(|p| p[3]).collect())?;
+    let known = rgba.pixels().map(|p| p[3] > 0).collect();
+    let mut rgb: Vec<u8> = rgba.pixels().flat_map(|p| [p[0], p[1], p[2]]).collect();
+    bleed_transparent_colors(&mut rgb, known, width, height, |v| v.round() as u8);
+
+    Some((
+        DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, rgb)?),
+        DynamicImage::ImageLuma8(alpha),
+    ))
+}
+
+/// Re-attaches an upscaled alpha plane to an upscaled color image (straight alpha).
+/// Float color images produce `Rgba32F`, everything else `Rgba8`.
+pub fn merge_alpha(rgb: &DynamicImage, alpha: &DynamicImage) -> AppResult<DynamicImage> {
+    let (width, height) = rgb.dimensions();
+    let alpha = if alpha.dimensions() != (width, height) {
+        resize_image(&DynamicImage::ImageLuma16(alpha.to_luma16()), width, height)?
     } else {
-        config.padding.max(32)
+        alpha.clone()
     };
 
-    std::thread::scope(|s| {
-        // PERFORMANCE FIX: Increased channel size from 2 to 4.
-        // This allows the CPU to prepare up to 4 batches ahead of the GPU.
-        // For a batch size of 4, this means 16 tiles are "in flight", ensuring the GPU never waits.
-        let (tx, rx) = std::sync::mpsc::sync_channel(4);
+    if is_float(rgb.color()) {
+        let rgb = rgb.to_rgb32f();
+        let alpha = alpha.to_luma16();
+        let mut rgba = image::Rgba32FImage::new(width, height);
+        rgba.as_mut()
+            .par_chunks_mut(4)
+            .zip(rgb.as_raw().par_chunks(3))
+            .zip(alpha.as_raw().par_iter())
+            .for_each(|((dst, src), &a)| {
+                dst[..3].copy_from_slice(src);
+                dst[3] = a as f32 / 65535.0;
+            });
+        return Ok(DynamicImage::ImageRgba32F(rgba));
+    }
 
-        // Producer: Extract tiles with metadata
-        s.spawn(move || {
-            let mut tile_batch: Vec<DynamicImage> = Vec::with_capacity(batch_size);
-            let mut meta_batch: Vec<TileMetadata> = Vec::with_capacity(batch_size);
+    let rgb = rgb.to_rgb8();
+    let alpha = alpha.to_luma8();
+    let mut rgba = RgbaImage::new(width, height);
+    rgba.as_mut()
+        .par_chunks_mut(4)
+        .zip(rgb.as_raw().par_chunks(3))
+        .zip(alpha.as_raw().par_iter())
+        .for_each(|((dst, src), &a)| {
+            dst[..3].copy_from_slice(src);
+            dst[3] = a;
+        });
 
-            for y in 0..tiles_y {
-                for x in 0..tiles_x {
-                    if cancel_flag.load(Ordering::Relaxed) {
-                        return;
-                    }
+    Ok(DynamicImage::ImageRgba8(rgba))
+}
 
-                    // Calculate actual content dimensions for this tile location
-                    // This is only used for STITCHING later.
-                    let tile_x_start = x * tile_size;
-                    let tile_y_start = y * tile_size;
-                    let valid_content_w = tile_size.min(width - tile_x_start);
-                    let valid_content_h = tile_size.min(height - tile_y_start);
+// Iteratively replaces the color of transparent pixels with the average of their
+// known 8-neighbours. Anything further than ALPHA_BLEED_PASSES away gets the mean
+// opaque color, which is never visible once alpha is re-applied.
+fn bleed_transparent_colors<T>(
+    rgb: &mut [T],
+    mut known: Vec<bool>,
+    width: u32,
+    height: u32,
+    from_f32: fn(f32) -> T,
+) where
+    T: Copy + Send + Sync + Into<f32>,
+{
+    let (w, h) = (width as i64, height as i64);
+    let mut unknown: Vec<usize> = (0..known.len()).filter(|&i| !known[i]).collect();
 
-                    let metadata = TileMetadata {
-                        x_index: x,
-                        y_index: y,
-                        content_width: valid_content_w,
-                        content_height: valid_content_h,
-                    };
+    for _ in 0..ALPHA_BLEED_PASSES {
+        if unknown.is_empty() {
+            break;
+        }
 
-                    // IMPORTANT: We request a tile of the FULL 'tile_size', even if we are at the edge.
-                    // This ensures every single image sent to the model is exactly the same resolution (tile_size + 2*padding).
-                    // This prevents the inference engine from stretching small edge tiles.
-                    let tile = extract_tile_with_mirroring(
-                        image,
-                        tile_x_start,
-                        tile_y_start,
-                        tile_size, // Request full size
-                        tile_size, // Request full size
-                        padding,
-                    );
-
-                    tile_batch.push(tile);
-                    meta_batch.push(metadata);
-
-                    if tile_batch.len() >= batch_size {
-                        if tx.send((tile_batch.clone(), meta_batch.clone())).is_err() {
-                            return;
+        let updates: Vec<Option<[T; 3]>> = unknown
+            .par_iter()
+            .map(|&idx| {
+                let (x, y) = ((idx as i64) % w, (idx as i64) / w);
+                let mut sum = [0.0f32; 3];
+                let mut count = 0;
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        let (nx, ny) = (x + dx, y + dy);
+                        if nx < 0 || ny < 0 || nx >= w || ny >= h {
+                            continue;
+                        }
+                        let n = (ny * w + nx) as usize;
+                        if known[n] {
+                            for c in 0..3 {
+                                sum[c] += rgb[n * 3 + c].into();
+                            }
+                            count += 1;
                         }
-                        tile_batch.clear();
-                        meta_batch.clear();
                     }
                 }
-            }
+                (count > 0).then(|| sum.map(|v| from_f32(v / count as f32)))
+            })
+            .collect();
 
-            if !tile_batch.is_empty() {
-                let _ = tx.send((tile_batch, meta_batch));
+        // Updates were computed from the previous pass only, so each pass grows
+        // the known region by exactly one pixel.
+        let mut still_unknown = Vec::with_capacity(unknown.len());
+        for (&idx, update) in unknown.iter().zip(&updates) {
+            match update {
+                Some(color) => {
+                    rgb[idx * 3..idx * 3 + 3].copy_from_slice(color);
+                    known[idx] = true;
+                }
+                None => still_unknown.push(idx),
             }
-        });
+        }
 
-        // Consumer: Run inference and stitch
-        while let Ok((batch, metadata_batch)) = rx.recv() {
-            if cancel_flag.load(Ordering::Relaxed) {
-                return Err(AppError::Unknown("Operation cancelled".to_string()));
-            }
+        if still_unknown.len() == unknown.len() {
+            break; // Nothing opaque to bleed from
+        }
+        unknown = still_unknown;
+    }
 
-            let upscaled_tiles = inference_callback(batch)?;
+    if !unknown.is_empty() {
+        let (sum, count) = known.iter().enumerate().filter(|(_, &k)| k).fold(
+            ([0f64; 3], 0u64),
+            |(mut sum, count), (i, _)| {
+                for c in 0..3 {
+                    sum[c] += Into::<f32>::into(rgb[i * 3 + c]) as f64;
+                }
+                (sum, count + 1)
+            },
+        );
+        let fill = sum.map(|v| {
+            from_f32(if count > 0 {
+                (v / count as f64) as f32
+            } else {
+                0.0
+            })
+        });
+        for idx in unknown {
+            rgb[idx * 3..idx * 3 + 3].copy_from_slice(&fill);
+        }
+    }
+}
 
-            if upscaled_tiles.len() != metadata_batch.len() {
-                return Err(AppError::Unknown("Batch size mismatch".to_string()));
-            }
+/// How overlapping tile regions are cross-faded when stitching.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlendMode {
+    /// Hard seams: each tile only contributes its own content area (fastest).
+    None,
+    /// Linear ramp across the overlap band.
+    Linear,
+    /// Raised-cosine ramp across the overlap band (smoothest transition).
+    #[default]
+    Cosine,
+}
 
-            for (tile, meta) in upscaled_tiles.iter().zip(metadata_batch.iter()) {
-                stitch_tile(&mut output_image, tile, meta, scale, padding, tile_size)?;
+impl BlendMode {
+    /// Weight for a normalized position `t` (0..1) across the ramp.
+    /// Always satisfies `weight(t) + weight(1 - t) == 1`, so neighbouring tiles sum to one.
+    pub fn weight(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            BlendMode::None => {
+                if t >= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
             }
-
-            processed_tiles += metadata_batch.len();
-            progress_callback(processed_tiles as f32 / total_tiles as f32);
+            BlendMode::Linear => t,
+            BlendMode::Cosine => 0.5 - 0.5 * (std::f32::consts::PI * t).cos(),
         }
-        Ok(())
-    })?;
+    }
+}
 
-    Ok(DynamicImage::ImageRgb8(output_image))
+#[derive(Clone, Copy, Debug)]
+pub struct TilingConfig {
+    pub tile_size: u32,
+    pub padding: u32,
+    pub batch_size: usize,
+    /// Source pixels each tile extends into its neighbours for cross-fading.
+    /// Must not exceed `padding` (the model already sees that context).
+    pub overlap: u32,
+    pub blend_mode: BlendMode,
+    /// Static model input (width, height), padding included. A fixed axis
+    /// overrides `tile_size` for that axis.
+    pub input_size: (Option<u32>, Option<u32>),
+    /// Static batch dimension. Replaces `batch_size`; short batches are padded up to it.
+    pub static_batch: Option<usize>,
+    /// Dynamic axes are padded (right/bottom) to a multiple of this and cropped back.
+    pub alignment: u32,
+    /// Batches run through the inference callback concurrently (session pools).
+    /// Results are still stitched in order.
+    pub workers: usize,
 }
 
-// Helper to mirror coordinates (Reflect mode)
-// Handles coordinates outside the image bounds by reflecting them back in.
-fn mirror_coordinate(coord: i64, max: i64) -> u32 {
-    let mut c = coord;
-    // Reflect lower bound (negative coordinates)
-    while c < 0 {
-        c = -c;
+impl TilingConfig {
+    // Content size of each tile at the given padding. Fixed axes leave room
+    // for the padding on both sides, dynamic axes use `tile_size`.
+    fn tile_dims(&self, padding: u32) -> AppResult<(u32, u32)> {
+        let axis = |fixed: Option<u32>| match fixed {
+            Some(size) if size <= 2 * padding => Err(AppError::Unknown(format!(
+                "Model input size {} is too small for padding {}",
+                size, padding
+            ))),
+            Some(size) => Ok(size - 2 * padding),
+            None => Ok(self.tile_size),
+        };
+        Ok((axis(self.input_size.0)?, axis(self.input_size.1)?))
     }
-    // Reflect upper bound (past image width/height)
-    // Uses ping-pong style reflection: 0 1 2 1 0
-    while c >= max {
-        c = 2 * (max - 1) - c;
+
+    // Extra pixels that bring a dynamic input side up to the alignment multiple
+    fn alignment_pad(&self, side: u32, fixed: Option<u32>) -> u32 {
+        if fixed.is_some() || self.alignment <= 1 {
+            0
+        } else {
+            side.div_ceil(self.alignment) * self.alignment - side
+        }
     }
-    // Final clamp just to be safe against edge cases like max=1
-    c.clamp(0, max - 1) as u32
 }
 
-// Extract tile with proper padding using Mirroring
-// Now accepts target_w/h to ensure output is always standard size
-fn extract_tile_with_mirroring(
-    image: &DynamicImage,
-    x_start: u32,
-    y_start: u32,
-    target_w: u32,
-    target_h: u32,
-    padding: u32,
-) -> DynamicImage {
-    let (img_w, img_h) = image.dimensions();
-
-    // The physical size of the tile image to be created
-    let total_w = target_w + 2 * padding;
-    let total_h = target_h + 2 * padding;
+/// Pixels a tiled pass reads from: a decoded image, or the disk canvas an
+/// earlier pass of an out-of-core job wrote (`Rgb8` or `Rgb32F`).
+///
+/// An alpha plane (the one `split_alpha` returns, or the one attached to the
+/// canvas) is tiled as a fourth plane, for models that upscale it themselves.
+#[derive(Clone, Copy)]
+pub enum TileInput<'a> {
+    Image(&'a DynamicImage),
+    ImageAlpha(&'a DynamicImage, &'a DynamicImage),
+    Disk(&'a DiskImage),
+}
 
-    let mut tile_buffer = ImageBuffer::<Rgb<u8>, Vec<u8>>::new(total_w, total_h);
-    let rgb_image = image.to_rgb8();
+impl TileInput<'_> {
+    pub fn dimensions(&self) -> (u32, u32) {
+        match self {
+            TileInput::Image(image) | TileInput::ImageAlpha(image, _) => image.dimensions(),
+            TileInput::Disk(canvas) => canvas.dimensions(),
+        }
+    }
 
-    for tile_y in 0..total_h {
-        for tile_x in 0..total_w {
-            // Calculate the coordinate relative to the image source.
-            // (x_start - padding) moves us to the top-left of the padded area.
-            // + tile_x moves us through the pixels of the new tile.
-            let src_x_ideal = (x_start as i64) + (tile_x as i64) - (padding as i64);
-            let src_y_ideal = (y_start as i64) + (tile_y as i64) - (padding as i64);
+    pub fn has_alpha(&self) -> bool {
+        match self {
+            TileInput::Image(_) => false,
+            TileInput::ImageAlpha(..) => true,
+            TileInput::Disk(canvas) => canvas.alpha.is_some(),
+        }
+    }
+}
 
-            // Use mirror_coordinate to handle ANY out of bounds access.
-            // This handles:
-            // 1. Left/Top padding (negative coords)
-            // 2. Right/Bottom padding (coords > img_w)
-            // 3. "Filler" content if the tile is larger than the remaining image.
-            let src_x = mirror_coordinate(src_x_ideal, img_w as i64);
-            let src_y = mirror_coordinate(src_y_ideal, img_h as i64);
+impl<'a> From<&'a DynamicImage> for TileInput<'a> {
+    fn from(image: &'a DynamicImage) -> Self {
+        TileInput::Image(image)
+    }
+}
 
-            // Safe get_pixel because mirror_coordinate guarantees bounds
-            let pixel = rgb_image.get_pixel(src_x, src_y);
-            tile_buffer.put_pixel(tile_x, tile_y, *pixel);
-        }
+impl<'a> From<&'a DiskImage> for TileInput<'a> {
+    fn from(canvas: &'a DiskImage) -> Self {
+        TileInput::Disk(canvas)
     }
+}
 
-    DynamicImage::ImageRgb8(tile_buffer)
+/// Tiles in the planar NCHW layout models consume: R, G and B planes per tile,
+/// values nominally 0..1 (float, so HDR highlights survive). Transparent inputs
+/// for 4-channel models carry their alpha as a fourth plane.
+///
+/// This is what flows between the tiler and the inference callback. Tiles are
+/// filled straight from the source rows and outputs are stitched straight from
+/// the planes, so no per-tile image buffers are built on either side.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TileBatch {
+    pub data: Vec<f32>,
+    pub len: usize,
+    /// Planes per tile: 3 (RGB) or 4 (RGBA).
+    pub channels: usize,
+    pub width: u32,
+    pub height: u32,
 }
 
-// Stitch upscaled tile into output
-fn stitch_tile(
-    output: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-    upscaled_tile: &DynamicImage,
-    meta: &TileMetadata,
-    scale: u32,
-    padding: u32,
-    tile_size: u32,
-) -> AppResult<()> {
-    // We only want to paste the VALID part of the tile.
-    // The tile might contain mirrored junk at the right/bottom edges if it was an edge tile.
+impl TileBatch {
+    /// Wraps model output. `shape` must be [N, 3, H, W] or [N, 4, H, W] and match `data`.
+    pub fn from_nchw(shape: &[i64], data: Vec<f32>) -> AppResult<Self> {
+        let &[n, c, h, w] = shape else {
+            return Err(AppError::Unknown(format!(
+                "Unexpected output tensor rank: {}",
+                shape.len()
+            )));
+        };
+        let batch = Self {
+            data,
+            len: n as usize,
+            channels: c as usize,
+            width: w as u32,
+            height: h as u32,
+        };
+        if c != 3 && c != 4 {
+            return Err(AppError::InferenceError(format!(
+                "Expected 3 or 4 output channels, model returned {}",
+                c
+            )));
+        }
+        if batch.data.len() != batch.len * batch.tile_len() {
+            return Err(AppError::Unknown(format!(
+                "Output tensor {:?} does not match {} values",
+                shape,
+                batch.data.len()
+            )));
+        }
+        Ok(batch)
+    }
 
-    // 1. Skip the padding area
-    let crop_x = padding * scale;
-    let crop_y = padding * scale;
+    pub fn shape(&self) -> Vec<i64> {
+        vec![
+            self.len as i64,
+            self.channels as i64,
+            self.height as i64,
+            self.width as i64,
+        ]
+    }
 
-    // 2. Only take the valid content width/height
-    let crop_w = meta.content_width * scale;
-    let crop_h = meta.content_height * scale;
+    /// Values per tile (all planes).
+    pub fn tile_len(&self) -> usize {
+        self.width as usize * self.height as usize * self.channels
+    }
 
-    let cropped = upscaled_tile
-        .crop_imm(crop_x, crop_y, crop_w, crop_h)
-        .to_rgb8();
+    pub fn tile(&self, index: usize) -> &[f32] {
+        let n = self.tile_len();
+        &self.data[index * n..(index + 1) * n]
+    }
+}
 
-    // Stitch into output at correct position
-    let out_x = meta.x_index * tile_size * scale;
-    let out_y = meta.y_index * tile_size * scale;
+// Borrowed view of one planar tile
+#[derive(Clone, Copy)]
+struct TilePlanes<'a> {
+    data: &'a [f32],
+    channels: usize,
+    width: u32,
+    height: u32,
+}
 
-    // Fast row-based copy
-    let dst_width = output.width();
+impl TilePlanes<'_> {
+    // Row `y` of plane `c`, starting at column `x`, `len` values long
+    fn plane_row(&self, c: usize, x: u32, y: u32, len: usize) -> &[f32] {
+        let plane = self.width as usize * self.height as usize;
+        let start = c * plane + y as usize * self.width as usize + x as usize;
+        &self.data[start..start + len]
+    }
 
-    // Safety check: ensure we don't write past output bounds
-    // (though logic should prevent this, robust code checks)
-    let rows_to_copy = crop_h.min(output.height().saturating_sub(out_y));
-    let cols_to_copy = crop_w.min(dst_width.saturating_sub(out_x));
+    // Row `y` of each color plane
+    fn rows(&self, x: u32, y: u32, len: usize) -> [&[f32]; 3] {
+        [0, 1, 2].map(|c| self.plane_row(c, x, y, len))
+    }
 
-    for y in 0..rows_to_copy {
-        let src_row = &cropped.as_raw()
-            [(y * crop_w * 3) as usize..((y * crop_w + cols_to_copy) * 3) as usize];
-        let dst_idx = ((out_y + y) * dst_width * 3 + out_x * 3) as usize;
-        let dst_row = &mut output.as_mut()[dst_idx..dst_idx + (cols_to_copy * 3) as usize];
-        dst_row.copy_from_slice(src_row);
+    // Row `y` of the alpha plane; RGB tiles are opaque
+    fn alpha_row(&self, x: u32, y: u32, len: usize) -> Option<&[f32]> {
+        (self.channels == 4).then(|| self.plane_row(3, x, y, len))
     }
+}
 
-    Ok(())
+// Tile metadata to track actual dimensions
+#[derive(Clone, Copy, Debug)]
+struct TileMetadata {
+    x_index: u32,
+    y_index: u32,
+    content_width: u32,  // Actual content width (valid part of the tile)
+    content_height: u32, // Actual content height (valid part of the tile)
+}
+
+pub fn process_tiled<F, I>(
+    image: &DynamicImage,
+    config: TilingConfig,
+    scale: u32,
+    cancel_flag: &Arc<AtomicBool>,
+    progress_callback: F,
+    inference_callback: I,
+) -> AppResult<DynamicImage>
+where
+    F: FnMut(f32),
+    I: Fn(TileBatch) -> AppResult<TileBatch> + Sync,
+{
+    let (color, _) = tile_in_memory(
+        TileInput::Image(image),
+        config,
+        scale,
+        cancel_flag,
+        progress_callback,
+        inference_callback,
+    )?;
+    Ok(color)
+}
+
+/// `process_tiled` for 4-channel models: the `split_alpha` plane of the image is
+/// tiled as the fourth plane, and the fourth plane the model returns becomes the
+/// upscaled alpha (`Luma8`, or `Luma16` next to float color).
+pub fn process_tiled_with_alpha<F, I>(
+    image: &DynamicImage,
+    alpha: &DynamicImage,
+    config: TilingConfig,
+    scale: u32,
+    cancel_flag: &Arc<AtomicBool>,
+    progress_callback: F,
+    inference_callback: I,
+) -> AppResult<(DynamicImage, DynamicImage)>
+where
+    F: FnMut(f32),
+    I: Fn(TileBatch) -> AppResult<TileBatch> + Sync,
+{
+    let (color, alpha) = tile_in_memory(
+        TileInput::ImageAlpha(image, alpha),
+        config,
+        scale,
+        cancel_flag,
+        progress_callback,
+        inference_callback,
+    )?;
+    let alpha = alpha.ok_or_else(|| AppError::ImageError("Alpha plane was not tiled".into()))?;
+    Ok((color, alpha))
+}
+
+// In-memory stitching shared by `process_tiled` and `process_tiled_with_alpha`
+fn tile_in_memory<F, I>(
+    input: TileInput,
+    config: TilingConfig,
+    scale: u32,
+    cancel_flag: &Arc<AtomicBool>,
+    progress_callback: F,
+    inference_callback: I,
+) -> AppResult<(DynamicImage, Option<DynamicImage>)>
+where
+    F: FnMut(f32),
+    I: Fn(TileBatch) -> AppResult<TileBatch> + Sync,
+{
+    let (width, height) = input.dimensions();
+    let out_width = width * scale;
+    let out_height = height * scale;
+
+    // Float inputs (high-precision mode) are stitched into a float canvas
+    let high_precision = matches!(
+        input,
+        TileInput::Image(DynamicImage::ImageRgb32F(_))
+            | TileInput::ImageAlpha(DynamicImage::ImageRgb32F(_), _)
+    );
+
+    // Memory check
+    check_memory(out_width, out_height, high_precision, input.has_alpha())?;
+
+    let mut output_image =
+        TileCanvas::new(out_width, out_height, high_precision, input.has_alpha());
+    run_tiles(
+        input,
+        config,
+        scale,
+        cancel_flag,
+        progress_callback,
+        inference_callback,
+        &mut output_image,
+    )?;
+
+    Ok(output_image.into_images())
+}
+
+/// Out-of-core variant of `process_tiled`: finished pixels go straight into a
+/// disk-backed canvas, so peak RAM scales with the tile-row height rather than
+/// the output size. The canvas must be `width * scale` by `height * scale`.
+///
+/// The input can itself be a disk canvas (the output of an earlier pass).
+/// A transparent input (4-channel models) needs an alpha canvas attached to
+/// `canvas`, which receives the model's fourth plane.
+pub fn process_tiled_to_disk<'a, F, I>(
+    input: impl Into<TileInput<'a>>,
+    config: TilingConfig,
+    scale: u32,
+    cancel_flag: &Arc<AtomicBool>,
+    progress_callback: F,
+    inference_callback: I,
+    canvas: &mut DiskImage,
+) -> AppResult<()>
+where
+    F: FnMut(f32),
+    I: Fn(TileBatch) -> AppResult<TileBatch> + Sync,
+{
+    let input = input.into();
+    let (width, height) = input.dimensions();
+    if !is_tile_target(canvas.color) {
+        return Err(AppError::ImageError(format!(
+            "Cannot stitch tiles into a {:?} disk canvas",
+            canvas.color
+        )));
+    }
+    if canvas.dimensions() != (width * scale, height * scale) {
+        return Err(AppError::ImageError(format!(
+            "Disk canvas is {}x{}, expected {}x{}",
+            canvas.width,
+            canvas.height,
+            width * scale,
+            height * scale
+        )));
+    }
+    if input.has_alpha() != canvas.alpha.is_some() {
+        return Err(AppError::ImageError(
+            "Tiled alpha needs an alpha canvas to stitch into, and only then one".to_string(),
+        ));
+    }
+
+    run_tiles(
+        input,
+        config,
+        scale,
+        cancel_flag,
+        progress_callback,
+        inference_callback,
+        canvas,
+    )?;
+
+    canvas.flush()?;
+    match canvas.alpha() {
+        Some(alpha) => alpha.flush(),
+        None => Ok(()),
+    }
+}
+
+/// Bytes needed to hold an output canvas of this size in RAM, plus some headroom.
+/// High precision canvases hold f32 channels, and a transparent output carries
+/// its alpha as a fourth channel.
+pub fn canvas_memory(width: u32, height: u32, high_precision: bool, alpha: bool) -> u64 {
+    let channels = if alpha { 4 } else { 3 };
+    let bytes_per_channel = if high_precision {
+        std::mem::size_of::<f32>() as u64
+    } else {
+        1
+    };
+    width as u64 * height as u64 * channels * bytes_per_channel + 1024 * 1024 * 100
+}
+
+/// Fails with "Insufficient memory" if an output canvas of this size would not
+/// fit in available RAM.
+pub fn check_memory(width: u32, height: u32, high_precision: bool, alpha: bool) -> AppResult<()> {
+    let mut sys = System::new();
+    sys.refresh_memory();
+    check_memory_against(sys.available_memory(), width, height, high_precision, alpha)
+}
+
+/// `check_memory` against `available` bytes instead of the RAM available now.
+pub fn check_memory_against(
+    available: u64,
+    width: u32,
+    height: u32,
+    high_precision: bool,
+    alpha: bool,
+) -> AppResult<()> {
+    let required_memory = canvas_memory(width, height, high_precision, alpha);
+    if available < required_memory {
+        return Err(AppError::Unknown(format!(
+            "Insufficient memory. Need ~{} MB, Available {} MB",
+            required_memory / 1024 / 1024,
+            available / 1024 / 1024
+        )));
+    }
+    Ok(())
+}
+
+/// True if an in-memory output canvas of this size fits in available RAM.
+/// Used to decide between `process_tiled` and `process_tiled_to_disk`.
+pub fn fits_in_memory(width: u32, height: u32, high_precision: bool, alpha: bool) -> bool {
+    check_memory(width, height, high_precision, alpha).is_ok()
+}
+
+// Tile producer/consumer shared by the in-memory and disk-backed paths
+fn run_tiles<F, I>(
+    input: TileInput,
+    config: TilingConfig,
+    scale: u32,
+    cancel_flag: &Arc<AtomicBool>,
+    mut progress_callback: F,
+    inference_callback: I,
+    output_image: &mut dyn CanvasTarget,
+) -> AppResult<()>
+where
+    F: FnMut(f32),
+    I: Fn(TileBatch) -> AppResult<TileBatch> + Sync,
+{
+    let (width, height) = input.dimensions();
+    let batch_size = config.static_batch.unwrap_or(config.batch_size).max(1);
+    let out_height = height * scale;
+
+    let multi_padding = config.padding.max(32).max(config.overlap);
+    let (mut tile_w, mut tile_h) = config.tile_dims(multi_padding)?;
+    let tiles_x = width.div_ceil(tile_w);
+    let tiles_y = height.div_ceil(tile_h);
+    let total_tiles = tiles_x * tiles_y;
+    let mut processed_tiles = 0;
+
+    // Force strict padding so every tile carries enough context for its overlap band.
+    // A single tile needs none, and a fixed-size model then gets its full input as content.
+    let padding = if total_tiles == 1 {
+        (tile_w, tile_h) = config.tile_dims(0)?;
+        0
+    } else {
+        multi_padding
+    };
+
+    // Overlap can never exceed half a tile, otherwise opposite ramps would cross.
+    let overlap = if total_tiles == 1 || config.blend_mode == BlendMode::None {
+        0
+    } else {
+        config.overlap.min(tile_w.min(tile_h) / 2)
+    };
+
+    // Alignment filler, extracted past the content on the right/bottom and never stitched
+    let align_w = config.alignment_pad(tile_w + 2 * padding, config.input_size.0);
+    let align_h = config.alignment_pad(tile_h + 2 * padding, config.input_size.1);
+
+    // Planes per tile: RGB, plus the alpha plane when it is tiled too
+    let channels = if input.has_alpha() { 4 } else { 3 };
+
+    // Rolling float strip for cross-fading. Only allocated when blending is active.
+    let mut blend_canvas = if overlap > 0 {
+        Some(BlendCanvas::new(
+            (width, height),
+            scale,
+            (tile_w, tile_h),
+            padding,
+            overlap,
+            config.blend_mode,
+            channels,
+        ))
+    } else {
+        None
+    };
+
+    // Tiles are read straight from the source rows. Other layouts are converted once
+    // here rather than per tile.
+    let (converted, converted_alpha);
+    let source = match input {
+        TileInput::Image(image) | TileInput::ImageAlpha(image, _) => {
+            let alpha = match input {
+                TileInput::ImageAlpha(_, plane) if plane.dimensions() != (width, height) => {
+                    return Err(AppError::ImageError(format!(
+                        "Alpha plane is {}x{}, expected {}x{}",
+                        plane.width(),
+                        plane.height(),
+                        width,
+                        height
+                    )));
+                }
+                TileInput::ImageAlpha(_, DynamicImage::ImageLuma8(buf)) => {
+                    Some(AlphaSource::Standard(buf.as_raw()))
+                }
+                TileInput::ImageAlpha(_, DynamicImage::ImageLuma16(buf)) => {
+                    Some(AlphaSource::Wide(buf.as_raw()))
+                }
+                TileInput::ImageAlpha(_, plane) => {
+                    converted_alpha = plane.to_luma16();
+                    Some(AlphaSource::Wide(converted_alpha.as_raw()))
+                }
+                _ => None,
+            };
+            match image {
+                DynamicImage::ImageRgb8(buf) => TileSource::Standard {
+                    data: buf.as_raw(),
+                    alpha,
+                    width,
+                    height,
+                },
+                DynamicImage::ImageRgb32F(buf) => TileSource::High {
+                    data: buf.as_raw(),
+                    alpha,
+                    width,
+                    height,
+                },
+                image => {
+                    converted = image.to_rgb8();
+                    TileSource::Standard {
+                        data: converted.as_raw(),
+                        alpha,
+                        width,
+                        height,
+                    }
+                }
+            }
+        }
+        TileInput::Disk(canvas) => canvas.tile_source()?,
+    };
+    let source = &source;
+
+    // Model input size. Every tile has exactly this size, edge tiles included
+    // (filled by mirroring), so the model never sees a stretched or odd-sized tile.
+    let input_w = tile_w + align_w + 2 * padding;
+    let input_h = tile_h + align_h + 2 * padding;
+    let input_len = input_w as usize * input_h as usize * channels;
+
+    let workers = config.workers.max(1);
+
+    std::thread::scope(|s| {
+        // PERFORMANCE FIX: Increased channel size from 2 to 4.
+        // This allows the CPU to prepare up to 4 batches ahead of the GPU.
+        // For a batch size of 4, this means 16 tiles are "in flight", ensuring the GPU never waits.
+        // With several workers, keep two batches queued per worker.
+        let (tx, rx) = std::sync::mpsc::sync_channel(4.max(2 * workers));
+        // Stitched output buffers go back to the producer as the next input buffers
+        let (recycle_tx, recycle_rx) = std::sync::mpsc::channel::<Vec<f32>>();
+
+        // Producer: Fill planar batches with metadata
+        s.spawn(move || {
+            let mut meta_batch: Vec<TileMetadata> = Vec::with_capacity(batch_size);
+            let mut sequence = 0usize;
+
+            let mut send_batch = |meta_batch: &mut Vec<TileMetadata>| {
+                // A static batch dimension must always be filled: repeat the last tile
+                let len = config
+                    .static_batch
+                    .unwrap_or(meta_batch.len())
+                    .max(meta_batch.len());
+                let mut data = recycle_rx.try_recv().unwrap_or_default();
+                data.clear();
+                data.resize(len * input_len, 0.0);
+
+                data.par_chunks_mut(input_len)
+                    .enumerate()
+                    .for_each(|(i, dest)| {
+                        let meta = &meta_batch[i.min(meta_batch.len() - 1)];
+                        let region = (
+                            meta.x_index * tile_w,
+                            meta.y_index * tile_h,
+                            tile_w + align_w,
+                            tile_h + align_h,
+                        );
+                        source.fill_planes(region, padding, dest);
+                    });
+
+                let batch = TileBatch {
+                    data,
+                    len,
+                    channels,
+                    width: input_w,
+                    height: input_h,
+                };
+                sequence += 1;
+                tx.send((sequence - 1, batch, std::mem::take(meta_batch)))
+                    .is_ok()
+            };
+
+            for y in 0..tiles_y {
+                for x in 0..tiles_x {
+                    if cancel_flag.load(Ordering::Relaxed) {
+                        return;
+                    }
+
+                    // Calculate actual content dimensions for this tile location
+                    // This is only used for STITCHING later.
+                    let tile_x_start = x * tile_w;
+                    let tile_y_start = y * tile_h;
+                    meta_batch.push(TileMetadata {
+                        x_index: x,
+                        y_index: y,
+                        content_width: tile_w.min(width - tile_x_start),
+                        content_height: tile_h.min(height - tile_y_start),
+                    });
+
+                    if meta_batch.len() >= batch_size && !send_batch(&mut meta_batch) {
+                        return;
+                    }
+                }
+            }
+
+            if !meta_batch.is_empty() {
+                send_batch(&mut meta_batch);
+            }
+        });
+
+        // Workers: Run inference. Each owns a handle on the queue, so the producer
+        // stops as soon as the last one exits (done, failed or cancelled).
+        let rx = Arc::new(std::sync::Mutex::new(rx));
+        let (result_tx, result_rx) = std::sync::mpsc::channel();
+        for _ in 0..workers {
+            let rx = rx.clone();
+            let result_tx = result_tx.clone();
+            let inference_callback = &inference_callback;
+            s.spawn(move || {
+                while let Ok((sequence, batch, metadata_batch)) = rx
+                    .lock()
+                    .map_err(|_| ())
+                    .and_then(|rx| rx.recv().map_err(|_| ()))
+                {
+                    if cancel_flag.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let result = inference_callback(batch);
+                    let failed = result.is_err();
+                    if result_tx.send((sequence, result, metadata_batch)).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+        }
+        drop((rx, result_tx));
+
+        // Consumer: Stitch results in batch order (the blend strip flushes rows top-down)
+        let mut pending = std::collections::BTreeMap::new();
+        let mut next_sequence = 0;
+        while let Ok((sequence, result, metadata_batch)) = result_rx.recv() {
+            if cancel_flag.load(Ordering::Relaxed) {
+                return Err(AppError::Cancelled);
+            }
+            pending.insert(sequence, (result?, metadata_batch));
+
+            while let Some((upscaled, metadata_batch)) = pending.remove(&next_sequence) {
+                next_sequence += 1;
+                if upscaled.len < metadata_batch.len() {
+                    return Err(AppError::Unknown("Batch size mismatch".to_string()));
+                }
+                // The alpha of a transparent input has to come back from the model
+                if upscaled.channels < channels {
+                    return Err(AppError::InferenceError(format!(
+                        "Model returned {} planes for {}-plane tiles",
+                        upscaled.channels, channels
+                    )));
+                }
+
+                for (i, meta) in metadata_batch.iter().enumerate() {
+                    let tile = TilePlanes {
+                        data: upscaled.tile(i),
+                        channels: upscaled.channels,
+                        width: upscaled.width,
+                        height: upscaled.height,
+                    };
+                    match blend_canvas.as_mut() {
+                        Some(canvas) => canvas.accumulate(output_image, tile, meta)?,
+                        None => {
+                            stitch_tile(output_image, tile, meta, scale, padding, (tile_w, tile_h))?
+                        }
+                    }
+                }
+
+                let _ = recycle_tx.send(upscaled.data);
+                processed_tiles += metadata_batch.len();
+                progress_callback(processed_tiles as f32 / total_tiles as f32);
+            }
+        }
+
+        // Workers also stop on cancellation without sending anything
+        if cancel_flag.load(Ordering::Relaxed) {
+            return Err(AppError::Cancelled);
+        }
+        Ok(())
+    })?;
+
+    // Flush whatever is still held in the blend strip (the last row of tiles)
+    if let Some(mut canvas) = blend_canvas {
+        canvas.flush_rows(output_image, out_height);
+    }
+
+    Ok(())
+}
+
+// Stitching target. 8-bit for the standard path, float when the input is `Rgb32F`
+// (high-precision mode) so nothing is quantized before the final encode. Tiled
+// alpha lands in a `Luma8`/`Luma16` plane, the layouts `split_alpha` produces.
+enum TileCanvas {
+    Standard(RgbImage, Option<GrayImage>),
+    High(Rgb32FImage, Option<ImageBuffer<Luma<u16>, Vec<u16>>>),
+}
+
+impl TileCanvas {
+    fn new(width: u32, height: u32, high_precision: bool, alpha: bool) -> Self {
+        if high_precision {
+            TileCanvas::High(
+                Rgb32FImage::new(width, height),
+                alpha.then(|| ImageBuffer::new(width, height)),
+            )
+        } else {
+            TileCanvas::Standard(
+                RgbImage::new(width, height),
+                alpha.then(|| GrayImage::new(width, height)),
+            )
+        }
+    }
+
+    fn into_images(self) -> (DynamicImage, Option<DynamicImage>) {
+        match self {
+            TileCanvas::Standard(buf, alpha) => (
+                DynamicImage::ImageRgb8(buf),
+                alpha.map(DynamicImage::ImageLuma8),
+            ),
+            TileCanvas::High(buf, alpha) => (
+                DynamicImage::ImageRgb32F(buf),
+                alpha.map(DynamicImage::ImageLuma16),
+            ),
+        }
+    }
+}
+
+// Destination for finished output pixels: an in-memory `TileCanvas` or a `DiskImage`.
+// Targets with an alpha plane take it from the fourth value or plane.
+trait CanvasTarget {
+    // Writes blend strip rows starting at output row `y`, normalized by their weights.
+    // `color` holds `stride` values per pixel: RGB, or RGBA when alpha is tiled.
+    fn put_rows(&mut self, y: u32, color: &[f32], stride: usize, weight: &[f32]);
+
+    // Copies `size` pixels from `src` in a planar tile to (x, y) in the canvas.
+    fn put_planes(&mut self, tile: TilePlanes, src: (u32, u32), size: (u32, u32), x: u32, y: u32);
+}
+
+impl CanvasTarget for TileCanvas {
+    fn put_rows(&mut self, y: u32, color: &[f32], stride: usize, weight: &[f32]) {
+        // Three values in, every pixel's chunk starts at its alpha
+        let alpha_values = color.get(3..).filter(|_| stride == 4);
+        match self {
+            TileCanvas::Standard(out, alpha) => {
+                let start = y as usize * out.width() as usize;
+                let dst = &mut out.as_mut()[start * 3..(start + weight.len()) * 3];
+                normalize_into(dst, 3, color, stride, weight, to_u8);
+                if let (Some(alpha), Some(values)) = (alpha, alpha_values) {
+                    let dst = &mut alpha.as_mut()[start..start + weight.len()];
+                    normalize_into(dst, 1, values, stride, weight, to_u8);
+                }
+            }
+            TileCanvas::High(out, alpha) => {
+                let start = y as usize * out.width() as usize;
+                let dst = &mut out.as_mut()[start * 3..(start + weight.len()) * 3];
+                normalize_into(dst, 3, color, stride, weight, |v| v);
+                if let (Some(alpha), Some(values)) = (alpha, alpha_values) {
+                    let dst = &mut alpha.as_mut()[start..start + weight.len()];
+                    normalize_into(dst, 1, values, stride, weight, to_u16);
+                }
+            }
+        }
+    }
+
+    fn put_planes(&mut self, tile: TilePlanes, src: (u32, u32), size: (u32, u32), x: u32, y: u32) {
+        match self {
+            TileCanvas::Standard(out, alpha) => {
+                interleave_into(out, tile, src, size, (x, y), to_u8);
+                if let Some(alpha) = alpha {
+                    alpha_into(alpha, tile, src, size, (x, y), to_u8);
+                }
+            }
+            TileCanvas::High(out, alpha) => {
+                interleave_into(out, tile, src, size, (x, y), |v| v);
+                if let Some(alpha) = alpha {
+                    alpha_into(alpha, tile, src, size, (x, y), to_u16);
+                }
+            }
+        }
+    }
+}
+
+fn to_u8(v: f32) -> u8 {
+    (v * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+fn to_u16(v: f32) -> u16 {
+    (v * 65535.0).round().clamp(0.0, 65535.0) as u16
+}
+
+// Planar tile region -> interleaved RGB buffer, clipped to the buffer bounds
+fn interleave_into<P: Pixel>(
+    dst: &mut ImageBuffer<P, Vec<P::Subpixel>>,
+    tile: TilePlanes,
+    (src_x, src_y): (u32, u32),
+    (w, h): (u32, u32),
+    (out_x, out_y): (u32, u32),
+    convert: fn(f32) -> P::Subpixel,
+) {
+    let dst_width = dst.width() as usize;
+    let rows = h.min(dst.height().saturating_sub(out_y));
+    let cols = w.min(dst.width().saturating_sub(out_x)) as usize;
+
+    for v in 0..rows {
+        let [r, g, b] = tile.rows(src_x, src_y + v, cols);
+        let start = ((out_y + v) as usize * dst_width + out_x as usize) * 3;
+        let dst_row = &mut dst.as_mut()[start..start + cols * 3];
+        for (i, pixel) in dst_row.chunks_exact_mut(3).enumerate() {
+            pixel[0] = convert(r[i]);
+            pixel[1] = convert(g[i]);
+            pixel[2] = convert(b[i]);
+        }
+    }
+}
+
+// Alpha plane of a tile region -> grey buffer, like `interleave_into`
+fn alpha_into<T: image::Primitive>(
+    dst: &mut ImageBuffer<Luma<T>, Vec<T>>,
+    tile: TilePlanes,
+    (src_x, src_y): (u32, u32),
+    (w, h): (u32, u32),
+    (out_x, out_y): (u32, u32),
+    convert: fn(f32) -> T,
+) {
+    let dst_width = dst.width() as usize;
+    let rows = h.min(dst.height().saturating_sub(out_y));
+    let cols = w.min(dst.width().saturating_sub(out_x)) as usize;
+
+    for v in 0..rows {
+        let Some(a) = tile.alpha_row(src_x, src_y + v, cols) else {
+            return;
+        };
+        let start = (out_y + v) as usize * dst_width + out_x as usize;
+        for (dst, &value) in dst.as_mut()[start..start + cols].iter_mut().zip(a) {
+            *dst = convert(value);
+        }
+    }
+}
+
+/// Output canvas backed by a memory-mapped scratch file, for images too large for RAM.
+///
+/// Pixels are tightly packed rows of `Rgb8`, `Rgb16` or `Rgb32F` (native endian),
+/// or `L8`/`L16` for grayscale output, which keeps the first of the tiler's planes.
+/// Transparent outputs carry their alpha plane as a second `L8`/`L16` canvas that
+/// is interleaved with the color rows as they are encoded. When it is attached
+/// before tiling, it takes the tiler's alpha plane (4-channel models).
+/// The OS pages finished rows out to disk; the file is deleted when dropped.
+pub struct DiskImage {
+    width: u32,
+    height: u32,
+    color: image::ColorType,
+    path: PathBuf,
+    mmap: Option<memmap2::MmapMut>,
+    alpha: Option<Box<DiskImage>>,
+    // Undo `tone_map` on every sample the tiler writes (last pass of a tone-mapped job)
+    inverse_tone_map: bool,
+}
+
+impl DiskImage {
+    /// Creates a zeroed canvas in `dir`. Fails early if the disk lacks the space
+    /// (writing into a mapping on a full disk would crash the process instead).
+    ///
+    /// The tiler writes RGB and luma layouts; `Rgba*`/`La*` hold merged output.
+    pub fn create(dir: &Path, width: u32, height: u32, color: image::ColorType) -> AppResult<Self> {
+        if !is_tile_target(color)
+            && !matches!(
+                color,
+                image::ColorType::Rgba8
+                    | image::ColorType::Rgba16
+                    | image::ColorType::Rgba32F
+                    | image::ColorType::La8
+                    | image::ColorType::La16
+            )
+        {
+            return Err(AppError::ImageError(format!(
+                "Unsupported disk canvas color type {:?}",
+                color
+            )));
+        }
+
+        let size = width as u64 * height as u64 * color.bytes_per_pixel() as u64;
+        if let Some(available) = available_disk_space(dir) {
+            if available < size {
+                return Err(AppError::Unknown(format!(
+                    "Insufficient disk space for streaming output. Need ~{} MB, Available {} MB",
+                    size / 1024 / 1024,
+                    available / 1024 / 1024
+                )));
+            }
+        }
+
+        let path = dir.join(format!(".rustscale-{}.canvas", uuid::Uuid::new_v4()));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let mut canvas = Self {
+            width,
+            height,
+            color,
+            path,
+            mmap: None,
+            alpha: None,
+            inverse_tone_map: false,
+        };
+        file.set_len(size)?;
+        canvas.mmap = Some(unsafe { memmap2::MmapMut::map_mut(&file)? });
+
+        Ok(canvas)
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn color(&self) -> image::ColorType {
+        self.color
+    }
+
+    /// Raw pixel bytes, row-major.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.mmap.as_deref().unwrap_or(&[])
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8] {
+        self.mmap.as_deref_mut().unwrap_or(&mut [])
+    }
+
+    fn flush(&self) -> AppResult<()> {
+        if let Some(mmap) = &self.mmap {
+            mmap.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Scratch folder holding the canvas. Canvases derived from it go there too.
+    pub fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new("."))
+    }
+
+    /// Attaches the upscaled alpha plane, which must match the canvas size and
+    /// use the layout `disk_alpha_color` picks for it.
+    pub fn set_alpha(&mut self, alpha: DiskImage) -> AppResult<()> {
+        if alpha.dimensions() != self.dimensions() || alpha.color != disk_alpha_color(self.color) {
+            return Err(AppError::ImageError(format!(
+                "Alpha canvas {}x{} {:?} does not fit a {}x{} {:?} canvas",
+                alpha.width, alpha.height, alpha.color, self.width, self.height, self.color
+            )));
+        }
+        self.alpha = Some(Box::new(alpha));
+        Ok(())
+    }
+
+    pub fn alpha(&self) -> Option<&DiskImage> {
+        self.alpha.as_deref()
+    }
+
+    /// Applies the inverse of `tone_map` to everything the tiler writes from now on.
+    pub fn set_inverse_tone_map(&mut self, enabled: bool) {
+        self.inverse_tone_map = enabled;
+    }
+
+    /// Layout of the encoded pixels: the canvas color plus the alpha plane, if any.
+    pub fn output_color(&self) -> image::ColorType {
+        if self.alpha.is_none() {
+            return self.color;
+        }
+        match self.color {
+            image::ColorType::Rgb8 => image::ColorType::Rgba8,
+            image::ColorType::Rgb16 => image::ColorType::Rgba16,
+            image::ColorType::Rgb32F => image::ColorType::Rgba32F,
+            image::ColorType::L8 => image::ColorType::La8,
+            image::ColorType::L16 => image::ColorType::La16,
+            color => color,
+        }
+    }
+
+    fn row(&self, y: u32) -> &[u8] {
+        let len = self.width as usize * self.color.bytes_per_pixel() as usize;
+        &self.as_bytes()[y as usize * len..(y as usize + 1) * len]
+    }
+
+    // Row `y` as encoded: the canvas row, interleaved with the alpha row if there is one
+    fn output_row<'a>(&'a self, y: u32, merged: &'a mut Vec<u8>) -> &'a [u8] {
+        let Some(alpha) = &self.alpha else {
+            return self.row(y);
+        };
+        let pixel = self.color.bytes_per_pixel() as usize;
+        let sample = pixel / self.color.channel_count() as usize;
+        let alpha_pixel = alpha.color.bytes_per_pixel() as usize;
+
+        merged.clear();
+        for (color, a) in self
+            .row(y)
+            .chunks_exact(pixel)
+            .zip(alpha.row(y).chunks_exact(alpha_pixel))
+        {
+            merged.extend_from_slice(color);
+            if sample == 4 {
+                // Float canvases keep a 16-bit alpha plane
+                let a = u16::from_ne_bytes([a[0], a[1]]);
+                merged.extend_from_slice(&(a as f32 / 65535.0).to_ne_bytes());
+            } else {
+                merged.extend_from_slice(a);
+            }
+        }
+        merged
+    }
+
+    // Copy with the alpha plane interleaved, for encoders that take all rows at once
+    fn merged(&self) -> AppResult<DiskImage> {
+        let mut merged =
+            DiskImage::create(self.dir(), self.width, self.height, self.output_color())?;
+        let row_len = self.width as usize * merged.color.bytes_per_pixel() as usize;
+        let mut buffer = Vec::with_capacity(row_len);
+        for y in 0..self.height {
+            let row = self.output_row(y, &mut buffer);
+            let start = y as usize * row_len;
+            merged.as_bytes_mut()[start..start + row_len].copy_from_slice(row);
+        }
+        merged.flush()?;
+        Ok(merged)
+    }
+
+    // The canvas as input of a further tiled pass, with its alpha plane if any
+    fn tile_source(&self) -> AppResult<TileSource<'_>> {
+        let (width, height) = self.dimensions();
+        // The mapping is page aligned and any bit pattern is a valid sample
+        let unaligned =
+            || AppError::ImageError("Disk canvas is not aligned for wide samples".to_string());
+        let alpha = match self.alpha.as_deref() {
+            Some(alpha) if alpha.color == image::ColorType::L16 => {
+                let (prefix, data, _) = unsafe { alpha.as_bytes().align_to::<u16>() };
+                if !prefix.is_empty() {
+                    return Err(unaligned());
+                }
+                Some(AlphaSource::Wide(data))
+            }
+            Some(alpha) => Some(AlphaSource::Standard(alpha.as_bytes())),
+            None => None,
+        };
+        match self.color {
+            image::ColorType::Rgb8 => Ok(TileSource::Standard {
+                data: self.as_bytes(),
+                alpha,
+                width,
+                height,
+            }),
+            image::ColorType::Rgb32F => {
+                let (prefix, data, _) = unsafe { self.as_bytes().align_to::<f32>() };
+                if !prefix.is_empty() {
+                    return Err(unaligned());
+                }
+                Ok(TileSource::High {
+                    data,
+                    alpha,
+                    width,
+                    height,
+                })
+            }
+            color => Err(AppError::ImageError(format!(
+                "Cannot tile from a {:?} disk canvas",
+                color
+            ))),
+        }
+    }
+
+    // Writes blend strip rows (`stride` values per pixel, this canvas' channels
+    // first), normalized by their weights
+    fn store_rows(&mut self, y: u32, color: &[f32], stride: usize, weight: &[f32]) {
+        let width = self.width as usize;
+        let channels = self.color.channel_count() as usize;
+        let sample = self.color.bytes_per_pixel() as usize / channels;
+        let color_type = self.color;
+        let convert = sample_transform(self.inverse_tone_map);
+        let start = y as usize * width * channels * sample;
+        let dst = &mut self.as_bytes_mut()[start..start + weight.len() * channels * sample];
+
+        dst.par_chunks_mut(width * channels * sample)
+            .zip(color.par_chunks(width * stride))
+            .zip(weight.par_chunks(width))
+            .for_each(|((dst_row, acc_row), weight_row)| {
+                for (i, (acc, &weight)) in acc_row.chunks(stride).zip(weight_row).enumerate() {
+                    let inv = if weight > 0.0 { 1.0 / weight } else { 0.0 };
+                    for (c, &value) in acc[..channels].iter().enumerate() {
+                        store_sample(dst_row, i * channels + c, convert(value * inv), color_type);
+                    }
+                }
+            });
+    }
+
+    // Writes `rows` x `cols` pixels at (x, y), taking row `v` of each plane from `planes(v)`
+    fn store_planes<'t>(
+        &mut self,
+        planes: impl Fn(u32) -> [&'t [f32]; 3],
+        rows: u32,
+        cols: usize,
+        (x, y): (u32, u32),
+    ) {
+        let width = self.width as usize;
+        let channels = self.color.channel_count() as usize;
+        let sample = self.color.bytes_per_pixel() as usize / channels;
+        let color_type = self.color;
+        let convert = sample_transform(self.inverse_tone_map);
+        let bytes = self.as_bytes_mut();
+
+        for v in 0..rows {
+            let planes = planes(v);
+            let start = ((y + v) as usize * width + x as usize) * channels * sample;
+            let dst_row = &mut bytes[start..start + cols * channels * sample];
+            for i in 0..cols {
+                for (c, plane) in planes[..channels].iter().enumerate() {
+                    store_sample(dst_row, i * channels + c, convert(plane[i]), color_type);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for DiskImage {
+    fn drop(&mut self) {
+        // Unmap first, Windows refuses to delete mapped files
+        self.mmap.take();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl CanvasTarget for DiskImage {
+    fn put_rows(&mut self, y: u32, color: &[f32], stride: usize, weight: &[f32]) {
+        self.store_rows(y, color, stride, weight);
+        if let (Some(alpha), 4) = (self.alpha.as_deref_mut(), stride) {
+            // Three values in, every pixel's chunk starts at its alpha
+            alpha.store_rows(y, &color[3..], stride, weight);
+        }
+    }
+
+    fn put_planes(&mut self, tile: TilePlanes, src: (u32, u32), size: (u32, u32), x: u32, y: u32) {
+        let rows = size.1.min(self.height.saturating_sub(y));
+        let cols = size.0.min(self.width.saturating_sub(x)) as usize;
+        self.store_planes(|v| tile.rows(src.0, src.1 + v, cols), rows, cols, (x, y));
+        if let (Some(alpha), 4) = (self.alpha.as_deref_mut(), tile.channels) {
+            // The alpha canvas keeps the first plane it is given
+            let plane = |v| [tile.plane_row(3, src.0, src.1 + v, cols); 3];
+            alpha.store_planes(plane, rows, cols, (x, y));
+        }
+    }
+}
+
+// Layouts the tiler can stitch into
+fn is_tile_target(color: image::ColorType) -> bool {
+    matches!(
+        color,
+        image::ColorType::Rgb8
+            | image::ColorType::Rgb16
+            | image::ColorType::Rgb32F
+            | image::ColorType::L8
+            | image::ColorType::L16
+    )
+}
+
+/// Layout of the alpha canvas that goes with a color canvas: `L8` next to 8-bit
+/// color, `L16` next to 16-bit and float color.
+pub fn disk_alpha_color(color: image::ColorType) -> image::ColorType {
+    if color.bytes_per_pixel() / color.channel_count() > 1 {
+        image::ColorType::L16
+    } else {
+        image::ColorType::L8
+    }
+}
+
+fn sample_transform(inverse_tone_map: bool) -> fn(f32) -> f32 {
+    if inverse_tone_map {
+        inverse_tone_map_value
+    } else {
+        |v| v
+    }
+}
+
+// Rows resampled at a time when resizing into a disk canvas
+const RESIZE_BAND_ROWS: u32 = 256;
+
+/// Resamples a disk canvas (and its alpha plane) into a new one of the same
+/// layout, next to it. Works a band of output rows at a time, so neither image
+/// has to fit in RAM.
+pub fn resize_disk_image(image: &DiskImage, width: u32, height: u32) -> AppResult<DiskImage> {
+    let mut resized = DiskImage::create(image.dir(), width, height, image.color)?;
+    resize_into_disk(image.as_bytes(), image.dimensions(), &mut resized)?;
+    if let Some(alpha) = image.alpha() {
+        resized.set_alpha(resize_disk_image(alpha, width, height)?)?;
+    }
+    Ok(resized)
+}
+
+/// Resamples an in-memory alpha plane straight into a new `L8`/`L16` disk canvas,
+/// the streaming counterpart of the resize `merge_alpha` does.
+pub fn resize_alpha_to_disk(
+    alpha: &DynamicImage,
+    dir: &Path,
+    (width, height): (u32, u32),
+    color: image::ColorType,
+) -> AppResult<DiskImage> {
+    let raw = match color {
+        image::ColorType::L8 => alpha.to_luma8().into_raw(),
+        image::ColorType::L16 => u16_to_bytes(alpha.to_luma16().as_raw()),
+        _ => {
+            return Err(AppError::ImageError(format!(
+                "Unsupported alpha canvas color type {:?}",
+                color
+            )))
+        }
+    };
+    let mut canvas = DiskImage::create(dir, width, height, color)?;
+    resize_into_disk(&raw, alpha.dimensions(), &mut canvas)?;
+    Ok(canvas)
+}
+
+// Resamples packed pixels of the canvas's layout into the canvas, band by band.
+// Each band crops the source rows it maps to; the filter still reads past the
+// crop box, so bands join up without seams.
+fn resize_into_disk(
+    src: &[u8],
+    (src_w, src_h): (u32, u32),
+    canvas: &mut DiskImage,
+) -> AppResult<()> {
+    use fast_image_resize::PixelType;
+    let pixel_type = match canvas.color {
+        image::ColorType::L8 => PixelType::U8,
+        image::ColorType::L16 => PixelType::U16,
+        image::ColorType::Rgb8 => PixelType::U8x3,
+        image::ColorType::Rgb16 => PixelType::U16x3,
+        image::ColorType::Rgb32F => PixelType::F32x3,
+        color => {
+            return Err(AppError::ImageError(format!(
+                "Cannot resize a {:?} disk canvas",
+                color
+            )))
+        }
+    };
+    let src_image = fast_image_resize::images::ImageRef::new(src_w, src_h, src, pixel_type)
+        .map_err(|e| AppError::ImageError(e.to_string()))?;
+
+    let (width, height) = canvas.dimensions();
+    let row_len = width as usize * canvas.color.bytes_per_pixel() as usize;
+    let ratio = src_h as f64 / height as f64;
+    let mut resizer = Resizer::new();
+    for y in (0..height).step_by(RESIZE_BAND_ROWS as usize) {
+        let rows = RESIZE_BAND_ROWS.min(height - y);
+        let top = y as f64 * ratio;
+        let bottom = ((y + rows) as f64 * ratio).min(src_h as f64);
+        let band = &mut canvas.as_bytes_mut()[y as usize * row_len..(y + rows) as usize * row_len];
+        let mut dst_image = Image::from_slice_u8(width, rows, band, pixel_type)
+            .map_err(|e| AppError::ImageError(e.to_string()))?;
+        resizer
+            .resize(
+                &src_image,
+                &mut dst_image,
+                &fast_image_resize::ResizeOptions::new()
+                    .resize_alg(fast_image_resize::ResizeAlg::Convolution(
+                        fast_image_resize::FilterType::CatmullRom,
+                    ))
+                    .crop(0.0, top, src_w as f64, bottom - top),
+            )
+            .map_err(|e| AppError::ImageError(e.to_string()))?;
+    }
+    canvas.flush()
+}
+
+/// Center-crops or pads a disk canvas (and its alpha plane) to `width` x `height`,
+/// like `crop_center` and `pad_center` do in memory. The border stays zero:
+/// black, or transparent in an alpha canvas.
+pub fn reframe_disk_image(image: &DiskImage, width: u32, height: u32) -> AppResult<DiskImage> {
+    let mut framed = DiskImage::create(image.dir(), width, height, image.color)?;
+    let (w, h) = image.dimensions();
+    let pixel = image.color.bytes_per_pixel() as usize;
+    let (src_x, src_y) = (w.saturating_sub(width) / 2, h.saturating_sub(height) / 2);
+    let (dst_x, dst_y) = (width.saturating_sub(w) / 2, height.saturating_sub(h) / 2);
+    let len = w.min(width) as usize * pixel;
+
+    for v in 0..h.min(height) {
+        let src = ((src_y + v) as usize * w as usize + src_x as usize) * pixel;
+        let dst = ((dst_y + v) as usize * width as usize + dst_x as usize) * pixel;
+        framed.as_bytes_mut()[dst..dst + len].copy_from_slice(&image.as_bytes()[src..src + len]);
+    }
+    framed.flush()?;
+    if let Some(alpha) = image.alpha() {
+        framed.set_alpha(reframe_disk_image(alpha, width, height)?)?;
+    }
+    Ok(framed)
+}
+
+// Writes one 0..1 sample into a packed row of the given color type
+fn store_sample(dst: &mut [u8], index: usize, value: f32, color: image::ColorType) {
+    match color {
+        image::ColorType::Rgb8 | image::ColorType::L8 => {
+            dst[index] = (value * 255.0).round().clamp(0.0, 255.0) as u8
+        }
+        image::ColorType::Rgb16 | image::ColorType::L16 => {
+            let v = (value * 65535.0).round().clamp(0.0, 65535.0) as u16;
+            dst[index * 2..index * 2 + 2].copy_from_slice(&v.to_ne_bytes());
+        }
+        _ => dst[index * 4..index * 4 + 4].copy_from_slice(&value.to_ne_bytes()),
+    }
+}
+
+// Free space on the disk holding `dir`, if it can be determined
+fn available_disk_space(dir: &Path) -> Option<u64> {
+    let dir = dir.canonicalize().ok()?;
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    disks
+        .list()
+        .iter()
+        .filter(|disk| dir.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| disk.available_space())
+}
+
+// Helper to mirror coordinates (Reflect mode)
+// Handles coordinates outside the image bounds by reflecting them back in.
+fn mirror_coordinate(coord: i64, max: i64) -> u32 {
+    let mut c = coord;
+    // Reflect lower bound (negative coordinates)
+    while c < 0 {
+        c = -c;
+    }
+    // Reflect upper bound (past image width/height)
+    // Uses ping-pong style reflection: 0 1 2 1 0
+    while c >= max {
+        c = 2 * (max - 1) - c;
+    }
+    // Final clamp just to be safe against edge cases like max=1
+    c.clamp(0, max - 1) as u32
+}
+
+// Source pixels the tiler reads from, borrowed for the whole job: tightly
+// packed RGB rows of a decoded image or of a disk canvas, and the matching
+// alpha plane when it is tiled too
+enum TileSource<'a> {
+    Standard {
+        data: &'a [u8],
+        alpha: Option<AlphaSource<'a>>,
+        width: u32,
+        height: u32,
+    },
+    High {
+        data: &'a [f32],
+        alpha: Option<AlphaSource<'a>>,
+        width: u32,
+        height: u32,
+    },
+}
+
+// `L8` or `L16` alpha samples, one per source pixel
+enum AlphaSource<'a> {
+    Standard(&'a [u8]),
+    Wide(&'a [u16]),
+}
+
+impl TileSource<'_> {
+    // Fills one planar tile (`target_w + 2 * padding` square-ish region around
+    // (x_start, y_start)) straight from the source rows. Out-of-bounds reads are
+    // mirrored, which covers the padding and any filler past the image edge.
+    fn fill_planes(
+        &self,
+        (x_start, y_start, target_w, target_h): (u32, u32, u32, u32),
+        padding: u32,
+        dest: &mut [f32],
+    ) {
+        let total_w = target_w + 2 * padding;
+        let total_h = target_h + 2 * padding;
+        let (img_w, img_h, alpha) = match self {
+            TileSource::Standard {
+                width,
+                height,
+                alpha,
+                ..
+            }
+            | TileSource::High {
+                width,
+                height,
+                alpha,
+                ..
+            } => (*width, *height, alpha),
+        };
+
+        // Mirrored source column for every tile column, shared by all rows
+        let cols: Vec<usize> = (0..total_w)
+            .map(|tx| {
+                let ideal = x_start as i64 + tx as i64 - padding as i64;
+                mirror_coordinate(ideal, img_w as i64) as usize * 3
+            })
+            .collect();
+
+        let plane = total_w as usize * total_h as usize;
+        let (r, rest) = dest.split_at_mut(plane);
+        let (g, rest) = rest.split_at_mut(plane);
+        // `a` is empty unless the alpha plane is tiled too
+        let (b, a) = rest.split_at_mut(plane);
+        let lut = crate::inference::pixel_lut();
+
+        for ty in 0..total_h {
+            let src_y = mirror_coordinate(y_start as i64 + ty as i64 - padding as i64, img_h as i64)
+                as usize;
+            let o = ty as usize * total_w as usize;
+            let (r, g, b) = (
+                &mut r[o..o + total_w as usize],
+                &mut g[o..o + total_w as usize],
+                &mut b[o..o + total_w as usize],
+            );
+            let row_len = img_w as usize * 3;
+
+            match self {
+                TileSource::Standard { data, .. } => {
+                    let row = &data[src_y * row_len..(src_y + 1) * row_len];
+                    for (i, &x) in cols.iter().enumerate() {
+                        r[i] = lut[row[x] as usize];
+                        g[i] = lut[row[x + 1] as usize];
+                        b[i] = lut[row[x + 2] as usize];
+                    }
+                }
+                TileSource::High { data, .. } => {
+                    let row = &data[src_y * row_len..(src_y + 1) * row_len];
+                    for (i, &x) in cols.iter().enumerate() {
+                        r[i] = row[x];
+                        g[i] = row[x + 1];
+                        b[i] = row[x + 2];
+                    }
+                }
+            }
+
+            // Alpha fills the fourth plane from the same mirrored columns,
+            // which are offsets into RGB rows
+            if let Some(alpha) = alpha {
+                let a = &mut a[o..o + total_w as usize];
+                let row = src_y * img_w as usize;
+                match alpha {
+                    AlphaSource::Standard(data) => {
+                        for (i, &x) in cols.iter().enumerate() {
+                            a[i] = lut[data[row + x / 3] as usize];
+                        }
+                    }
+                    AlphaSource::Wide(data) => {
+                        for (i, &x) in cols.iter().enumerate() {
+                            a[i] = data[row + x / 3] as f32 / 65535.0;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Stitch upscaled tile into output
+fn stitch_tile(
+    output: &mut dyn CanvasTarget,
+    upscaled_tile: TilePlanes,
+    meta: &TileMetadata,
+    scale: u32,
+    padding: u32,
+    (tile_w, tile_h): (u32, u32),
+) -> AppResult<()> {
+    // We only want to paste the VALID part of the tile.
+    // The tile might contain mirrored junk at the right/bottom edges if it was an edge tile.
+
+    // 1. Skip the padding area
+    let crop_x = padding * scale;
+    let crop_y = padding * scale;
+
+    // 2. Only take the valid content width/height
+    let crop_w = meta.content_width * scale;
+    let crop_h = meta.content_height * scale;
+
+    if upscaled_tile.width < crop_x + crop_w || upscaled_tile.height < crop_y + crop_h {
+        return Err(AppError::ImageError(format!(
+            "Upscaled tile {}x{} is smaller than expected {}x{}",
+            upscaled_tile.width,
+            upscaled_tile.height,
+            crop_x + crop_w,
+            crop_y + crop_h
+        )));
+    }
+
+    // Stitch into output at correct position
+    let out_x = meta.x_index * tile_w * scale;
+    let out_y = meta.y_index * tile_h * scale;
+
+    output.put_planes(
+        upscaled_tile,
+        (crop_x, crop_y),
+        (crop_w, crop_h),
+        out_x,
+        out_y,
+    );
+
+    Ok(())
+}
+
+// Weighted accumulation buffer for feathered tile blending.
+//
+// Tiles arrive in row-major order, so we only need to keep a float strip covering
+// one row of tiles plus its overlap band. Rows that no later tile can touch are
+// normalized and flushed into the output, keeping memory at O(width * tile_height).
+struct BlendCanvas {
+    src_width: u32,
+    src_height: u32,
+    out_width: u32,
+    out_height: u32,
+    scale: u32,
+    tile_w: u32,
+    tile_h: u32,
+    padding: u32,
+    overlap: u32,
+    mode: BlendMode,
+    origin_y: u32,   // Output row stored at strip row 0
+    rows: u32,       // Strip capacity in output rows
+    channels: usize, // Accumulated values per pixel: RGB, or RGBA when alpha is tiled
+    color: Vec<f32>,
+    weight: Vec<f32>,
+}
+
+impl BlendCanvas {
+    fn new(
+        (src_width, src_height): (u32, u32),
+        scale: u32,
+        (tile_w, tile_h): (u32, u32),
+        padding: u32,
+        overlap: u32,
+        mode: BlendMode,
+        channels: usize,
+    ) -> Self {
+        let out_width = src_width * scale;
+        let rows = (tile_h + 2 * overlap) * scale;
+        let strip_pixels = out_width as usize * rows as usize;
+        Self {
+            src_width,
+            src_height,
+            out_width,
+            out_height: src_height * scale,
+            scale,
+            tile_w,
+            tile_h,
+            padding,
+            overlap,
+            mode,
+            origin_y: 0,
+            rows,
+            channels,
+            color: vec![0.0; strip_pixels * channels],
+            weight: vec![0.0; strip_pixels],
+        }
+    }
+
+    // Weight of a pixel at `pos` inside a region of `len` pixels, with ramps of
+    // `2 * start_band` / `2 * end_band` pixels centered on the tile boundaries.
+    fn ramp(&self, pos: u32, len: u32, start_band: u32, end_band: u32) -> f32 {
+        let mut w = 1.0;
+        if start_band > 0 && pos < 2 * start_band {
+            w *= self
+                .mode
+                .weight((pos as f32 + 0.5) / (2 * start_band) as f32);
+        }
+        let from_end = len.saturating_sub(1 + pos);
+        if end_band > 0 && from_end < 2 * end_band {
+            w *= self
+                .mode
+                .weight((from_end as f32 + 0.5) / (2 * end_band) as f32);
+        }
+        w
+    }
+
+    // Region covered by a tile, in output coordinates, and where it sits in the tile.
+    fn region(&self, meta: &TileMetadata) -> BlendRegion {
+        let s = self.scale;
+        let content_x = meta.x_index * self.tile_w;
+        let content_y = meta.y_index * self.tile_h;
+
+        // Only extend into a neighbour if there is one on that side
+        let ov_left = if content_x > 0 { self.overlap } else { 0 };
+        let ov_top = if content_y > 0 { self.overlap } else { 0 };
+        let ov_right = if content_x + meta.content_width < self.src_width {
+            self.overlap
+        } else {
+            0
+        };
+        let ov_bottom = if content_y + meta.content_height < self.src_height {
+            self.overlap
+        } else {
+            0
+        };
+
+        let x = (content_x - ov_left) * s;
+        let y = (content_y - ov_top) * s;
+        let full_w = (meta.content_width + ov_left + ov_right) * s;
+        let full_h = (meta.content_height + ov_top + ov_bottom) * s;
+
+        BlendRegion {
+            x,
+            y,
+            w: full_w.min(self.out_width - x),
+            h: full_h.min(self.out_height - y),
+            full_w,
+            full_h,
+            // Matching offset inside the upscaled (padded) tile
+            tile_x: (self.padding - ov_left) * s,
+            tile_y: (self.padding - ov_top) * s,
+            bands: [ov_left * s, ov_top * s, ov_right * s, ov_bottom * s],
+        }
+    }
+
+    fn accumulate(
+        &mut self,
+        output: &mut dyn CanvasTarget,
+        upscaled_tile: TilePlanes,
+        meta: &TileMetadata,
+    ) -> AppResult<()> {
+        let region = self.region(meta);
+
+        let (tile_w, tile_h) = (upscaled_tile.width, upscaled_tile.height);
+        if tile_w < region.tile_x + region.w || tile_h < region.tile_y + region.h {
+            return Err(AppError::ImageError(format!(
+                "Upscaled tile {}x{} is smaller than expected blend region {}x{}",
+                tile_w,
+                tile_h,
+                region.tile_x + region.w,
+                region.tile_y + region.h
+            )));
+        }
+
+        // A new row of tiles begins: everything above its region is final
+        if region.y > self.origin_y {
+            self.flush_rows(output, region.y);
+        }
+
+        self.add_tile(upscaled_tile, &region);
+        Ok(())
+    }
+
+    fn add_tile(&mut self, tile: TilePlanes, r: &BlendRegion) {
+        let [band_left, band_top, band_right, band_bottom] = r.bands;
+        let weights_x: Vec<f32> = (0..r.w)
+            .map(|u| self.ramp(u, r.full_w, band_left, band_right))
+            .collect();
+
+        let out_w = self.out_width as usize;
+        let region_w = r.w as usize;
+        let channels = self.channels;
+
+        for v in 0..r.h {
+            let wy = self.ramp(v, r.full_h, band_top, band_bottom);
+            let strip_row = (r.y + v - self.origin_y) as usize;
+            let [src_r, src_g, src_b] = tile.rows(r.tile_x, r.tile_y + v, region_w);
+            let src_a = tile.alpha_row(r.tile_x, r.tile_y + v, region_w);
+            let dst_start = strip_row * out_w + r.x as usize;
+
+            let color = &mut self.color[dst_start * channels..(dst_start + region_w) * channels];
+            let weight = &mut self.weight[dst_start..dst_start + region_w];
+
+            for (i, &wx) in weights_x.iter().enumerate() {
+                let w = wx * wy;
+                let pixel = &mut color[i * channels..(i + 1) * channels];
+                pixel[0] += src_r[i] * w;
+                pixel[1] += src_g[i] * w;
+                pixel[2] += src_b[i] * w;
+                if let (Some(a), 4) = (src_a, channels) {
+                    pixel[3] += a[i] * w;
+                }
+                weight[i] += w;
+            }
+        }
+    }
+
+    // Normalize rows [origin_y, upto) into the output and slide the strip down.
+    fn flush_rows(&mut self, output: &mut dyn CanvasTarget, upto: u32) {
+        let upto = upto.min(self.out_height).min(self.origin_y + self.rows);
+        if upto <= self.origin_y {
+            return;
+        }
+
+        let w = self.out_width as usize;
+        let n = (upto - self.origin_y) as usize;
+        let c = self.channels;
+        output.put_rows(
+            self.origin_y,
+            &self.color[..n * w * c],
+            c,
+            &self.weight[..n * w],
+        );
+
+        let color_len = self.color.len();
+        self.color.copy_within(n * w * c.., 0);
+        self.color[color_len - n * w * c..].fill(0.0);
+        let weight_len = self.weight.len();
+        self.weight.copy_within(n * w.., 0);
+        self.weight[weight_len - n * w..].fill(0.0);
+
+        self.origin_y = upto;
+    }
+}
+
+struct BlendRegion {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+    full_w: u32, // Unclipped size, used for ramp positions
+    full_h: u32,
+    tile_x: u32,
+    tile_y: u32,
+    bands: [u32; 4], // Overlap bands (left, top, right, bottom) in output pixels
+}
+
+// Normalizes accumulated pixels of `stride` values into `dst`, keeping the first
+// `channels` of each
+fn normalize_into<T: Send>(
+    dst: &mut [T],
+    channels: usize,
+    color: &[f32],
+    stride: usize,
+    weight: &[f32],
+    convert: fn(f32) -> T,
+) {
+    dst.par_chunks_mut(channels)
+        .zip(color.par_chunks(stride))
+        .zip(weight.par_iter())
+        .for_each(|((pixel, acc), &weight)| {
+            let inv = if weight > 0.0 { 1.0 / weight } else { 0.0 };
+            for (dst, &value) in pixel.iter_mut().zip(acc) {
+                *dst = convert(value * inv);
+            }
+        });
 }
@@ -1,15 +1,22 @@
 use crate::error::{AppError, AppResult};
-use half::f16;
-use image::GenericImageView;
+use crate::image_processing::TileBatch;
+use crate::models::ModelParam;
+use crate::tensor_format::{Quantization, TensorFormat, TensorFormatOverride, TensorLayout};
+use half::{bf16, f16, slice::HalfFloatSliceExt};
 use ort::{
-    inputs,
-    session::{builder::GraphOptimizationLevel, builder::SessionBuilder, Session},
+    session::{
+        builder::GraphOptimizationLevel, builder::SessionBuilder, RunOptions, Session,
+        SessionInputValue,
+    },
     tensor::TensorElementType,
-    value::{Tensor, ValueType},
+    value::{DynValue, Tensor, TensorRef, ValueType},
 };
-use rayon::prelude::*;
-use std::path::Path;
-use std::sync::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 #[cfg(target_os = "macos")]
 use ort::execution_providers::CoreMLExecutionProvider;
@@ -18,6 +25,9 @@ use ort::execution_providers::CoreMLExecutionProvider;
 pub enum TensorData {
     Float32(Vec<f32>),
     Float16(Vec<f16>),
+    BFloat16(Vec<bf16>),
+    Uint8(Vec<u8>),
+    Int8(Vec<i8>),
 }
 
 impl Clone for TensorData {
@@ -25,16 +35,380 @@ impl Clone for TensorData {
         match self {
             Self::Float32(v) => Self::Float32(v.clone()),
             Self::Float16(v) => Self::Float16(v.clone()),
+            Self::BFloat16(v) => Self::BFloat16(v.clone()),
+            Self::Uint8(v) => Self::Uint8(v.clone()),
+            Self::Int8(v) => Self::Int8(v.clone()),
         }
     }
 }
 
+impl TensorData {
+    /// An empty buffer of a supported model element type.
+    pub fn empty(ty: TensorElementType) -> AppResult<Self> {
+        Ok(match ty {
+            TensorElementType::Float32 => Self::Float32(Vec::new()),
+            TensorElementType::Float16 => Self::Float16(Vec::new()),
+            TensorElementType::Bfloat16 => Self::BFloat16(Vec::new()),
+            TensorElementType::Uint8 => Self::Uint8(Vec::new()),
+            TensorElementType::Int8 => Self::Int8(Vec::new()),
+            ty => {
+                return Err(AppError::ModelLoadError(format!(
+                    "Unsupported model tensor type {:?}. Supported: float32, float16, bfloat16, uint8 and int8.",
+                    ty
+                )))
+            }
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Float32(v) => v.len(),
+            Self::Float16(v) => v.len(),
+            Self::BFloat16(v) => v.len(),
+            Self::Uint8(v) => v.len(),
+            Self::Int8(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Replaces the contents with `data` in this buffer's element type.
+    /// Quantized types use `quant`, or `Quantization::for_signed` if unset.
+    pub fn fill_from_f32(&mut self, data: &[f32], quant: Option<Quantization>) {
+        match self {
+            Self::Float32(v) => {
+                v.clear();
+                v.extend_from_slice(data);
+            }
+            Self::Float16(v) => {
+                v.clear();
+                v.resize(data.len(), f16::ZERO);
+                v.convert_from_f32_slice(data);
+            }
+            Self::BFloat16(v) => {
+                v.clear();
+                v.resize(data.len(), bf16::ZERO);
+                v.convert_from_f32_slice(data);
+            }
+            Self::Uint8(v) => {
+                let quant = quant.unwrap_or(Quantization::for_signed(false));
+                v.clear();
+                v.extend(data.iter().map(|&x| quant.quantize(x, 0.0, 255.0) as u8));
+            }
+            Self::Int8(v) => {
+                let quant = quant.unwrap_or(Quantization::for_signed(true));
+                v.clear();
+                v.extend(data.iter().map(|&x| quant.quantize(x, -128.0, 127.0) as i8));
+            }
+        }
+    }
+
+    // Borrowed ORT view of the buffer
+    fn view(&self, shape: Vec<i64>) -> AppResult<SessionInputValue<'_>> {
+        Ok(match self {
+            Self::Float32(v) => TensorRef::from_array_view((shape, v.as_slice()))?.into(),
+            Self::Float16(v) => TensorRef::from_array_view((shape, v.as_slice()))?.into(),
+            Self::BFloat16(v) => TensorRef::from_array_view((shape, v.as_slice()))?.into(),
+            Self::Uint8(v) => TensorRef::from_array_view((shape, v.as_slice()))?.into(),
+            Self::Int8(v) => TensorRef::from_array_view((shape, v.as_slice()))?.into(),
+        })
+    }
+}
+
+/// Copies a model output tensor into `output` as f32, dequantizing uint8/int8
+/// with `quant` (or `Quantization::for_signed`). Returns the tensor shape.
+fn extract_f32(
+    value: &DynValue,
+    quant: Option<Quantization>,
+    output: &mut Vec<f32>,
+) -> AppResult<Vec<i64>> {
+    output.clear();
+    let shape = match value.dtype() {
+        ValueType::Tensor {
+            ty: TensorElementType::Float16,
+            ..
+        } => {
+            let (shape, data) = value.try_extract_tensor::<f16>()?;
+            output.resize(data.len(), 0.0);
+            data.convert_to_f32_slice(output);
+            shape
+        }
+        ValueType::Tensor {
+            ty: TensorElementType::Bfloat16,
+            ..
+        } => {
+            let (shape, data) = value.try_extract_tensor::<bf16>()?;
+            output.resize(data.len(), 0.0);
+            data.convert_to_f32_slice(output);
+            shape
+        }
+        ValueType::Tensor {
+            ty: TensorElementType::Uint8,
+            ..
+        } => {
+            let quant = quant.unwrap_or(Quantization::for_signed(false));
+            let (shape, data) = value.try_extract_tensor::<u8>()?;
+            output.extend(data.iter().map(|&q| quant.dequantize(q as f32)));
+            shape
+        }
+        ValueType::Tensor {
+            ty: TensorElementType::Int8,
+            ..
+        } => {
+            let quant = quant.unwrap_or(Quantization::for_signed(true));
+            let (shape, data) = value.try_extract_tensor::<i8>()?;
+            output.extend(data.iter().map(|&q| quant.dequantize(q as f32)));
+            shape
+        }
+        _ => {
+            let (shape, data) = value.try_extract_tensor::<f32>()?;
+            output.extend_from_slice(data);
+            shape
+        }
+    };
+    Ok(shape.to_vec())
+}
+
+/// Static input dimensions of a model (NCHW).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InputDims {
+    pub batch: Option<usize>,
+    pub height: Option<u32>,
+    pub width: Option<u32>,
+}
+
+/// How CPU inference is split across sessions: `sessions` copies of the model run
+/// tile batches concurrently with `threads` intra-op threads each. None = autodetect.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuPool {
+    pub sessions: Option<usize>,
+    pub threads: Option<usize>,
+}
+
+impl CpuPool {
+    /// Resolves to (sessions, threads per session). By default one session per
+    /// 8 cores: a single session stops scaling well past that.
+    pub fn resolve(self) -> (usize, usize) {
+        let cores = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(4);
+        let sessions = self.sessions.unwrap_or(cores / 8).clamp(1, 8);
+        let threads = self.threads.unwrap_or(cores / sessions).clamp(1, 16);
+        (sessions, threads)
+    }
+}
+
+/// ORT graph optimization levels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptimizationLevel {
+    Disable,
+    Basic,
+    Extended,
+    #[default]
+    All,
+}
+
+impl From<OptimizationLevel> for GraphOptimizationLevel {
+    fn from(level: OptimizationLevel) -> Self {
+        match level {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Basic => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Extended => GraphOptimizationLevel::Level2,
+            OptimizationLevel::All => GraphOptimizationLevel::Level3,
+        }
+    }
+}
+
+/// Per-model ORT session settings from `model_config.json`. Unset fields keep
+/// the defaults: all optimizations, one intra-op thread per core (split across
+/// the CPU pool), ORT's inter-op default and memory pattern on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub optimization_level: Option<OptimizationLevel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub intra_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inter_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_pattern: Option<bool>,
+}
+
+/// ORT-optimized copies of one model, kept in `dir` so later loads skip graph
+/// optimization. Entries are keyed by model hash, ORT build, machine, provider and
+/// session options; a change to any of them optimizes afresh.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OptimizedCache {
+    pub dir: PathBuf,
+    pub model_hash: String,
+    // ONNX Runtime build info (`ort::info()`)
+    pub runtime: String,
+    // GPU, CPU and OS (`tuning::machine_fingerprint`): optimized graphs may use
+    // kernels and layouts picked for this hardware
+    pub hardware: String,
+}
+
+impl OptimizedCache {
+    pub fn path(&self, provider: &str, options: &SessionOptions) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.runtime);
+        hasher.update(&self.hardware);
+        hasher.update(provider);
+        hasher.update(format!("{:?}", options));
+        let key = format!("{:x}", hasher.finalize());
+        self.dir.join(format!(
+            "{}{}.onnx",
+            Self::prefix(&self.model_hash),
+            &key[..16]
+        ))
+    }
+
+    // File name prefix shared by every optimized copy of this model
+    fn prefix(model_hash: &str) -> String {
+        format!("{}-", &model_hash[..model_hash.len().min(16)])
+    }
+
+    /// Deletes all optimized copies and markers of a model, for any runtime.
+    pub fn clear(dir: &Path, model_hash: &str) -> usize {
+        let prefix = Self::prefix(model_hash);
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return 0;
+        };
+        entries
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with(&prefix))
+            .filter(|e| std::fs::remove_file(e.path()).is_ok())
+            .count()
+    }
+
+    // Loads the optimized copy for `provider` if there is one. Otherwise commits
+    // the raw model and has ORT save its optimized graph for next time. Providers
+    // that compile the graph (OpenVINO, CoreML) can't save it; that is remembered
+    // with a marker so they don't pay for the attempt on every load.
+    fn commit(
+        &self,
+        builder: SessionBuilder,
+        model_path: &Path,
+        provider: &str,
+        options: &SessionOptions,
+    ) -> ort::Result<Session> {
+        let cached = self.path(provider, options);
+        let marker = cached.with_extension("unsupported");
+        if marker.exists() {
+            return builder.commit_from_file(model_path);
+        }
+        if cached.exists() {
+            match builder
+                .clone()
+                .with_optimization_level(GraphOptimizationLevel::Disable)
+                .and_then(|b| b.commit_from_file(&cached))
+            {
+                Ok(session) => {
+                    tracing::info!("Loaded optimized model: {:?}", cached);
+                    return Ok(session);
+                }
+                Err(e) => {
+                    tracing::warn!("Optimized model {:?} unusable, rebuilding: {}", cached, e);
+                    let _ = std::fs::remove_file(&cached);
+                }
+            }
+        }
+
+        // Saved under a temporary name so an interrupted write never looks complete
+        let _ = std::fs::create_dir_all(&self.dir);
+        let partial = cached.with_extension("partial");
+        let result = builder
+            .clone()
+            .with_optimized_model_path(&partial)
+            .and_then(|b| b.commit_from_file(model_path));
+        match result {
+            Ok(session) => {
+                if std::fs::rename(&partial, &cached).is_ok() {
+                    tracing::info!("Saved optimized model: {:?}", cached);
+                }
+                Ok(session)
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&partial);
+                let session = builder.commit_from_file(model_path)?;
+                tracing::info!("Optimized model not cacheable on {}: {}", provider, e);
+                let _ = std::fs::write(&marker, e.to_string());
+                Ok(session)
+            }
+        }
+    }
+}
+
+/// Cancellation for one job. The flag is checked between batches; the run options
+/// are passed to every model run so `cancel` also aborts a run already in progress.
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+    run_options: RunOptions,
+}
+
+impl CancelToken {
+    pub fn new() -> AppResult<Self> {
+        Ok(Self {
+            flag: Arc::new(AtomicBool::new(false)),
+            run_options: RunOptions::new()?,
+        })
+    }
+
+    pub fn flag(&self) -> &Arc<AtomicBool> {
+        &self.flag
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    /// Sets the flag and terminates in-flight runs. Later runs with these
+    /// options fail immediately, so a token is not reusable.
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+        if let Err(e) = self.run_options.terminate() {
+            tracing::warn!("Failed to terminate running inference: {}", e);
+        }
+    }
+}
+
+/// A model input beside the image, as the model declares it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtraInput {
+    pub name: String,
+    pub ty: TensorElementType,
+    pub shape: Vec<i64>,
+}
+
+/// The value an extra model input gets for one job (see `OrtSession::bind_params`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoundParam {
+    pub name: String,
+    pub value: f32,
+}
+
 // --- Session Wrapper ---
 pub struct OrtSession {
-    pub session: Mutex<Session>,
+    // More than one only on CPU (see `CpuPool`); GPU providers share one device
+    pub sessions: Vec<Mutex<Session>>,
     pub execution_provider: String, // The ACTUAL provider (e.g. "DirectMLExecutionProvider")
     pub provider_id: String,        // The REQUESTED provider (e.g. "auto", "directml")
     pub input_type: TensorElementType,
+    pub cpu_pool: CpuPool, // As requested, before autodetection
+    pub options: SessionOptions,
+    pub optimized_cache: Option<OptimizedCache>,
+    // I/O conventions from the input shape and ONNX metadata. User overrides
+    // are applied on top by the engine (`LoadedModel::format`).
+    pub format: TensorFormat,
+    // The first input takes the image; any others are conditioning parameters
+    pub image_input: String,
+    pub extra_inputs: Vec<ExtraInput>,
+    next_session: AtomicUsize,
+    warmed: AtomicBool,
 }
 
 impl OrtSession {
@@ -42,64 +416,118 @@ impl OrtSession {
         model_path: &Path,
         prefer_npu: bool,
         execution_provider: Option<String>,
+        cpu_pool: CpuPool,
+        options: SessionOptions,
+        optimized_cache: Option<OptimizedCache>,
     ) -> AppResult<Self> {
-        let num_threads = std::thread::available_parallelism()
-            .map(|n| n.get())
+        let num_threads = options
+            .intra_threads
+            .or_else(|| std::thread::available_parallelism().map(|n| n.get()).ok())
             .unwrap_or(4)
             .clamp(1, 16);
 
         let builder = SessionBuilder::new()
             .map_err(AppError::from)?
-            .with_optimization_level(GraphOptimizationLevel::Level3)
+            .with_optimization_level(options.optimization_level.unwrap_or_default().into())
             .map_err(AppError::from)?
             .with_intra_threads(num_threads)
             .map_err(AppError::from)?
-            .with_memory_pattern(true)
+            .with_memory_pattern(options.memory_pattern.unwrap_or(true))
             .map_err(AppError::from)?
             .with_log_level(ort::logging::LogLevel::Error)
             .map_err(AppError::from)?;
+        let builder = match options.inter_threads {
+            Some(threads) => builder
+                .with_inter_threads(threads)
+                .map_err(AppError::from)?,
+            None => builder,
+        };
 
         let mut session = None;
 
-        let try_provider = |name: &str, builder: SessionBuilder| -> Option<(Session, String)> {
-            match builder.commit_from_file(model_path) {
-                Ok(s) => Some((s, name.to_string())),
-                Err(e) => {
-                    tracing::warn!("Failed to initialize {}: {}", name, e);
-                    None
-                }
-            }
+        // Goes through the optimized-model cache when there is one
+        let commit = |builder: SessionBuilder, provider: &str| match &optimized_cache {
+            Some(cache) => cache.commit(builder, model_path, provider, &options),
+            None => builder.commit_from_file(model_path),
         };
 
+        // `id` as in `capabilities::PROVIDERS`. Registration errors are real errors,
+        // so a session is never labelled with a provider it silently fell back from.
+        let try_provider =
+            |id: &str, name: &str, builder: SessionBuilder| -> Option<(Session, String)> {
+                if !crate::capabilities::is_supported(id) {
+                    tracing::debug!(
+                        "Skipping {}: not supported by this ONNX Runtime build",
+                        name
+                    );
+                    return None;
+                }
+                match commit(builder, name) {
+                    Ok(s) => Some((s, name.to_string())),
+                    Err(e) => {
+                        tracing::warn!("Failed to initialize {}: {}", name, e);
+                        None
+                    }
+                }
+            };
+
         let provider_override = execution_provider
             .as_deref()
             .unwrap_or("auto")
             .to_lowercase();
 
         // --- MANUAL OVERRIDES ---
+        // Providers missing from this ORT build or OS are skipped by `try_provider`
         if provider_override == "directml" {
-            #[cfg(target_os = "windows")]
-            {
-                if let Ok(dml_builder) = builder.clone().with_execution_providers([
-                    ort::execution_providers::DirectMLExecutionProvider::default().build(),
-                ]) {
-                    if let Some(s) = try_provider("DirectML (Forced)", dml_builder) {
-                        session = Some(s);
-                    }
+            if let Ok(dml_builder) = builder.clone().with_execution_providers([
+                ort::execution_providers::DirectMLExecutionProvider::default()
+                    .build()
+                    .error_on_failure(),
+            ]) {
+                if let Some(s) = try_provider("directml", "DirectML (Forced)", dml_builder) {
+                    session = Some(s);
                 }
             }
         } else if provider_override == "openvino" {
-            #[cfg(target_os = "windows")]
-            {
-                // Try GPU first, then Auto
-                if let Ok(ov_builder) = builder.clone().with_execution_providers([
-                    ort::execution_providers::OpenVINOExecutionProvider::default()
-                        .with_device_type("GPU")
-                        .build(),
-                ]) {
-                    if let Some(s) = try_provider("OpenVINO (Forced GPU)", ov_builder) {
-                        session = Some(s);
-                    }
+            // Try GPU first, then Auto
+            if let Ok(ov_builder) = builder.clone().with_execution_providers([
+                ort::execution_providers::OpenVINOExecutionProvider::default()
+                    .with_device_type("GPU")
+                    .build()
+                    .error_on_failure(),
+            ]) {
+                if let Some(s) = try_provider("openvino", "OpenVINO (Forced GPU)", ov_builder) {
+                    session = Some(s);
+                }
+            }
+        } else if provider_override == "cuda" {
+            if let Ok(cuda_builder) = builder.clone().with_execution_providers([
+                ort::execution_providers::CUDAExecutionProvider::default()
+                    .build()
+                    .error_on_failure(),
+            ]) {
+                if let Some(s) = try_provider("cuda", "CUDA (Forced)", cuda_builder) {
+                    session = Some(s);
+                }
+            }
+        } else if provider_override == "rocm" {
+            if let Ok(rocm_builder) = builder.clone().with_execution_providers([
+                ort::execution_providers::ROCmExecutionProvider::default()
+                    .build()
+                    .error_on_failure(),
+            ]) {
+                if let Some(s) = try_provider("rocm", "ROCm (Forced)", rocm_builder) {
+                    session = Some(s);
+                }
+            }
+        } else if provider_override == "coreml" {
+            if let Ok(coreml_builder) = builder.clone().with_execution_providers([
+                ort::execution_providers::CoreMLExecutionProvider::default()
+                    .build()
+                    .error_on_failure(),
+            ]) {
+                if let Some(s) = try_provider("coreml", "CoreML (Forced)", coreml_builder) {
+                    session = Some(s);
                 }
             }
         } else if provider_override == "cpu" {
@@ -113,9 +541,13 @@ impl OrtSession {
                 {
                     if session.is_none() {
                         if let Ok(ov_builder) = builder.clone().with_execution_providers([
-                            ort::execution_providers::OpenVINOExecutionProvider::default().build(),
+                            ort::execution_providers::OpenVINOExecutionProvider::default()
+                                .build()
+                                .error_on_failure(),
                         ]) {
-                            if let Some(s) = try_provider("OpenVINO (NPU/Auto)", ov_builder) {
+                            if let Some(s) =
+                                try_provider("openvino", "OpenVINO (NPU/Auto)", ov_builder)
+                            {
                                 session = Some(s);
                             }
                         }
@@ -127,9 +559,11 @@ impl OrtSession {
                         if let Ok(coreml_builder) = builder.clone().with_execution_providers([
                             ort::execution_providers::CoreMLExecutionProvider::default()
                                 .with_ane_only()
-                                .build(),
+                                .build()
+                                .error_on_failure(),
                         ]) {
-                            if let Some(s) = try_provider("CoreML (Neural Engine)", coreml_builder)
+                            if let Some(s) =
+                                try_provider("coreml", "CoreML (Neural Engine)", coreml_builder)
                             {
                                 session = Some(s);
                             }
@@ -154,9 +588,12 @@ impl OrtSession {
                     if let Ok(ov_builder) = builder.clone().with_execution_providers([
                         ort::execution_providers::OpenVINOExecutionProvider::default()
                             .with_device_type("GPU")
-                            .build(),
+                            .build()
+                            .error_on_failure(),
                     ]) {
-                        if let Some(s) = try_provider("OpenVINO (Intel GPU)", ov_builder) {
+                        if let Some(s) =
+                            try_provider("openvino", "OpenVINO (Intel GPU)", ov_builder)
+                        {
                             session = Some(s);
                         }
                     }
@@ -180,9 +617,12 @@ impl OrtSession {
 
                     if has_enough_memory {
                         if let Ok(dml_builder) = builder.clone().with_execution_providers([
-                            ort::execution_providers::DirectMLExecutionProvider::default().build(),
+                            ort::execution_providers::DirectMLExecutionProvider::default()
+                                .build()
+                                .error_on_failure(),
                         ]) {
-                            if let Some(s) = try_provider("DirectML (GPU)", dml_builder) {
+                            if let Some(s) = try_provider("directml", "DirectML (GPU)", dml_builder)
+                            {
                                 session = Some(s);
                             }
                         }
@@ -195,9 +635,11 @@ impl OrtSession {
         {
             if session.is_none() {
                 if let Ok(rocm_builder) = builder.clone().with_execution_providers([
-                    ort::execution_providers::ROCmExecutionProvider::default().build(),
+                    ort::execution_providers::ROCmExecutionProvider::default()
+                        .build()
+                        .error_on_failure(),
                 ]) {
-                    if let Some(s) = try_provider("ROCm (AMD)", rocm_builder) {
+                    if let Some(s) = try_provider("rocm", "ROCm (AMD)", rocm_builder) {
                         session = Some(s);
                     }
                 }
@@ -208,9 +650,11 @@ impl OrtSession {
         {
             if session.is_none() {
                 if let Ok(cuda_builder) = builder.clone().with_execution_providers([
-                    ort::execution_providers::CUDAExecutionProvider::default().build(),
+                    ort::execution_providers::CUDAExecutionProvider::default()
+                        .build()
+                        .error_on_failure(),
                 ]) {
-                    if let Some(s) = try_provider("CUDA (NVIDIA)", cuda_builder) {
+                    if let Some(s) = try_provider("cuda", "CUDA (NVIDIA)", cuda_builder) {
                         session = Some(s);
                     }
                 }
@@ -221,9 +665,11 @@ impl OrtSession {
         {
             if session.is_none() {
                 if let Ok(coreml_builder) = builder.clone().with_execution_providers([
-                    ort::execution_providers::CoreMLExecutionProvider::default().build(),
+                    ort::execution_providers::CoreMLExecutionProvider::default()
+                        .build()
+                        .error_on_failure(),
                 ]) {
-                    if let Some(s) = try_provider("CoreML (NPU/GPU)", coreml_builder) {
+                    if let Some(s) = try_provider("coreml", "CoreML (NPU/GPU)", coreml_builder) {
                         session = Some(s);
                     }
                 }
@@ -234,39 +680,124 @@ impl OrtSession {
         {
             if session.is_none() {
                 if let Ok(openvino_builder) = builder.clone().with_execution_providers([
-                    ort::execution_providers::OpenVINOExecutionProvider::default().build(),
+                    ort::execution_providers::OpenVINOExecutionProvider::default()
+                        .build()
+                        .error_on_failure(),
                 ]) {
-                    if let Some(s) = try_provider("OpenVINO (Intel)", openvino_builder) {
+                    if let Some(s) = try_provider("openvino", "OpenVINO (Intel)", openvino_builder)
+                    {
                         session = Some(s);
                     }
                 }
             }
         }
 
-        // Fallback to CPU
-        let (session, provider) = if let Some((s, p)) = session {
-            (s, p)
+        // Fallback to CPU: a pool of sessions with the cores split between them
+        let (sessions, provider) = if let Some((s, p)) = session {
+            (vec![s], p)
         } else {
-            let s = builder
-                .commit_from_file(model_path)
+            // Explicit intra threads stand in for an unset pool thread count
+            let (pool_size, threads) = CpuPool {
+                threads: cpu_pool.threads.or(options.intra_threads),
+                ..cpu_pool
+            }
+            .resolve();
+            tracing::info!("CPU session pool: {} x {} threads", pool_size, threads);
+            let sessions = (0..pool_size)
+                .map(|_| commit(builder.clone().with_intra_threads(threads)?, "CPU"))
+                .collect::<Result<Vec<_>, _>>()
                 .map_err(AppError::from)?;
-            (s, "CPU".to_string())
+            (sessions, "CPU".to_string())
         };
 
-        let input_type = session.inputs[0].input_type.clone();
-        if let ValueType::Tensor { ty, .. } = input_type {
+        let input_type = sessions[0].inputs[0].input_type.clone();
+        if let ValueType::Tensor { ty, shape, .. } = input_type {
             tracing::info!("Model loaded: {:?} | Input Type: {:?}", model_path, ty);
+            // Fails early on element types the tiler can't feed
+            TensorData::empty(ty)?;
+            let output_shape = match &sessions[0].outputs[0].output_type {
+                ValueType::Tensor { shape, .. } => shape.to_vec(),
+                _ => Vec::new(),
+            };
+            // QDQ models carry their quantization in the graph; metadata keys
+            // still win over it
+            let quant = crate::onnx::OnnxInfo::read(model_path).ok();
+            let detected = TensorFormat {
+                input_quant: quant.as_ref().and_then(|info| info.input_quant),
+                output_quant: quant.as_ref().and_then(|info| info.output_quant),
+                ..TensorFormat::detect(&shape, &output_shape)
+            };
+            let declared = match sessions[0].metadata() {
+                Ok(metadata) => {
+                    TensorFormatOverride::from_metadata(|key| metadata.custom(key).ok().flatten())
+                }
+                Err(_) => TensorFormatOverride::default(),
+            };
+            let format = declared.apply(detected);
+            if format != TensorFormat::default() {
+                tracing::info!("Model tensor format: {:?}", format);
+            }
+            let image_input = sessions[0].inputs[0].name.clone();
+            let extra_inputs = sessions[0].inputs[1..]
+                .iter()
+                .map(|input| match &input.input_type {
+                    ValueType::Tensor { ty, shape, .. } => Ok(ExtraInput {
+                        name: input.name.clone(),
+                        ty: *ty,
+                        shape: shape.to_vec(),
+                    }),
+                    _ => Err(AppError::ModelLoadError(format!(
+                        "Model input '{}' is not a tensor",
+                        input.name
+                    ))),
+                })
+                .collect::<AppResult<Vec<_>>>()?;
+            if !extra_inputs.is_empty() {
+                tracing::info!("Model extra inputs: {:?}", extra_inputs);
+            }
             Ok(Self {
-                session: Mutex::new(session),
+                sessions: sessions.into_iter().map(Mutex::new).collect(),
                 execution_provider: provider,
                 provider_id: provider_override,
                 input_type: ty,
+                cpu_pool,
+                options,
+                optimized_cache,
+                format,
+                image_input,
+                extra_inputs,
+                next_session: AtomicUsize::new(0),
+                warmed: AtomicBool::new(false),
             })
         } else {
             Err(AppError::Unknown("Model input is not a tensor".to_string()))
         }
     }
 
+    /// Number of sessions that can run at the same time.
+    pub fn pool_size(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// True the first time it is called on this session, false on every call after.
+    pub fn claim_warm_up(&self) -> bool {
+        !self.warmed.swap(true, Ordering::Relaxed)
+    }
+
+    // Takes a free session if there is one, otherwise waits on the next in turn
+    fn acquire(&self) -> AppResult<MutexGuard<'_, Session>> {
+        let start = self.next_session.fetch_add(1, Ordering::Relaxed);
+        let n = self.sessions.len();
+        for i in 0..n {
+            if let Ok(session) = self.sessions[(start + i) % n].try_lock() {
+                return Ok(session);
+            }
+        }
+        self.sessions[start % n]
+            .lock()
+            .map_err(|_| AppError::Unknown("Failed to lock session".to_string()))
+    }
+
     // Internal Helper
     fn run_inference_inner(
         &self,
@@ -274,38 +805,28 @@ impl OrtSession {
         shape: Vec<i64>,
         input_data: TensorData,
     ) -> AppResult<(Vec<i64>, Vec<f32>)> {
-        let shape_usize: Vec<usize> = shape.iter().map(|&x| x as usize).collect();
-
-        let input_tensor_value = match self.input_type {
-            TensorElementType::Float32 => {
-                let data_f32 = match input_data {
-                    TensorData::Float32(d) => d,
-                    TensorData::Float16(d) => d.iter().map(|x| x.to_f32()).collect(),
-                };
-                Tensor::from_array((shape_usize, data_f32))
-                    .map_err(AppError::from)?
-                    .into_dyn()
-            }
-            TensorElementType::Float16 => {
-                let data_f16 = match input_data {
-                    TensorData::Float16(d) => d,
-                    TensorData::Float32(d) => d.iter().map(|x| f16::from_f32(*x)).collect(),
-                };
-                Tensor::from_array((shape_usize, data_f16))
-                    .map_err(AppError::from)?
-                    .into_dyn()
-            }
-            _ => {
-                return Err(AppError::Unknown(format!(
-                    "Unsupported model input type: {:?}",
-                    self.input_type
-                )))
+        // f32 data is converted to the model's element type
+        let input_data = match input_data {
+            TensorData::Float32(data) if self.input_type != TensorElementType::Float32 => {
+                let mut typed = TensorData::empty(self.input_type)?;
+                typed.fill_from_f32(&data, self.format.input_quant);
+                typed
             }
+            data => data,
         };
 
-        let outputs = session
-            .run(inputs![input_tensor_value])
-            .map_err(AppError::from)?;
+        // Extra inputs get 0 here; this path only probes shapes
+        let (height_axis, width_axis) = self.format.layout.spatial_axes();
+        let extras = self.extra_values(
+            &[],
+            self.format.layout,
+            shape[0] as usize,
+            shape[height_axis] as u32,
+            shape[width_axis] as u32,
+        )?;
+        let mut feed = vec![(self.image_input.as_str(), input_data.view(shape)?)];
+        feed.extend(extras);
+        let outputs = session.run(feed).map_err(AppError::from)?;
 
         let output_value = outputs
             .iter()
@@ -313,96 +834,235 @@ impl OrtSession {
             .ok_or_else(|| AppError::OrtError("No output tensor found".to_string()))?
             .1;
 
-        let is_f16 = matches!(
-            output_value.dtype(),
-            ValueType::Tensor {
-                ty: TensorElementType::Float16,
-                ..
-            }
-        );
+        let mut output_data = Vec::new();
+        let output_shape = extract_f32(&output_value, self.format.output_quant, &mut output_data)?;
+        Ok((output_shape, output_data))
+    }
 
-        let (output_shape, output_data_f32) = if is_f16 {
-            let (shape, data_f16) = output_value
-                .try_extract_tensor::<f16>()
-                .map_err(AppError::from)?;
-            (
-                shape.to_vec(),
-                data_f16.iter().map(|&x| x.to_f32()).collect::<Vec<f32>>(),
-            )
+    /// Runs one planar batch. The input is handed to ORT as a borrowed view (f16,
+    /// bf16 and quantized models get a pooled typed copy); the output is copied
+    /// into `output` as f32.
+    /// Models with other conventions than planar RGB 0..1 (see `TensorFormat`)
+    /// get a converted copy in and are converted back on the way out.
+    /// A run terminated through `cancel` returns `AppError::Cancelled`.
+    pub fn run_planar(
+        &self,
+        input: &TileBatch,
+        output: &mut Vec<f32>,
+        pool: &BufferPool,
+        format: &TensorFormat,
+        params: &[BoundParam],
+        cancel: &CancelToken,
+    ) -> AppResult<TileBatch> {
+        if cancel.is_cancelled() {
+            return Err(AppError::Cancelled);
+        }
+
+        let shape = format.input_shape(input);
+        let extras =
+            self.extra_values(params, format.layout, input.len, input.height, input.width)?;
+        let mut encoded = None;
+        let input_data: &[f32] = if format.is_canonical_input() && input.channels == 3 {
+            &input.data
         } else {
-            let (shape, data_f32) = output_value
-                .try_extract_tensor::<f32>()
-                .map_err(AppError::from)?;
-            (shape.to_vec(), data_f32.to_vec())
+            let mut data = pool.get_f32(input.data.len());
+            format.encode(input, &mut data);
+            encoded.insert(data)
         };
+        let mut session = self.acquire()?;
+
+        // Other element types than f32 go through a pooled typed copy
+        let typed_input = match self.input_type {
+            TensorElementType::Float32 => None,
+            ty => {
+                let mut data = pool.get_typed(ty, input_data.len())?;
+                data.fill_from_f32(input_data, format.input_quant);
+                Some(data)
+            }
+        };
+        let value = match &typed_input {
+            Some(data) => data.view(shape)?,
+            None => TensorRef::from_array_view((shape, input_data))?.into(),
+        };
+        let mut feed = vec![(self.image_input.as_str(), value)];
+        feed.extend(extras);
+        let outputs = session.run_with_options(feed, &cancel.run_options);
+        // Termination surfaces as an ORT error; report it as what it is
+        let outputs = outputs.map_err(|e| {
+            if cancel.is_cancelled() {
+                AppError::Cancelled
+            } else {
+                AppError::from(e)
+            }
+        })?;
 
-        Ok((output_shape, output_data_f32))
+        let output_value = outputs
+            .iter()
+            .next()
+            .ok_or_else(|| AppError::OrtError("No output tensor found".to_string()))?
+            .1;
+
+        let output_shape = extract_f32(&output_value, format.output_quant, output)?;
+        drop(outputs);
+
+        if let Some(data) = typed_input {
+            pool.return_typed(data);
+        }
+        if let Some(data) = encoded {
+            pool.return_f32(data);
+        }
+
+        let output_shape = if format.is_canonical_output() {
+            output_shape
+        } else {
+            let mut decoded = pool.get_f32(output.len());
+            let shape = format.decode(&output_shape, output, &mut decoded)?;
+            pool.return_f32(std::mem::replace(output, decoded));
+            shape
+        };
+        TileBatch::from_nchw(&output_shape, std::mem::take(output))
     }
 
-    pub fn run_inference(
+    /// Values for the model's extra inputs: the job's `values` where given,
+    /// otherwise the defaults `declared` in the manifest. Every extra input of
+    /// the model must be declared, and every value must name one of them.
+    pub fn bind_params(
         &self,
-        shape: Vec<i64>,
-        input_data: TensorData,
-    ) -> AppResult<(Vec<i64>, Vec<f32>)> {
-        let mut session = self
-            .session
-            .lock()
-            .map_err(|_| AppError::Unknown("Failed to lock session".to_string()))?;
-        self.run_inference_inner(&mut *session, shape, input_data)
+        declared: &[ModelParam],
+        values: &HashMap<String, f32>,
+    ) -> AppResult<Vec<BoundParam>> {
+        if let Some(name) = values
+            .keys()
+            .find(|name| !self.extra_inputs.iter().any(|input| &input.name == *name))
+        {
+            return Err(AppError::InferenceError(format!(
+                "Model has no input named '{}'",
+                name
+            )));
+        }
+        self.extra_inputs
+            .iter()
+            .map(|input| {
+                let param = declared
+                    .iter()
+                    .find(|param| param.name == input.name)
+                    .ok_or_else(|| {
+                        AppError::ModelLoadError(format!(
+                            "Model input '{}' has no default. Declare it under \"params\" in model_config.json.",
+                            input.name
+                        ))
+                    })?;
+                Ok(BoundParam {
+                    name: input.name.clone(),
+                    value: param.resolve(values.get(&input.name).copied()),
+                })
+            })
+            .collect()
     }
 
-    // Smart Copy Execution (Replacing brittle IoBinding)
-    pub fn run_with_binding(
+    // Tensors for the extra inputs of a run over `batch` tiles of `height` x `width`.
+    // Rank 4 inputs are constant maps the size of the tiles; other dynamic dims
+    // are 1. Inputs without a bound value get 0.
+    fn extra_values(
         &self,
-        shape: Vec<i64>,
-        input_buffer: &TensorData,
-        output_buffer: &mut TensorData,
-    ) -> AppResult<(Vec<i64>, usize)> {
-        let mut session = self
-            .session
-            .lock()
-            .map_err(|_| AppError::Unknown("Failed to lock session".to_string()))?;
-
-        // 1. Run Standard Inference
-        let (shape, data) = self.run_inference_inner(&mut *session, shape, input_buffer.clone())?;
-
-        // 2. Copy to Recycled Buffer
-        let len = data.len();
-        match output_buffer {
-            TensorData::Float32(buf) => {
-                if buf.len() < len {
-                    buf.resize(len, 0.0);
-                }
-                buf[..len].copy_from_slice(&data);
-            }
-            TensorData::Float16(buf) => {
-                if buf.len() < len {
-                    buf.resize(len, f16::from_f32(0.0));
-                }
-                for (i, &val) in data.iter().enumerate() {
-                    buf[i] = f16::from_f32(val);
+        params: &[BoundParam],
+        layout: TensorLayout,
+        batch: usize,
+        height: u32,
+        width: u32,
+    ) -> AppResult<Vec<(&str, SessionInputValue<'static>)>> {
+        let (height_axis, width_axis) = layout.spatial_axes();
+        self.extra_inputs
+            .iter()
+            .map(|input| {
+                let value = params
+                    .iter()
+                    .find(|param| param.name == input.name)
+                    .map_or(0.0, |param| param.value);
+                let mut shape: Vec<usize> =
+                    input.shape.iter().map(|&d| d.max(1) as usize).collect();
+                if shape.len() == 4 {
+                    shape[0] = batch;
+                    shape[height_axis] = height as usize;
+                    shape[width_axis] = width as usize;
                 }
-            }
-        }
-        Ok((shape, len))
+                let len = shape.iter().product();
+                let tensor: DynValue = match input.ty {
+                    TensorElementType::Float32 => {
+                        Tensor::from_array((shape, vec![value; len]))?.into_dyn()
+                    }
+                    TensorElementType::Float16 => {
+                        Tensor::from_array((shape, vec![f16::from_f32(value); len]))?.into_dyn()
+                    }
+                    TensorElementType::Bfloat16 => {
+                        Tensor::from_array((shape, vec![bf16::from_f32(value); len]))?.into_dyn()
+                    }
+                    TensorElementType::Int64 => {
+                        Tensor::from_array((shape, vec![value.round() as i64; len]))?.into_dyn()
+                    }
+                    TensorElementType::Int32 => {
+                        Tensor::from_array((shape, vec![value.round() as i32; len]))?.into_dyn()
+                    }
+                    ty => {
+                        return Err(AppError::ModelLoadError(format!(
+                            "Unsupported type {:?} for model input '{}'",
+                            ty, input.name
+                        )))
+                    }
+                };
+                Ok((input.name.as_str(), SessionInputValue::from(tensor)))
+            })
+            .collect()
     }
 
-    pub fn detect_scale(&self) -> AppResult<u32> {
-        let mut session = self
-            .session
+    /// Static dimensions of the first input. None where the graph leaves them dynamic.
+    pub fn static_input_dims(&self, layout: TensorLayout) -> AppResult<InputDims> {
+        let session = self.sessions[0]
             .lock()
             .map_err(|_| AppError::Unknown("Failed to lock session".to_string()))?;
 
+        let ValueType::Tensor { shape, .. } = &session.inputs[0].input_type else {
+            return Ok(InputDims::default());
+        };
+        // A non-positive entry (-1 or symbolic) is dynamic
+        let fixed = |axis: usize| shape.get(axis).filter(|&&d| d > 0).map(|&d| d as u32);
+        let (height, width) = layout.spatial_axes();
+        Ok(InputDims {
+            batch: fixed(0).map(|d| d as usize),
+            height: fixed(height),
+            width: fixed(width),
+        })
+    }
+
+    /// Scale from a test run. `format` is the effective tensor format, with any
+    /// `model_config.json` override applied, so the probe has the right shape.
+    pub fn detect_scale(&self, format: &TensorFormat) -> AppResult<u32> {
         // Adaptive Scale Detection Strategy:
         // 1. Try 64x64 (Fastest, works for dynamic models)
         // 2. Try 256x256 (Standard, works for most fixed models)
         // 3. Try 512x512 (Safe, works for large fixed models like Real-ESRGAN)
-        let test_sizes = [64, 256, 512];
+        // Models with a fixed square input only accept that size. Read before
+        // `acquire`: `static_input_dims` locks the first session itself.
+        let mut test_sizes = vec![64, 256, 512];
+        let fixed = self.static_input_dims(format.layout)?;
+        if let (Some(h), Some(w)) = (fixed.height, fixed.width) {
+            if h == w {
+                test_sizes.insert(0, h as i64);
+            }
+        }
+
+        let mut session = self.acquire()?;
         let mut last_error = AppError::Unknown("No sizes tested".to_string());
 
+        // Zeros are valid in any channel order; only the layout matters here
+        let (_, width_axis) = format.layout.spatial_axes();
         for &input_dim in &test_sizes {
-            let input_shape = vec![1, 3, input_dim, input_dim];
-            let pixel_count = (input_dim * input_dim * 3) as usize;
+            let channels = format.input_channels as i64;
+            let input_shape = match format.layout {
+                TensorLayout::Nchw => vec![1, channels, input_dim, input_dim],
+                TensorLayout::Nhwc => vec![1, input_dim, input_dim, channels],
+            };
+            let pixel_count = (input_dim * input_dim * channels) as usize;
 
             let input_data = match self.input_type {
                 TensorElementType::Float16 => {
@@ -420,7 +1080,7 @@ impl OrtSession {
                             "Invalid output shape from model".to_string(),
                         ));
                     }
-                    let out_width = output_shape[3] as u32;
+                    let out_width = output_shape[width_axis] as u32;
                     let scale = out_width / (input_dim as u32);
                     return Ok(scale);
                 }
@@ -439,20 +1099,23 @@ impl OrtSession {
 
 // --- Buffer Pooling & Helpers ---
 pub struct BufferPool {
-    f32_buffers: Mutex<Vec<Vec<f32>>>,
-    f16_buffers: Mutex<Vec<Vec<f16>>>,
+    f32_buffers: Buffers<f32>,
+    f16_buffers: Buffers<f16>,
+    bf16_buffers: Buffers<bf16>,
+    u8_buffers: Buffers<u8>,
+    i8_buffers: Buffers<i8>,
 }
 
-impl BufferPool {
-    pub fn new(capacity: usize) -> Self {
-        Self {
-            f32_buffers: Mutex::new(Vec::with_capacity(capacity)),
-            f16_buffers: Mutex::new(Vec::with_capacity(capacity)),
-        }
+// Free buffers of one element type
+struct Buffers<T>(Mutex<Vec<Vec<T>>>);
+
+impl<T> Buffers<T> {
+    fn new(capacity: usize) -> Self {
+        Self(Mutex::new(Vec::with_capacity(capacity)))
     }
 
-    pub fn get_f32(&self, capacity: usize) -> Vec<f32> {
-        let mut pool = self.f32_buffers.lock().unwrap();
+    fn get(&self, capacity: usize) -> Vec<T> {
+        let mut pool = self.0.lock().unwrap();
         if let Some(mut buf) = pool.pop() {
             if buf.capacity() < capacity {
                 buf.reserve(capacity - buf.len());
@@ -464,29 +1127,61 @@ impl BufferPool {
         }
     }
 
-    pub fn return_f32(&self, mut buf: Vec<f32>) {
-        let mut pool = self.f32_buffers.lock().unwrap();
+    fn put(&self, mut buf: Vec<T>) {
+        let mut pool = self.0.lock().unwrap();
         buf.clear();
         pool.push(buf);
     }
+}
 
-    pub fn get_f16(&self, capacity: usize) -> Vec<f16> {
-        let mut pool = self.f16_buffers.lock().unwrap();
-        if let Some(mut buf) = pool.pop() {
-            if buf.capacity() < capacity {
-                buf.reserve(capacity - buf.len());
-            }
-            buf.clear();
-            buf
-        } else {
-            Vec::with_capacity(capacity)
+impl BufferPool {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            f32_buffers: Buffers::new(capacity),
+            f16_buffers: Buffers::new(capacity),
+            bf16_buffers: Buffers::new(capacity),
+            u8_buffers: Buffers::new(capacity),
+            i8_buffers: Buffers::new(capacity),
         }
     }
 
-    pub fn return_f16(&self, mut buf: Vec<f16>) {
-        let mut pool = self.f16_buffers.lock().unwrap();
-        buf.clear();
-        pool.push(buf);
+    pub fn get_f32(&self, capacity: usize) -> Vec<f32> {
+        self.f32_buffers.get(capacity)
+    }
+
+    pub fn return_f32(&self, buf: Vec<f32>) {
+        self.f32_buffers.put(buf)
+    }
+
+    pub fn get_f16(&self, capacity: usize) -> Vec<f16> {
+        self.f16_buffers.get(capacity)
+    }
+
+    pub fn return_f16(&self, buf: Vec<f16>) {
+        self.f16_buffers.put(buf)
+    }
+
+    /// An empty pooled buffer of a model element type.
+    pub fn get_typed(&self, ty: TensorElementType, capacity: usize) -> AppResult<TensorData> {
+        Ok(match ty {
+            TensorElementType::Float32 => TensorData::Float32(self.f32_buffers.get(capacity)),
+            TensorElementType::Float16 => TensorData::Float16(self.f16_buffers.get(capacity)),
+            TensorElementType::Bfloat16 => TensorData::BFloat16(self.bf16_buffers.get(capacity)),
+            TensorElementType::Uint8 => TensorData::Uint8(self.u8_buffers.get(capacity)),
+            TensorElementType::Int8 => TensorData::Int8(self.i8_buffers.get(capacity)),
+            // Unsupported; reports which types are
+            ty => return TensorData::empty(ty),
+        })
+    }
+
+    pub fn return_typed(&self, data: TensorData) {
+        match data {
+            TensorData::Float32(buf) => self.f32_buffers.put(buf),
+            TensorData::Float16(buf) => self.f16_buffers.put(buf),
+            TensorData::BFloat16(buf) => self.bf16_buffers.put(buf),
+            TensorData::Uint8(buf) => self.u8_buffers.put(buf),
+            TensorData::Int8(buf) => self.i8_buffers.put(buf),
+        }
     }
 }
 
@@ -494,7 +1189,8 @@ use std::sync::OnceLock;
 
 static PIXEL_LUT: OnceLock<[f32; 256]> = OnceLock::new();
 
-fn get_pixel_lut() -> &'static [f32; 256] {
+/// u8 -> 0..1 lookup table used when filling input tensors.
+pub fn pixel_lut() -> &'static [f32; 256] {
     PIXEL_LUT.get_or_init(|| {
         let mut lut = [0.0; 256];
         for (i, val) in lut.iter_mut().enumerate() {
@@ -504,207 +1200,37 @@ fn get_pixel_lut() -> &'static [f32; 256] {
     })
 }
 
-pub fn images_to_buffer(
-    images: &[image::DynamicImage],
-    target_type: TensorElementType,
-    buffer: &mut TensorData,
-) -> AppResult<Vec<i64>> {
-    if images.is_empty() {
-        return Err(AppError::Unknown(
-            "No images provided for batching".to_string(),
-        ));
-    }
-
-    let (width, height) = images[0].dimensions();
-    let batch_size = images.len();
-
-    // Check consistency
-    for (i, img) in images.iter().enumerate() {
-        if img.dimensions() != (width, height) {
-            return Err(AppError::Unknown(format!(
-                "Batch dimension mismatch: Image 0 is {}x{}, but Image {} is {}x{}",
-                width,
-                height,
-                i,
-                img.width(),
-                img.height()
-            )));
-        }
-    }
-
-    let pixel_count = (width * height) as usize;
-    let elements_per_image = pixel_count * 3;
-    let total_elements = batch_size * elements_per_image;
-
-    match (target_type, buffer) {
-        (TensorElementType::Float16, TensorData::Float16(batch_data)) => {
-            if batch_data.len() < total_elements {
-                batch_data.resize(total_elements, f16::from_f32(0.0));
-            }
-
-            batch_data
-                .par_chunks_mut(elements_per_image)
-                .zip(images.par_iter())
-                .for_each(|(dest_slice, img)| {
-                    let rgb = img.to_rgb8();
-                    let lut = get_pixel_lut();
-                    let raw_pixels = rgb.as_raw();
-
-                    let (r_plane, rest) = dest_slice.split_at_mut(pixel_count);
-                    let (g_plane, b_plane) = rest.split_at_mut(pixel_count);
-
-                    for (i, chunk) in raw_pixels.chunks(3).enumerate() {
-                        r_plane[i] = f16::from_f32(lut[chunk[0] as usize]);
-                        g_plane[i] = f16::from_f32(lut[chunk[1] as usize]);
-                        b_plane[i] = f16::from_f32(lut[chunk[2] as usize]);
-                    }
-                });
-
-            let shape = vec![batch_size as i64, 3, height as i64, width as i64];
-            Ok(shape)
-        }
-        (TensorElementType::Float32, TensorData::Float32(batch_data)) => {
-            if batch_data.len() < total_elements {
-                batch_data.resize(total_elements, 0.0);
-            }
-
-            batch_data
-                .par_chunks_mut(elements_per_image)
-                .zip(images.par_iter())
-                .for_each(|(dest_slice, img)| {
-                    let rgb = img.to_rgb8();
-                    let lut = get_pixel_lut();
-                    let raw_pixels = rgb.as_raw();
-
-                    let (r_plane, rest) = dest_slice.split_at_mut(pixel_count);
-                    let (g_plane, b_plane) = rest.split_at_mut(pixel_count);
-
-                    for (i, chunk) in raw_pixels.chunks(3).enumerate() {
-                        r_plane[i] = lut[chunk[0] as usize];
-                        g_plane[i] = lut[chunk[1] as usize];
-                        b_plane[i] = lut[chunk[2] as usize];
-                    }
-                });
-
-            let shape = vec![batch_size as i64, 3, height as i64, width as i64];
-            Ok(shape)
-        }
-        _ => Err(AppError::Unknown(format!(
-            "Buffer type mismatch. Target: {:?}, Buffer: ...",
-            target_type
-        ))),
-    }
-}
-
-pub fn images_to_batch_tensor(
-    images: &[image::DynamicImage],
-    target_type: TensorElementType,
-) -> AppResult<(Vec<i64>, TensorData)> {
-    let mut buffer = match target_type {
-        TensorElementType::Float16 => TensorData::Float16(Vec::new()),
-        _ => TensorData::Float32(Vec::new()),
-    };
-    let shape = images_to_buffer(images, target_type, &mut buffer)?;
-    Ok((shape, buffer))
-}
-
-fn tensor_to_image_from_dims(
-    data: &[f32],
-    width: u32,
-    height: u32,
-) -> AppResult<image::DynamicImage> {
-    let pixel_count = (width * height) as usize;
-    if data.len() != pixel_count * 3 {
-        return Err(AppError::Unknown(format!(
-            "Tensor data length {} does not match expected size {} for {}x{}",
-            data.len(),
-            pixel_count * 3,
-            width,
-            height
-        )));
-    }
-
-    let r_channel = &data[0..pixel_count];
-    let g_channel = &data[pixel_count..2 * pixel_count];
-    let b_channel = &data[2 * pixel_count..];
-
-    let mut raw_buffer = vec![0u8; (width * height * 3) as usize];
-
-    raw_buffer
-        .par_chunks_mut(3)
-        .enumerate()
-        .for_each(|(i, pixel)| {
-            let r = (r_channel[i].clamp(0.0, 1.0) * 255.0) as u8;
-            let g = (g_channel[i].clamp(0.0, 1.0) * 255.0) as u8;
-            let b = (b_channel[i].clamp(0.0, 1.0) * 255.0) as u8;
-            pixel[0] = r;
-            pixel[1] = g;
-            pixel[2] = b;
-        });
-
-    let img_buffer = image::RgbImage::from_raw(width, height, raw_buffer).ok_or_else(|| {
-        AppError::Unknown("Failed to create image buffer from raw data".to_string())
-    })?;
-
-    Ok(image::DynamicImage::ImageRgb8(img_buffer))
+/// True if an ONNX Runtime error message reports an allocation failure
+/// (host or device). Covers CUDA, DirectML, ROCm and CPU allocator wording.
+pub fn is_out_of_memory(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "out of memory",
+        "outofmemory", // E_OUTOFMEMORY, hipErrorOutOfMemory
+        "8007000e",    // E_OUTOFMEMORY as an HRESULT
+        "failed to allocate",
+        "bad_alloc",
+        "cudaerrormemoryallocation",
+        "cublas_status_alloc_failed",
+        "is smaller than requested bytes", // BFC arena exhausted
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
 }
 
-pub fn batch_tensor_to_images(
-    inference_output: (Vec<i64>, Vec<f32>),
-) -> AppResult<Vec<image::DynamicImage>> {
-    let (shape, data) = inference_output;
-
-    if shape.len() != 4 {
-        return Err(AppError::Unknown(format!(
-            "Unexpected output tensor rank: {}",
-            shape.len()
-        )));
-    }
-
-    let batch_size = shape[0] as usize;
-    let height = shape[2] as u32;
-    let width = shape[3] as u32;
-
-    if batch_size == 1 {
-        let image = tensor_to_image_from_dims(&data, width, height)?;
-        return Ok(vec![image]);
-    }
-
-    let elements_per_image = (width * height * 3) as usize;
-
-    if data.len() != batch_size * elements_per_image {
-        return Err(AppError::Unknown(format!(
-            "Batch output size mismatch. Expected {}, got {}",
-            batch_size * elements_per_image,
-            data.len()
-        )));
-    }
-
-    let images: Result<Vec<_>, _> = (0..batch_size)
-        .into_par_iter()
-        .map(|i| {
-            let start = i * elements_per_image;
-            let end = start + elements_per_image;
-            let image_data = &data[start..end];
-            tensor_to_image_from_dims(image_data, width, height)
-        })
-        .collect();
-
-    images
-}
-
-pub fn batch_buffer_to_images(
-    shape: &[i64],
-    buffer: &TensorData,
-    valid_len: usize,
-) -> AppResult<Vec<image::DynamicImage>> {
-    match buffer {
-        TensorData::Float32(data) => {
-            batch_tensor_to_images((shape.to_vec(), data[..valid_len].to_vec()))
-        }
-        TensorData::Float16(data) => {
-            let data_f32: Vec<f32> = data[..valid_len].iter().map(|x| x.to_f32()).collect();
-            batch_tensor_to_images((shape.to_vec(), data_f32))
-        }
-    }
+/// True if the GPU went away under the session (driver reset, TDR, removed
+/// device). Nothing run on that session can succeed again.
+pub fn is_device_lost(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "887a0005", // DXGI_ERROR_DEVICE_REMOVED
+        "887a0006", // DXGI_ERROR_DEVICE_HUNG
+        "887a0007", // DXGI_ERROR_DEVICE_RESET
+        "device removed",
+        "device_removed",
+        "cudaerrordevicesunavailable",
+        "cudaerrorlaunchfailure",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
 }
@@ -1,5 +1,7 @@
 pub mod error;
 // Updated to match the new filename (image_processing.rs)
+pub mod capabilities;
+pub mod catalog;
 pub mod commands;
 pub mod engine;
 pub mod gpu;
@@ -7,8 +9,13 @@ pub mod image_processing;
 pub mod inference;
 pub mod logging;
 pub mod metadata;
+pub mod model_cache;
+pub mod model_roots;
 pub mod models;
+pub mod onnx;
 pub mod state;
+pub mod tensor_format;
+pub mod tuning;
 
 #[cfg(test)]
 mod tests;
@@ -32,13 +39,27 @@ pub fn run() {
             commands::upscale_image,
             commands::cancel_job,
             commands::get_system_info,
+            commands::get_capabilities,
             commands::generate_preview,
             commands::get_models,
+            commands::get_model_roots,
+            commands::add_model_root,
+            commands::remove_model_root,
             commands::update_model_info,
             commands::reset_model_info,
+            commands::import_model,
+            commands::remove_model,
+            commands::get_catalog,
+            commands::set_catalog_source,
+            commands::download_catalog_model,
             commands::preload_model,
             commands::scan_paths,
-            commands::upscale_multiple
+            commands::upscale_multiple,
+            commands::benchmark_model,
+            commands::list_cached_models,
+            commands::pin_cached_model,
+            commands::evict_cached_model,
+            commands::set_model_cache_budget
         ])
         .setup(|app| {
             // Initialize Logging
@@ -59,57 +80,80 @@ pub fn run() {
                 }
             });
 
-            // Spawn background task to watch models directory
+            // Spawn background task to watch every model folder
             let app_handle_clone = app_handle.clone();
             tauri::async_runtime::spawn_blocking(move || {
                 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
-                use std::sync::mpsc::channel;
+                use std::sync::mpsc::{channel, RecvTimeoutError};
                 use std::time::Duration;
 
-                let models_dir = std::env::current_dir()
-                    .unwrap_or_else(|_| std::path::PathBuf::from("."))
-                    .join("models");
-
-                if !models_dir.exists() {
-                    return;
-                }
-
                 let (tx, rx) = channel();
 
                 // Debounce events slightly to avoid double-firing
                 let config = Config::default().with_poll_interval(Duration::from_secs(2));
-                let mut watcher: RecommendedWatcher = Watcher::new(tx, config).unwrap();
-
-                if let Err(e) = watcher.watch(&models_dir, RecursiveMode::NonRecursive) {
-                    eprintln!("Failed to watch models directory: {}", e);
-                    return;
-                }
+                let mut watcher: RecommendedWatcher = match Watcher::new(tx, config) {
+                    Ok(w) => w,
+                    Err(e) => {
+                        tracing::warn!("Failed to create model watcher: {}", e);
+                        return;
+                    }
+                };
+
+                let state = app_handle_clone.state::<AppState>();
+                let mut watched: Vec<std::path::PathBuf> = Vec::new();
+                let mut revision = None;
+
+                loop {
+                    // (Re)watch when folders were added or removed
+                    let roots = {
+                        let roots = state.model_roots.lock().unwrap();
+                        (revision != Some(roots.revision))
+                            .then(|| (roots.revision, roots.roots().to_vec()))
+                    };
+                    if let Some((current, roots)) = roots {
+                        for path in watched.drain(..) {
+                            let _ = watcher.unwatch(&path);
+                        }
+                        for root in roots.iter().filter(|r| r.path.is_dir()) {
+                            let mode = if root.recursive {
+                                RecursiveMode::Recursive
+                            } else {
+                                RecursiveMode::NonRecursive
+                            };
+                            match watcher.watch(&root.path, mode) {
+                                Ok(()) => watched.push(root.path.clone()),
+                                Err(e) => tracing::warn!("Failed to watch {:?}: {}", root.path, e),
+                            }
+                        }
+                        revision = Some(current);
+                    }
 
-                // Loop forever handling events
-                for res in rx {
-                    match res {
-                        Ok(event) => {
-                            // Filter for relevant events (Create, Remove, Modify, Rename)
-                            // and ignore temp files if possible
-                            match event.kind {
-                                notify::EventKind::Create(_)
-                                | notify::EventKind::Remove(_)
-                                | notify::EventKind::Modify(_) => {
-                                    // 1. Invalidate Scale Cache for this file
-                                    // This ensures that if a user replaces a broken model, we re-detect it.
-                                    let state = app_handle_clone.state::<AppState>();
-                                    for path in &event.paths {
-                                        state.invalidate_cache(path);
-                                    }
-
-                                    // 2. Emit event to frontend
-                                    // We use emit_all because this is a global app state change
-                                    let _ = app_handle_clone.emit("models-changed", ());
-                                }
-                                _ => {}
+                    let event = match rx.recv_timeout(Duration::from_secs(2)) {
+                        Ok(Ok(event)) => event,
+                        Ok(Err(e)) => {
+                            tracing::warn!("Watch error: {}", e);
+                            continue;
+                        }
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    };
+
+                    // Filter for relevant events (Create, Remove, Modify, Rename)
+                    match event.kind {
+                        notify::EventKind::Create(_)
+                        | notify::EventKind::Remove(_)
+                        | notify::EventKind::Modify(_) => {
+                            // 1. Invalidate caches for the changed files only, so the
+                            // rescan re-reads just those headers.
+                            // This ensures that if a user replaces a broken model, we re-detect it.
+                            for path in &event.paths {
+                                state.invalidate_cache(path);
                             }
+
+                            // 2. Emit event to frontend
+                            let _ = app_handle_clone.emit("models-changed", ());
                         }
-                        Err(e) => eprintln!("Watch error: {}", e),
+                        _ => {}
                     }
                 }
             });
@@ -49,6 +49,40 @@ pub fn save_with_metadata(
     Ok(())
 }
 
+/// ICC profile and raw EXIF of a source image, for encoders that write them
+/// while streaming instead of grafting them onto an encoded buffer.
+#[derive(Debug, Clone, Default)]
+pub struct SourceMetadata {
+    pub icc_profile: Option<Vec<u8>>,
+    pub exif: Option<Vec<u8>>,
+}
+
+impl SourceMetadata {
+    /// Missing or unreadable metadata reads as empty.
+    pub fn read(source_path: &Path) -> Self {
+        let Ok(source_bytes) = std::fs::read(source_path) else {
+            return Self::default();
+        };
+        Self {
+            icc_profile: extract_icc_profile(&source_bytes).map(|icc| icc.to_vec()),
+            exif: extract_raw_exif(&source_bytes).map(|exif| exif.to_vec()),
+        }
+    }
+}
+
+fn extract_icc_profile(source_data: &[u8]) -> Option<Bytes> {
+    if let Ok(jpeg) = Jpeg::from_bytes(Bytes::copy_from_slice(source_data)) {
+        return jpeg.icc_profile();
+    }
+    if let Ok(png) = Png::from_bytes(Bytes::copy_from_slice(source_data)) {
+        return png.icc_profile();
+    }
+    if let Ok(webp) = WebP::from_bytes(Bytes::copy_from_slice(source_data)) {
+        return webp.icc_profile();
+    }
+    None
+}
+
 fn extract_raw_exif(source_data: &[u8]) -> Option<Bytes> {
     // Try JPEG
     if let Ok(jpeg) = Jpeg::from_bytes(Bytes::copy_from_slice(source_data)) {
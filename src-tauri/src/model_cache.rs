@@ -0,0 +1,253 @@
+use crate::error::AppResult;
+use crate::inference::OrtSession;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const BUDGET_FILE: &str = "model_cache.json";
+
+/// Memory the cache may hold, in MB. None = automatic: a quarter of system
+/// RAM and three quarters of VRAM (unlimited while VRAM is unknown).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CacheBudget {
+    pub ram_mb: Option<u64>,
+    pub vram_mb: Option<u64>,
+}
+
+impl CacheBudget {
+    pub fn load(app_data_dir: &Path) -> Self {
+        let path = app_data_dir.join(BUDGET_FILE);
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring unreadable cache budget {:?}: {}", path, e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, app_data_dir: &Path) -> AppResult<()> {
+        std::fs::create_dir_all(app_data_dir)?;
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(app_data_dir.join(BUDGET_FILE), content)?;
+        Ok(())
+    }
+
+    /// (RAM, VRAM) limits in MB for this machine. Without a known VRAM size
+    /// the automatic VRAM limit is off, rather than a zero that would evict
+    /// every other GPU session on each load.
+    pub fn limits(&self, total_ram_mb: u64, total_vram_mb: Option<u64>) -> Footprint {
+        Footprint {
+            ram_mb: self.ram_mb.unwrap_or(total_ram_mb / 4),
+            vram_mb: self
+                .vram_mb
+                .or(total_vram_mb.map(|mb| mb * 3 / 4))
+                .unwrap_or(u64::MAX),
+        }
+    }
+}
+
+/// Memory a session added when it was loaded, measured around `OrtSession::new`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Footprint {
+    pub ram_mb: u64,
+    pub vram_mb: u64,
+}
+
+impl Footprint {
+    fn exceeds(&self, limits: &Footprint) -> bool {
+        self.ram_mb > limits.ram_mb || self.vram_mb > limits.vram_mb
+    }
+}
+
+pub struct CachedSession<S> {
+    pub id: u64,
+    pub path: PathBuf,
+    pub model_hash: String,
+    pub session: Arc<S>,
+    pub footprint: Footprint,
+    pub pinned: bool,
+    last_used: u64,
+}
+
+impl<S> CachedSession<S> {
+    /// Still held by a running job (or a benchmark).
+    pub fn in_use(&self) -> bool {
+        Arc::strong_count(&self.session) > 1
+    }
+}
+
+/// A cached session as listed to the UI.
+#[derive(Debug, Clone, Serialize)]
+pub struct CachedSessionInfo {
+    pub id: u64,
+    pub model: String,
+    pub path: String,
+    pub requested: String,
+    pub execution_provider: String,
+    pub footprint: Footprint,
+    pub pinned: bool,
+    pub in_use: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelCacheReport {
+    pub budget: CacheBudget,
+    pub limits: Footprint,
+    pub usage: Footprint,
+    pub sessions: Vec<CachedSessionInfo>,
+}
+
+/// Loaded sessions kept across jobs, keyed by model content hash plus the
+/// request (provider, CPU pool, session options). When over budget the least
+/// recently used entries go first; pinned and in-use entries are never evicted.
+pub struct ModelCache<S = OrtSession> {
+    entries: Vec<CachedSession<S>>,
+    pub budget: CacheBudget,
+    clock: u64,
+    next_id: u64,
+}
+
+impl<S> ModelCache<S> {
+    pub fn new(budget: CacheBudget) -> Self {
+        Self {
+            entries: Vec::new(),
+            budget,
+            clock: 0,
+            next_id: 1,
+        }
+    }
+
+    pub fn entries(&self) -> &[CachedSession<S>] {
+        &self.entries
+    }
+
+    pub fn usage(&self) -> Footprint {
+        self.entries
+            .iter()
+            .fold(Footprint::default(), |sum, e| Footprint {
+                ram_mb: sum.ram_mb + e.footprint.ram_mb,
+                vram_mb: sum.vram_mb + e.footprint.vram_mb,
+            })
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// A session for this model content that satisfies the request, marked as
+    /// most recently used.
+    pub fn get(&mut self, model_hash: &str, matches: impl Fn(&S) -> bool) -> Option<Arc<S>> {
+        let now = self.tick();
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|e| e.model_hash == model_hash && matches(&e.session))?;
+        entry.last_used = now;
+        Some(entry.session.clone())
+    }
+
+    /// Adds a session and returns its id. Call `evict_to_fit` afterwards.
+    pub fn insert(
+        &mut self,
+        path: &Path,
+        model_hash: &str,
+        session: Arc<S>,
+        footprint: Footprint,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let last_used = self.tick();
+        self.entries.push(CachedSession {
+            id,
+            path: path.to_path_buf(),
+            model_hash: model_hash.to_string(),
+            session,
+            footprint,
+            pinned: false,
+            last_used,
+        });
+        id
+    }
+
+    /// Evicts least recently used entries until usage is within `limits`.
+    /// `keep` (usually the session just loaded) stays even if it alone is over.
+    pub fn evict_to_fit(&mut self, limits: &Footprint, keep: u64) -> usize {
+        let mut evicted = 0;
+        while self.usage().exceeds(limits) {
+            let victim = self
+                .entries
+                .iter()
+                .filter(|e| e.id != keep && !e.pinned && !e.in_use())
+                .min_by_key(|e| e.last_used)
+                .map(|e| e.id);
+            let Some(id) = victim else {
+                break;
+            };
+            self.evict(id);
+            evicted += 1;
+        }
+        evicted
+    }
+
+    /// Drops an entry. A job still using it keeps its session until it finishes.
+    pub fn evict(&mut self, id: u64) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.id != id);
+        self.entries.len() != before
+    }
+
+    /// Drops every session built from this file, pinned or not: its content changed.
+    pub fn evict_path(&mut self, path: &Path) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.path != path);
+        before - self.entries.len()
+    }
+
+    /// Drops all entries that are not pinned.
+    pub fn evict_unpinned(&mut self) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.pinned);
+        before - self.entries.len()
+    }
+
+    pub fn set_pinned(&mut self, id: u64, pinned: bool) -> bool {
+        match self.entries.iter_mut().find(|e| e.id == id) {
+            Some(entry) => {
+                entry.pinned = pinned;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl ModelCache<OrtSession> {
+    pub fn report(&self, limits: Footprint) -> ModelCacheReport {
+        let mut sessions: Vec<&CachedSession<OrtSession>> = self.entries.iter().collect();
+        sessions.sort_by_key(|e| std::cmp::Reverse(e.last_used));
+        ModelCacheReport {
+            budget: self.budget,
+            limits,
+            usage: self.usage(),
+            sessions: sessions
+                .into_iter()
+                .map(|e| CachedSessionInfo {
+                    id: e.id,
+                    model: e
+                        .path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    path: e.path.to_string_lossy().to_string(),
+                    requested: e.session.provider_id.clone(),
+                    execution_provider: e.session.execution_provider.clone(),
+                    footprint: e.footprint,
+                    pinned: e.pinned,
+                    in_use: e.in_use(),
+                })
+                .collect(),
+        }
+    }
+}
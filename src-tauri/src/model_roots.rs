@@ -0,0 +1,306 @@
+use crate::error::{AppError, AppResult};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Component, Path, PathBuf};
+
+const ROOTS_FILE: &str = "model_roots.json";
+
+/// Per-folder name/description/tensor overrides, keyed by `ModelLocation::key`.
+pub const CONFIG_FILE: &str = "model_config.json";
+
+/// Recursive roots are scanned at most this many folders deep.
+const MAX_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RootKind {
+    /// Shipped with the app, under the resource dir
+    Bundled,
+    /// `<app data>/models`, always present
+    User,
+    /// Added by the user
+    Custom,
+}
+
+/// A folder models are loaded from. Its `id` prefixes the ids of its models,
+/// so they stay stable however the app was launched.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelRoot {
+    pub id: String,
+    pub kind: RootKind,
+    pub path: PathBuf,
+    pub recursive: bool,
+}
+
+/// A user-added folder as persisted in `model_roots.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CustomRoot {
+    path: PathBuf,
+    #[serde(default)]
+    recursive: bool,
+}
+
+/// A model file and where it was found.
+#[derive(Debug, Clone)]
+pub struct ModelLocation {
+    /// `<root id>/<key>`, e.g. "user/anime/4x-AnimeSharp.onnx"
+    pub id: String,
+    pub root: ModelRoot,
+    /// Path relative to the root with `/` separators; the key in its `model_config.json`
+    pub key: String,
+    pub path: PathBuf,
+}
+
+impl ModelLocation {
+    fn new(root: &ModelRoot, key: String) -> Self {
+        Self {
+            id: format!("{}/{}", root.id, key),
+            path: root.path.join(&key),
+            root: root.clone(),
+            key,
+        }
+    }
+
+    pub fn filename(&self) -> String {
+        self.path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Folder holding the `model_config.json` for this model.
+    pub fn config_dir(&self) -> &Path {
+        &self.root.path
+    }
+}
+
+/// Ordered model folders: bundled, then the user's, then added ones. Earlier
+/// roots win when a legacy bare filename matches in several.
+pub struct ModelRoots {
+    roots: Vec<ModelRoot>,
+    app_data_dir: PathBuf,
+    /// Bumped on every change, so the file watcher knows to re-watch
+    pub revision: u64,
+}
+
+impl ModelRoots {
+    pub fn new(bundled: Option<PathBuf>, app_data_dir: &Path) -> Self {
+        let mut roots = Vec::new();
+        if let Some(path) = bundled {
+            roots.push(ModelRoot {
+                id: "bundled".to_string(),
+                kind: RootKind::Bundled,
+                path,
+                recursive: false,
+            });
+        }
+
+        let user_dir = app_data_dir.join("models");
+        if let Err(e) = std::fs::create_dir_all(&user_dir) {
+            tracing::warn!("Failed to create user models dir {:?}: {}", user_dir, e);
+        }
+        roots.push(ModelRoot {
+            id: "user".to_string(),
+            kind: RootKind::User,
+            path: user_dir,
+            recursive: true,
+        });
+
+        let custom: Vec<CustomRoot> = std::fs::read_to_string(app_data_dir.join(ROOTS_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        roots.extend(
+            custom
+                .into_iter()
+                .map(|c| Self::custom(c.path, c.recursive)),
+        );
+
+        Self {
+            roots,
+            app_data_dir: app_data_dir.to_path_buf(),
+            revision: 0,
+        }
+    }
+
+    fn custom(path: PathBuf, recursive: bool) -> ModelRoot {
+        let hash = Sha256::digest(path.to_string_lossy().as_bytes());
+        ModelRoot {
+            id: format!("folder-{:x}", hash)[..15].to_string(),
+            kind: RootKind::Custom,
+            path,
+            recursive,
+        }
+    }
+
+    pub fn roots(&self) -> &[ModelRoot] {
+        &self.roots
+    }
+
+    /// `<app data>/models`, where imported and downloaded models go.
+    pub fn user_root(&self) -> &ModelRoot {
+        self.roots
+            .iter()
+            .find(|r| r.kind == RootKind::User)
+            .expect("the user model folder is always a root")
+    }
+
+    fn save(&self) -> AppResult<()> {
+        let custom: Vec<CustomRoot> = self
+            .roots
+            .iter()
+            .filter(|r| r.kind == RootKind::Custom)
+            .map(|r| CustomRoot {
+                path: r.path.clone(),
+                recursive: r.recursive,
+            })
+            .collect();
+        std::fs::create_dir_all(&self.app_data_dir)?;
+        let content = serde_json::to_string_pretty(&custom)?;
+        std::fs::write(self.app_data_dir.join(ROOTS_FILE), content)?;
+        Ok(())
+    }
+
+    /// Adds a folder, or updates `recursive` if it is already a custom root.
+    pub fn add(&mut self, path: &Path, recursive: bool) -> AppResult<ModelRoot> {
+        if !path.is_dir() {
+            return Err(AppError::IoError(format!("Not a folder: {:?}", path)));
+        }
+        let path = path.canonicalize()?;
+        // A recursive folder already lists everything below it, so a root may
+        // only sit inside another (or hold one) when the outer one is flat.
+        // Otherwise its models would be listed twice.
+        let overlapping = self.roots.iter().find(|r| {
+            // Built-in roots are stored as given
+            let other = r.path.canonicalize().unwrap_or_else(|_| r.path.clone());
+            other != path
+                && ((r.recursive && path.starts_with(&other))
+                    || (recursive && other.starts_with(&path)))
+        });
+        if let Some(other) = overlapping {
+            return Err(AppError::Unknown(format!(
+                "{:?} overlaps the model folder {:?}",
+                path, other.path
+            )));
+        }
+        if let Some(existing) = self.roots.iter_mut().find(|r| r.path == path) {
+            if existing.kind != RootKind::Custom {
+                return Err(AppError::Unknown(format!(
+                    "{:?} is already a model folder",
+                    path
+                )));
+            }
+            existing.recursive = recursive;
+        } else {
+            self.roots.push(Self::custom(path.clone(), recursive));
+        }
+        self.save()?;
+        self.revision += 1;
+        Ok(self.roots.iter().find(|r| r.path == path).unwrap().clone())
+    }
+
+    /// Removes a user-added folder. Its files are left alone.
+    pub fn remove(&mut self, id: &str) -> AppResult<()> {
+        let Some(index) = self.roots.iter().position(|r| r.id == id) else {
+            return Err(AppError::Unknown(format!("No model folder {}", id)));
+        };
+        if self.roots[index].kind != RootKind::Custom {
+            return Err(AppError::Unknown(
+                "Built-in model folders can't be removed".to_string(),
+            ));
+        }
+        self.roots.remove(index);
+        self.save()?;
+        self.revision += 1;
+        Ok(())
+    }
+
+    /// Finds a model by id. Bare filenames (ids before model roots existed)
+    /// resolve to the first root that has the file at its top level.
+    pub fn resolve(&self, id: &str) -> AppResult<ModelLocation> {
+        let not_found = || AppError::ModelLoadError(format!("Model not found: {}", id));
+
+        if let Some((root_id, key)) = id.split_once('/') {
+            if let Some(root) = self.roots.iter().find(|r| r.id == root_id) {
+                // Keys come from the frontend: never let them leave the root
+                let inside = Path::new(key)
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_)));
+                let location = ModelLocation::new(root, key.to_string());
+                if inside && location.path.is_file() {
+                    return Ok(location);
+                }
+                return Err(not_found());
+            }
+        }
+
+        let filename = if id.ends_with(".onnx") {
+            id.to_string()
+        } else {
+            format!("{}.onnx", id)
+        };
+        if Path::new(&filename).components().count() != 1 {
+            return Err(not_found());
+        }
+        self.roots
+            .iter()
+            .map(|root| ModelLocation::new(root, filename.clone()))
+            .find(|location| location.path.is_file())
+            .ok_or_else(not_found)
+    }
+
+    /// The model a path on disk belongs to, if it is inside a root.
+    pub fn locate(&self, path: &Path) -> Option<ModelLocation> {
+        self.roots.iter().find_map(|root| {
+            let relative = path.strip_prefix(&root.path).ok()?;
+            if !root.recursive && relative.components().count() != 1 {
+                return None;
+            }
+            Some(ModelLocation::new(root, key_of(relative)))
+        })
+    }
+
+    /// Every `.onnx` file under a root, sorted by key.
+    pub fn model_files(root: &ModelRoot) -> Vec<ModelLocation> {
+        let mut keys = Vec::new();
+        let depth = if root.recursive { MAX_DEPTH } else { 0 };
+        collect_models(&root.path, Path::new(""), depth, &mut keys);
+        keys.sort();
+        keys.into_iter()
+            .map(|key| ModelLocation::new(root, key))
+            .collect()
+    }
+}
+
+fn key_of(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn collect_models(dir: &Path, relative: &Path, depth: usize, keys: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        // Hidden files and folders
+        if name.to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            // Symlinked folders are skipped so a loop can't recurse forever
+            if depth > 0 {
+                collect_models(&path, &relative.join(&name), depth - 1, keys);
+            }
+        } else if path.extension().and_then(|s| s.to_str()) == Some("onnx") {
+            keys.push(key_of(&relative.join(&name)));
+        }
+    }
+}
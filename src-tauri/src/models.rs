@@ -1,10 +1,16 @@
 use crate::error::{AppError, AppResult};
+use crate::inference::SessionOptions;
+use crate::model_roots::{ModelLocation, ModelRoot, ModelRoots, CONFIG_FILE};
+use crate::onnx::OnnxInfo;
+use crate::tensor_format::TensorFormatOverride;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelManifest {
+    // Stable id: "<root id>/<path within the root>"
     pub id: String,
     pub name: String,
     pub description: String,
@@ -12,6 +18,118 @@ pub struct ModelManifest {
     pub scale: u32,
     pub alignment: u32,
     pub batch_size: Option<u32>,
+    // Extra model inputs the UI can expose, e.g. a denoise strength slider
+    #[serde(default)]
+    pub params: Vec<ModelParam>,
+    // Graph header: opset, producer, input/output names, types and shapes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub onnx: Option<OnnxInfo>,
+    // Self-description from ONNX metadata_props, or the user's override
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    // Intended content, e.g. "anime" or "photo"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tile_size: Option<u32>,
+    // Model folder the file was found in
+    #[serde(default)]
+    pub root: String,
+}
+
+/// What a model author embedded in the ONNX `metadata_props`, so a model can be
+/// distributed self-describing. Keys are lowercase; unknown keys are ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ModelMetadata {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub architecture: Option<String>,
+    pub author: Option<String>,
+    pub license: Option<String>,
+    pub content: Option<String>,
+    pub scale: Option<u32>,
+    /// Recommended tile size
+    pub tile_size: Option<u32>,
+    /// Input sides must be a multiple of this (window attention)
+    pub alignment: Option<u32>,
+}
+
+impl ModelMetadata {
+    pub fn from_props(props: &HashMap<String, String>) -> Self {
+        let text = |keys: &[&str]| {
+            keys.iter()
+                .find_map(|key| props.get(*key))
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        // "4", "4x" and "4X" all mean 4
+        let number = |keys: &[&str]| {
+            text(keys)
+                .and_then(|v| v.trim_end_matches(['x', 'X']).parse::<u32>().ok())
+                .filter(|&n| n > 0)
+        };
+        Self {
+            name: text(&["name", "model_name"]),
+            description: text(&["description"]),
+            architecture: text(&["architecture", "arch"]),
+            author: text(&["author"]),
+            license: text(&["license"]),
+            content: text(&["content", "intended_content"]),
+            scale: number(&["scale"]),
+            tile_size: number(&["tile_size", "recommended_tile_size"]),
+            alignment: number(&["alignment", "input_alignment"]),
+        }
+    }
+
+    /// Metadata of a model file; empty if the header can't be read.
+    pub fn read(path: &Path) -> Self {
+        OnnxInfo::read(path)
+            .map(|info| Self::from_props(&info.metadata))
+            .unwrap_or_default()
+    }
+}
+
+/// A file in a model folder that could not be used, and why.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RejectedModel {
+    pub id: String,
+    pub filename: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelScan {
+    pub models: Vec<ModelManifest>,
+    pub rejected: Vec<RejectedModel>,
+    pub roots: Vec<ModelRoot>,
+}
+
+/// An extra named model input beside the image, like a noise level, a strength
+/// scalar or a JPEG quality hint. Its element type and shape come from the model;
+/// inputs of rank 4 are filled as a constant map the size of each tile.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelParam {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    pub default: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f32>,
+}
+
+impl ModelParam {
+    /// The job's value if given, otherwise the default, kept within min/max.
+    pub fn resolve(&self, value: Option<f32>) -> f32 {
+        let value = value.unwrap_or(self.default);
+        let value = self.min.map_or(value, |min| value.max(min));
+        self.max.map_or(value, |max| value.min(max))
+    }
 }
 
 impl ModelManifest {
@@ -32,6 +150,14 @@ impl ModelManifest {
             scale,
             alignment,
             batch_size,
+            params: Vec::new(),
+            onnx: None,
+            architecture: None,
+            author: None,
+            license: None,
+            content: None,
+            tile_size: None,
+            root: String::new(),
         }
     }
 }
@@ -39,6 +165,22 @@ impl ModelManifest {
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ModelUserConfig {
     pub overrides: HashMap<String, UserModelInfo>,
+    // Where downloaded models came from, kept apart from the user's own edits
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub catalog: HashMap<String, CatalogOrigin>,
+}
+
+/// What the catalog index said about a downloaded model. Its texts only fill in
+/// what neither the user's override nor the ONNX metadata provide.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct CatalogOrigin {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub license: Option<String>,
+    pub url: String,
+    pub sha256: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -46,30 +188,62 @@ pub struct UserModelInfo {
     pub name: String,
     pub description: String,
     pub batch_size: Option<u32>,
+    // Tensor conventions; fields set here win over ONNX metadata and detection
+    pub tensor_format: Option<TensorFormatOverride>,
+    // Extra inputs of multi-input models, by ONNX input name
+    #[serde(default)]
+    pub params: Vec<ModelParam>,
+    // ORT session settings (optimization level, threads, memory pattern)
+    pub session_options: Option<SessionOptions>,
+    // Win over the model's own metadata_props
+    #[serde(default)]
+    pub architecture: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub license: Option<String>,
+    // Input size multiple, for architectures the filename guess gets wrong
+    #[serde(default)]
+    pub alignment: Option<u32>,
 }
 
 impl ModelUserConfig {
     pub fn load(path: &Path) -> Self {
+        Self::load_strict(path).unwrap_or_else(|e| {
+            // We log this as it indicates a persistent issue.
+            eprintln!("Warning: {}. Returning default.", e);
+            Self::default()
+        })
+    }
+
+    /// Like `load`, but fails if the file exists and still can't be parsed after
+    /// retrying. Paths that load, edit and `save` the file use this, so a broken
+    /// config is never overwritten with defaults and its other entries lost.
+    pub fn load_strict(path: &Path) -> AppResult<Self> {
+        // Most folders have no config: nothing to wait for
+        if !path.exists() {
+            return Ok(Self::default());
+        }
         let mut attempts = 0;
-        while attempts < 5 {
-            if let Ok(file) = std::fs::File::open(path) {
-                if let Ok(config) = serde_json::from_reader(&file) {
-                    return config;
-                }
+        loop {
+            let error = match std::fs::File::open(path) {
+                Ok(file) => match serde_json::from_reader(&file) {
+                    Ok(config) => return Ok(config),
+                    Err(e) => e.to_string(),
+                },
+                Err(e) => e.to_string(),
+            };
+            attempts += 1;
+            if attempts == 5 {
+                return Err(AppError::Unknown(format!(
+                    "Failed to load {} ({}). Fix or remove it to edit this folder's models.",
+                    path.display(),
+                    error
+                )));
             }
             // If we fail (file locked, empty, partial write), wait and retry
             std::thread::sleep(std::time::Duration::from_millis(100));
-            attempts += 1;
-        }
-
-        // If it still fails, try one last time or return default.
-        // We log this as it indicates a persistent issue.
-        if path.exists() {
-            eprintln!(
-                "Warning: Failed to load model_config.json after retries. Returning default."
-            );
         }
-        Self::default()
     }
 
     pub fn save(&self, path: &Path) -> AppResult<()> {
@@ -79,87 +253,216 @@ impl ModelUserConfig {
     }
 }
 
-pub struct ModelScanner;
+/// The `model_config.json` entry of a model, if any.
+pub fn user_model_info(models_dir: &Path, id: &str) -> Option<UserModelInfo> {
+    let config_path = models_dir.join(CONFIG_FILE);
+    if !config_path.exists() {
+        return None;
+    }
+    ModelUserConfig::load(&config_path).overrides.remove(id)
+}
+
+/// Tensor conventions declared for a model in `model_config.json`, if any.
+pub fn user_tensor_format(models_dir: &Path, id: &str) -> Option<TensorFormatOverride> {
+    user_model_info(models_dir, id).and_then(|info| info.tensor_format)
+}
+
+/// ORT session settings declared for a model in `model_config.json`.
+pub fn user_session_options(models_dir: &Path, id: &str) -> SessionOptions {
+    user_model_info(models_dir, id)
+        .and_then(|info| info.session_options)
+        .unwrap_or_default()
+}
+
+/// Extra inputs declared for a model in `model_config.json`.
+pub fn user_params(models_dir: &Path, id: &str) -> Vec<ModelParam> {
+    user_model_info(models_dir, id)
+        .map(|info| info.params)
+        .unwrap_or_default()
+}
+
+/// Architecture of a model: the user's override, else its ONNX metadata.
+pub fn model_architecture(models_dir: &Path, id: &str, declared: &ModelMetadata) -> Option<String> {
+    user_model_info(models_dir, id)
+        .and_then(|info| info.architecture)
+        .or_else(|| declared.architecture.clone())
+}
+
+/// Input alignment of a model: the user's override, else its ONNX metadata,
+/// else a guess from the architecture (declared, or in the filename).
+pub fn model_alignment(
+    models_dir: &Path,
+    id: &str,
+    declared: &ModelMetadata,
+    architecture: &str,
+) -> u32 {
+    user_model_info(models_dir, id)
+        .and_then(|info| info.alignment)
+        .or(declared.alignment)
+        .unwrap_or_else(|| default_alignment(architecture))
+}
+
+/// Input alignment guessed from the architecture (declared, or in the filename):
+/// 8 for HAT, 16 for DAT, 1 for others.
+pub fn default_alignment(filename: &str) -> u32 {
+    let arch_lower = filename.to_lowercase();
+    if arch_lower.contains("hat") {
+        8
+    } else if arch_lower.contains("dat") {
+        16
+    } else {
+        1
+    }
+}
+
+/// Builds manifests for the model folders. Graph headers are cached by
+/// modification time, so a rescan only parses files that changed.
+#[derive(Default)]
+pub struct ModelScanner {
+    headers: HashMap<PathBuf, (SystemTime, OnnxInfo)>,
+}
 
 impl ModelScanner {
-    // NEW: Scan a single file efficiently using provided config
-    pub fn scan_file(path: &Path, user_config: &ModelUserConfig) -> AppResult<ModelManifest> {
-        // println!("[DIAGNOSTIC] [ModelScanner] Scanning file: {:?}", path);
+    fn header(&mut self, path: &Path) -> AppResult<OnnxInfo> {
+        let modified = std::fs::metadata(path)?.modified()?;
+        if let Some((cached, info)) = self.headers.get(path) {
+            if *cached == modified {
+                return Ok(info.clone());
+            }
+        }
+        let info = OnnxInfo::read(path)?;
+        self.headers
+            .insert(path.to_path_buf(), (modified, info.clone()));
+        Ok(info)
+    }
+
+    /// Forget a file's header, e.g. when the watcher sees it change.
+    pub fn invalidate(&mut self, path: &Path) {
+        self.headers.remove(path);
+    }
+
+    // Scan a single file using provided config (the one of its model folder)
+    pub fn scan_file(
+        &mut self,
+        location: &ModelLocation,
+        user_config: &ModelUserConfig,
+    ) -> AppResult<ModelManifest> {
+        let path = &location.path;
         if path.extension().and_then(|s| s.to_str()) != Some("onnx") {
             return Err(AppError::Unknown("File is not an ONNX model".to_string()));
         }
 
-        let filename = path.file_name().unwrap().to_string_lossy().to_string();
-        let id = filename.clone();
+        let filename = location.filename();
+        let key = &location.key;
 
-        // FAST SCAN: Infer from filename only. No heavy inspection.
-        let scale = if filename.contains("x2") {
-            2
-        } else if filename.contains("x3") {
-            3
-        } else {
-            4
-        };
-
-        let arch_lower = filename.to_lowercase();
+        // Header only: weights are skipped, nothing is loaded into ORT.
+        // Fixed-size models get their real scale; dynamic ones keep the filename
+        // guess until `detect_scale` runs on first load.
+        let onnx = self.header(path)?;
+        let user_format = user_config
+            .overrides
+            .get(key)
+            .and_then(|info| info.tensor_format.as_ref());
+        let format = onnx.image_format(user_format)?;
+        let declared = ModelMetadata::from_props(&onnx.metadata);
+        let scale = onnx
+            .static_scale(&format)
+            .or(declared.scale)
+            .unwrap_or_else(|| {
+                if filename.contains("x2") {
+                    2
+                } else if filename.contains("x3") {
+                    3
+                } else {
+                    4
+                }
+            });
 
-        // Default alignment (8 for HAT/Swin, 1 for others)
-        let alignment = if arch_lower.contains("hat") {
-            8
-        } else if arch_lower.contains("dat") {
-            16
-        } else {
-            1
+        // Precedence: model_config.json override, then ONNX metadata, then the
+        // catalog the model was downloaded from, then filename
+        let user = user_config.overrides.get(key);
+        let origin = user_config.catalog.get(key);
+        let user_text =
+            |text: fn(&UserModelInfo) -> &String| user.map(text).filter(|t| !t.is_empty()).cloned();
+        let origin_text = |text: fn(&CatalogOrigin) -> &String| {
+            origin.map(text).filter(|t| !t.is_empty()).cloned()
         };
-
-        let (name, description, batch_size) = if let Some(info) = user_config.overrides.get(&id) {
-            // println!(
-            //     "[DIAGNOSTIC] [ModelScanner] Found override for {}: batch_size={:?}",
-            //     id, info.batch_size
-            // );
-            (info.name.clone(), info.description.clone(), info.batch_size)
-        } else {
-            // println!("[DIAGNOSTIC] [ModelScanner] No override found for {}", id);
-            (
-                filename.replace(".onnx", ""),
-                format!("{}x Upscaling Model", scale),
-                None,
-            )
+        let name = user_text(|info| &info.name)
+            .or(declared.name)
+            .or_else(|| origin_text(|origin| &origin.name))
+            .unwrap_or_else(|| filename.replace(".onnx", ""));
+        let description = user_text(|info| &info.description)
+            .or(declared.description)
+            .or_else(|| origin_text(|origin| &origin.description))
+            .unwrap_or_else(|| format!("{}x Upscaling Model", scale));
+        let user_field = |field: fn(&UserModelInfo) -> &Option<String>| {
+            user.and_then(|info| field(info).clone())
         };
+        let architecture = user_field(|info| &info.architecture).or(declared.architecture);
+        let author = user_field(|info| &info.author).or(declared.author);
+        let license = user_field(|info| &info.license)
+            .or(declared.license)
+            .or_else(|| origin.and_then(|origin| origin.license.clone()));
+        let (batch_size, params) = user
+            .map(|info| (info.batch_size, info.params.clone()))
+            .unwrap_or_default();
 
-        Ok(ModelManifest::new(
-            &id,
-            &name,
-            &description,
-            &filename,
-            scale,
-            alignment,
-            batch_size,
-        ))
-    }
-
-    pub fn scan_directory(dir: &Path) -> AppResult<Vec<ModelManifest>> {
-        let mut manifests = Vec::new();
+        let alignment = user
+            .and_then(|info| info.alignment)
+            .or(declared.alignment)
+            .unwrap_or_else(|| default_alignment(architecture.as_deref().unwrap_or(&filename)));
 
-        if !dir.exists() {
-            return Ok(manifests);
-        }
+        Ok(ModelManifest {
+            params,
+            onnx: Some(onnx),
+            architecture,
+            author,
+            license,
+            content: declared.content,
+            tile_size: declared.tile_size,
+            root: location.root.id.clone(),
+            ..ModelManifest::new(
+                &location.id,
+                &name,
+                &description,
+                &filename,
+                scale,
+                alignment,
+                batch_size,
+            )
+        })
+    }
 
-        // Load config ONCE for the entire directory scan
-        let config_path = dir.join("model_config.json");
-        let user_config = ModelUserConfig::load(&config_path);
+    /// Every model in every folder, in root order.
+    pub fn scan_roots(&mut self, roots: &[ModelRoot]) -> ModelScan {
+        let mut scan = ModelScan {
+            roots: roots.to_vec(),
+            ..Default::default()
+        };
+        let mut seen = std::collections::HashSet::new();
 
-        for entry in std::fs::read_dir(dir).map_err(|e| AppError::Unknown(e.to_string()))? {
-            let entry = entry.map_err(|e| AppError::Unknown(e.to_string()))?;
-            let path = entry.path();
+        for root in roots {
+            // Load config ONCE per folder
+            let user_config = ModelUserConfig::load(&root.path.join(CONFIG_FILE));
 
-            if path.extension().and_then(|s| s.to_str()) == Some("onnx") {
-                if let Ok(manifest) = Self::scan_file(&path, &user_config) {
-                    manifests.push(manifest);
+            for location in ModelRoots::model_files(root) {
+                seen.insert(location.path.clone());
+                match self.scan_file(&location, &user_config) {
+                    Ok(manifest) => scan.models.push(manifest),
+                    Err(e) => {
+                        tracing::warn!("Skipping model {:?}: {}", location.path, e);
+                        scan.rejected.push(RejectedModel {
+                            filename: location.filename(),
+                            id: location.id,
+                            reason: e.to_string(),
+                        });
+                    }
                 }
             }
         }
 
-        Ok(manifests)
+        // Deleted files
+        self.headers.retain(|path, _| seen.contains(path));
+        scan
     }
 }
@@ -0,0 +1,527 @@
+//! Reads the header of an ONNX file (opset, producer, metadata, graph inputs and
+//! outputs) without loading it into ONNX Runtime. Only the protobuf fields needed
+//! for that are decoded; weights are seeked over and nodes are read for their op
+//! type and connections only, so scanning a large model stays cheap.
+
+use crate::error::{AppError, AppResult};
+use crate::tensor_format::{Quantization, TensorFormat, TensorFormatOverride};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+
+/// Strings longer than this (names, producer) mean the file is not what it claims.
+const MAX_STRING_LEN: u64 = 64 * 1024;
+
+/// Element types the engine can feed and read (see `inference::TensorData`).
+const IMAGE_DTYPES: [&str; 5] = ["float32", "float16", "bfloat16", "uint8", "int8"];
+
+/// One axis of a declared tensor shape.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Dim {
+    Fixed(i64),
+    /// Symbolic (e.g. "height") or unnamed
+    Dynamic(Option<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TensorInfo {
+    pub name: String,
+    pub dtype: String,
+    pub shape: Vec<Dim>,
+}
+
+impl TensorInfo {
+    /// Shape in ORT's convention: -1 for every axis that is not a fixed size.
+    pub fn dims(&self) -> Vec<i64> {
+        self.shape
+            .iter()
+            .map(|d| match d {
+                Dim::Fixed(n) if *n > 0 => *n,
+                _ => -1,
+            })
+            .collect()
+    }
+}
+
+/// What the scanner learns from the graph header.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OnnxInfo {
+    pub ir_version: i64,
+    /// Default-domain opset
+    pub opset: Option<i64>,
+    pub producer: Option<String>,
+    /// Graph inputs, without initializers listed as inputs by older exporters
+    pub inputs: Vec<TensorInfo>,
+    pub outputs: Vec<TensorInfo>,
+    /// `metadata_props` set by the model author (name, scale, license, ...)
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// Quantization of the image input and output, from the parameters of the
+    /// DequantizeLinear right after the input and the QuantizeLinear right before
+    /// the output (QDQ models)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_quant: Option<Quantization>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_quant: Option<Quantization>,
+}
+
+impl OnnxInfo {
+    pub fn read(path: &Path) -> AppResult<Self> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader<R: Read + Seek>(reader: R) -> AppResult<Self> {
+        let mut reader = ProtoReader::new(reader)?;
+        if reader.len == 0 {
+            return Err(malformed("file is empty"));
+        }
+
+        let mut info = OnnxInfo {
+            ir_version: 0,
+            opset: None,
+            producer: None,
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            metadata: HashMap::new(),
+            input_quant: None,
+            output_quant: None,
+        };
+        let (mut producer, mut producer_version) = (String::new(), String::new());
+        let mut has_graph = false;
+        let mut initializers = HashSet::new();
+        let mut qdq = QdqScan::default();
+
+        // ModelProto
+        let end = reader.len;
+        reader.message(end, &mut |r, field, value| {
+            match (field, value) {
+                (1, Value::Varint(v)) => info.ir_version = v as i64,
+                (2, Value::Len(len)) => producer = r.string(len)?,
+                (3, Value::Len(len)) => producer_version = r.string(len)?,
+                (7, Value::Len(len)) => {
+                    has_graph = true;
+                    r.graph(len, &mut info, &mut initializers, &mut qdq)?;
+                }
+                (8, Value::Len(len)) => {
+                    let (domain, version) = r.opset(len)?;
+                    if domain.is_empty() || domain == "ai.onnx" {
+                        info.opset = Some(version);
+                    }
+                }
+                (14, Value::Len(len)) => {
+                    let (key, value) = r.entry(len)?;
+                    info.metadata.insert(key, value);
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+
+        if info.ir_version <= 0 || !has_graph {
+            return Err(AppError::ModelLoadError(
+                "Not an ONNX model (no IR version or graph)".to_string(),
+            ));
+        }
+        info.inputs.retain(|i| !initializers.contains(&i.name));
+        if let Some(input) = info.inputs.first() {
+            info.input_quant = qdq.quantization("DequantizeLinear", &input.name);
+        }
+        if let Some(output) = info.outputs.first() {
+            info.output_quant = qdq.quantization("QuantizeLinear", &output.name);
+        }
+        if !producer.is_empty() {
+            info.producer = Some(
+                format!("{} {}", producer, producer_version)
+                    .trim()
+                    .to_string(),
+            );
+        }
+        Ok(info)
+    }
+
+    /// Tensor conventions of the image input/output, or why this is not an image model.
+    /// The engine feeds the first input and reads the first output.
+    pub fn image_format(&self, user: Option<&TensorFormatOverride>) -> AppResult<TensorFormat> {
+        let reject = |reason: String| Err(AppError::ModelLoadError(reason));
+        let (Some(input), Some(output)) = (self.inputs.first(), self.outputs.first()) else {
+            return reject("Model has no graph inputs or outputs".to_string());
+        };
+        for (side, tensor) in [("input", input), ("output", output)] {
+            if tensor.shape.len() != 4 {
+                return reject(format!(
+                    "Not an image model: {} '{}' has rank {}, expected 4",
+                    side,
+                    tensor.name,
+                    tensor.shape.len()
+                ));
+            }
+            if !IMAGE_DTYPES.contains(&tensor.dtype.as_str()) {
+                return reject(format!(
+                    "Unsupported {} type {} on '{}'",
+                    side, tensor.dtype, tensor.name
+                ));
+            }
+        }
+
+        let detected = TensorFormat {
+            input_quant: self.input_quant,
+            output_quant: self.output_quant,
+            ..TensorFormat::detect(&input.dims(), &output.dims())
+        };
+        let format = match user {
+            Some(user) => user.clone().apply(detected),
+            None => detected,
+        };
+        format.validate()?;
+        Ok(format)
+    }
+
+    /// Scale from fixed spatial sizes, when both sides declare them and agree.
+    pub fn static_scale(&self, format: &TensorFormat) -> Option<u32> {
+        let (input, output) = (self.inputs.first()?.dims(), self.outputs.first()?.dims());
+        let (h_axis, w_axis) = format.layout.spatial_axes();
+        let ratio = |axis: usize| {
+            let (i, o) = (*input.get(axis)?, *output.get(axis)?);
+            (i > 0 && o > 0 && o % i == 0).then_some(o / i)
+        };
+        match (ratio(h_axis)?, ratio(w_axis)?) {
+            (h, w) if h == w && h > 0 => Some(h as u32),
+            _ => None,
+        }
+    }
+}
+
+fn malformed(reason: &str) -> AppError {
+    AppError::ModelLoadError(format!("Malformed ONNX file: {}", reason))
+}
+
+fn dtype_name(elem_type: u64) -> String {
+    match elem_type {
+        1 => "float32",
+        2 => "uint8",
+        3 => "int8",
+        4 => "uint16",
+        5 => "int16",
+        6 => "int32",
+        7 => "int64",
+        8 => "string",
+        9 => "bool",
+        10 => "float16",
+        11 => "float64",
+        12 => "uint32",
+        13 => "uint64",
+        16 => "bfloat16",
+        other => return format!("type {}", other),
+    }
+    .to_string()
+}
+
+// QuantizeLinear/DequantizeLinear nodes and single-value initializers: enough
+// to find the quantization parameters of the image tensors
+#[derive(Default)]
+struct QdqScan {
+    // (op type, inputs, outputs)
+    nodes: Vec<(String, Vec<String>, Vec<String>)>,
+    scalars: HashMap<String, f32>,
+}
+
+impl QdqScan {
+    // Parameters of the `op` node reading (DequantizeLinear) or writing
+    // (QuantizeLinear) `tensor`, when they are constant
+    fn quantization(&self, op: &str, tensor: &str) -> Option<Quantization> {
+        let (_, inputs, _) = self.nodes.iter().find(|(op_type, inputs, outputs)| {
+            let end = if op == "DequantizeLinear" {
+                inputs.first()
+            } else {
+                outputs.first()
+            };
+            op_type == op && end.is_some_and(|name| name == tensor)
+        })?;
+        let scale = *self.scalars.get(inputs.get(1)?)?;
+        let zero_point = match inputs.get(2).filter(|name| !name.is_empty()) {
+            Some(name) => *self.scalars.get(name)? as i32,
+            None => 0,
+        };
+        (scale.is_finite() && scale > 0.0).then_some(Quantization { scale, zero_point })
+    }
+}
+
+enum Value {
+    Varint(u64),
+    /// Length-delimited payload starting at the current position
+    Len(u64),
+}
+
+type Handler<'a, R> = dyn FnMut(&mut ProtoReader<R>, u32, Value) -> AppResult<()> + 'a;
+
+/// Minimal protobuf wire-format reader over a seekable stream.
+struct ProtoReader<R> {
+    inner: BufReader<R>,
+    pos: u64,
+    len: u64,
+}
+
+impl<R: Read + Seek> ProtoReader<R> {
+    fn new(mut inner: R) -> AppResult<Self> {
+        let len = inner.seek(std::io::SeekFrom::End(0))?;
+        inner.rewind()?;
+        Ok(Self {
+            inner: BufReader::new(inner),
+            pos: 0,
+            len,
+        })
+    }
+
+    fn byte(&mut self) -> AppResult<u8> {
+        let mut b = [0u8; 1];
+        self.inner
+            .read_exact(&mut b)
+            .map_err(|_| malformed("unexpected end of file"))?;
+        self.pos += 1;
+        Ok(b[0])
+    }
+
+    fn varint(&mut self) -> AppResult<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            value |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(malformed("varint too long"))
+    }
+
+    fn seek_to(&mut self, target: u64) -> AppResult<()> {
+        if target > self.len {
+            return Err(malformed("field runs past the end of the file"));
+        }
+        self.inner.seek_relative(target as i64 - self.pos as i64)?;
+        self.pos = target;
+        Ok(())
+    }
+
+    fn bytes(&mut self, len: u64) -> AppResult<Vec<u8>> {
+        if len > MAX_STRING_LEN {
+            return Err(malformed("string field too long"));
+        }
+        let mut buf = vec![0u8; len as usize];
+        self.inner
+            .read_exact(&mut buf)
+            .map_err(|_| malformed("unexpected end of file"))?;
+        self.pos += len;
+        Ok(buf)
+    }
+
+    fn string(&mut self, len: u64) -> AppResult<String> {
+        Ok(String::from_utf8_lossy(&self.bytes(len)?).into_owned())
+    }
+
+    /// Walks the fields of a message ending at `end`. Length-delimited fields the
+    /// handler does not read (fully or at all) are skipped.
+    fn message(&mut self, end: u64, handler: &mut Handler<'_, R>) -> AppResult<()> {
+        while self.pos < end {
+            let key = self.varint()?;
+            let field = (key >> 3) as u32;
+            match key & 7 {
+                0 => {
+                    let v = self.varint()?;
+                    handler(self, field, Value::Varint(v))?;
+                }
+                1 => self.seek_to(self.pos + 8)?,
+                2 => {
+                    let len = self.varint()?;
+                    let field_end = self.pos.checked_add(len).filter(|&e| e <= end);
+                    let Some(field_end) = field_end else {
+                        return Err(malformed("field runs past its message"));
+                    };
+                    handler(self, field, Value::Len(len))?;
+                    self.seek_to(field_end)?;
+                }
+                5 => self.seek_to(self.pos + 4)?,
+                wire => return Err(malformed(&format!("unsupported wire type {}", wire))),
+            }
+        }
+        if self.pos != end {
+            return Err(malformed("field runs past its message"));
+        }
+        Ok(())
+    }
+
+    fn opset(&mut self, len: u64) -> AppResult<(String, i64)> {
+        let (mut domain, mut version) = (String::new(), 0);
+        self.message(self.pos + len, &mut |r, field, value| {
+            match (field, value) {
+                (1, Value::Len(len)) => domain = r.string(len)?,
+                (2, Value::Varint(v)) => version = v as i64,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok((domain, version))
+    }
+
+    /// StringStringEntryProto
+    fn entry(&mut self, len: u64) -> AppResult<(String, String)> {
+        let (mut key, mut value) = (String::new(), String::new());
+        self.message(self.pos + len, &mut |r, field, v| {
+            match (field, v) {
+                (1, Value::Len(len)) => key = r.string(len)?,
+                (2, Value::Len(len)) => value = r.string(len)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok((key, value))
+    }
+
+    fn graph(
+        &mut self,
+        len: u64,
+        info: &mut OnnxInfo,
+        initializers: &mut HashSet<String>,
+        qdq: &mut QdqScan,
+    ) -> AppResult<()> {
+        self.message(self.pos + len, &mut |r, field, value| {
+            match (field, value) {
+                (1, Value::Len(len)) => {
+                    let node = r.node(len)?;
+                    if node.0 == "QuantizeLinear" || node.0 == "DequantizeLinear" {
+                        qdq.nodes.push(node);
+                    }
+                }
+                (5, Value::Len(len)) => {
+                    let (name, scalar) = r.initializer(len)?;
+                    if let Some(value) = scalar {
+                        qdq.scalars.insert(name.clone(), value);
+                    }
+                    initializers.insert(name);
+                }
+                (11, Value::Len(len)) => info.inputs.push(r.value_info(len)?),
+                (12, Value::Len(len)) => info.outputs.push(r.value_info(len)?),
+                _ => {}
+            }
+            Ok(())
+        })
+    }
+
+    /// NodeProto: (op type, inputs, outputs). Attributes are skipped.
+    fn node(&mut self, len: u64) -> AppResult<(String, Vec<String>, Vec<String>)> {
+        let (mut op_type, mut inputs, mut outputs) = (String::new(), Vec::new(), Vec::new());
+        self.message(self.pos + len, &mut |r, field, value| {
+            match (field, value) {
+                (1, Value::Len(len)) => inputs.push(r.string(len)?),
+                (2, Value::Len(len)) => outputs.push(r.string(len)?),
+                (4, Value::Len(len)) => op_type = r.string(len)?,
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok((op_type, inputs, outputs))
+    }
+
+    /// TensorProto: its name, and its value if it holds exactly one number.
+    /// Larger payloads (the weights) are skipped.
+    fn initializer(&mut self, len: u64) -> AppResult<(String, Option<f32>)> {
+        let (mut name, mut data_type, mut elements) = (String::new(), 0, 1u64);
+        let (mut raw, mut float_data, mut int32_data) = (None, None, None);
+        self.message(self.pos + len, &mut |r, field, value| {
+            match (field, value) {
+                (1, Value::Varint(dim)) => elements = elements.saturating_mul(dim),
+                (1, Value::Len(len)) => {
+                    let end = r.pos + len;
+                    while r.pos < end {
+                        elements = elements.saturating_mul(r.varint()?);
+                    }
+                }
+                (2, Value::Varint(v)) => data_type = v,
+                // Packed: a single value is 4 bytes, or one varint
+                (4, Value::Len(4)) => {
+                    float_data = Some(f32::from_le_bytes(r.bytes(4)?.try_into().unwrap()))
+                }
+                (5, Value::Len(len)) if len <= 10 => int32_data = Some(r.varint()? as i32),
+                (8, Value::Len(len)) => name = r.string(len)?,
+                (9, Value::Len(len)) if len <= 4 => raw = Some(r.bytes(len)?),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        if elements != 1 {
+            return Ok((name, None));
+        }
+
+        let raw = raw.as_deref();
+        let value = match data_type {
+            1 => float_data.or_else(|| Some(f32::from_le_bytes(raw?.try_into().ok()?))),
+            2 => raw
+                .and_then(|b| b.first().map(|&v| v as f32))
+                .or(int32_data.map(|v| v as f32)),
+            3 => raw
+                .and_then(|b| b.first().map(|&v| v as i8 as f32))
+                .or(int32_data.map(|v| v as f32)),
+            6 => raw
+                .and_then(|b| Some(i32::from_le_bytes(b.try_into().ok()?) as f32))
+                .or(int32_data.map(|v| v as f32)),
+            // float16 keeps its bits in int32_data
+            10 => raw
+                .and_then(|b| Some(u16::from_le_bytes(b.try_into().ok()?)))
+                .or(int32_data.map(|v| v as u16))
+                .map(|bits| half::f16::from_bits(bits).to_f32()),
+            _ => None,
+        };
+        Ok((name, value))
+    }
+
+    fn value_info(&mut self, len: u64) -> AppResult<TensorInfo> {
+        let mut tensor = TensorInfo {
+            name: String::new(),
+            dtype: "unknown".to_string(),
+            shape: Vec::new(),
+        };
+        self.message(self.pos + len, &mut |r, field, value| {
+            match (field, value) {
+                (1, Value::Len(len)) => tensor.name = r.string(len)?,
+                // TypeProto.tensor_type
+                (2, Value::Len(len)) => {
+                    r.message(r.pos + len, &mut |r, field, value| match (field, value) {
+                        (1, Value::Len(len)) => r.tensor_type(len, &mut tensor),
+                        _ => Ok(()),
+                    })?
+                }
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(tensor)
+    }
+
+    fn tensor_type(&mut self, len: u64, tensor: &mut TensorInfo) -> AppResult<()> {
+        self.message(self.pos + len, &mut |r, field, value| {
+            match (field, value) {
+                (1, Value::Varint(v)) => tensor.dtype = dtype_name(v),
+                // TensorShapeProto.dim
+                (2, Value::Len(len)) => r.message(r.pos + len, &mut |r, field, value| {
+                    if let (1, Value::Len(len)) = (field, value) {
+                        let mut dim = Dim::Dynamic(None);
+                        r.message(r.pos + len, &mut |r, field, value| {
+                            match (field, value) {
+                                (1, Value::Varint(v)) => dim = Dim::Fixed(v as i64),
+                                (2, Value::Len(len)) => dim = Dim::Dynamic(Some(r.string(len)?)),
+                                _ => {}
+                            }
+                            Ok(())
+                        })?;
+                        tensor.shape.push(dim);
+                    }
+                    Ok(())
+                })?,
+                _ => {}
+            }
+            Ok(())
+        })
+    }
+}
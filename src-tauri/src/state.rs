@@ -1,14 +1,21 @@
-use crate::error::AppResult;
-use crate::inference::OrtSession;
+use crate::error::{AppError, AppResult};
+use crate::inference::{CancelToken, CpuPool, OptimizedCache, OrtSession, SessionOptions};
+use crate::model_cache::{CacheBudget, Footprint, ModelCache};
+use crate::model_roots::{ModelLocation, ModelRoots, RootKind, CONFIG_FILE};
+use crate::models::{ModelManifest, ModelScan, ModelScanner, ModelUserConfig};
+use crate::onnx::OnnxInfo;
+use crate::tensor_format::TensorFormat;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use sysinfo::System;
 use tauri::Manager;
 
+const OPTIMIZED_DIR: &str = "optimized";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Job {
     pub id: String,
@@ -23,17 +30,24 @@ pub struct JobStore {
 }
 
 use crate::gpu::GpuInfo;
+use crate::tuning::{self, TunedSettings, TuningStore};
 
 #[derive(Clone)] // Added Clone
 pub struct AppState {
     pub store: Arc<Mutex<JobStore>>,
-    pub running_jobs: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
-    // Single-Slot Cache: Only holds the currently selected model.
-    // We store (Path, Session) to check if the requested model is already loaded.
-    pub model_cache: Arc<Mutex<Option<(PathBuf, Arc<OrtSession>)>>>,
+    pub running_jobs: Arc<Mutex<HashMap<String, Arc<CancelToken>>>>,
+    // Loaded sessions, LRU within a RAM/VRAM budget (see `model_cache`)
+    pub model_cache: Arc<Mutex<ModelCache>>,
     pub scale_cache: Arc<Mutex<HashMap<PathBuf, u32>>>,
     pub gpu_info: Arc<Mutex<Option<GpuInfo>>>,
+    // Benchmark results per model hash + hardware fingerprint (persisted)
+    pub tuning: Arc<Mutex<TuningStore>>,
+    // Model file hashes, invalidated by modification time
+    pub hash_cache: Arc<Mutex<HashMap<PathBuf, (SystemTime, String)>>>,
     pub app_data_dir: PathBuf,
+    // Ordered model folders (bundled, user, added) and the scanner over them
+    pub model_roots: Arc<Mutex<ModelRoots>>,
+    pub model_scanner: Arc<Mutex<ModelScanner>>,
 }
 
 impl AppState {
@@ -42,16 +56,33 @@ impl AppState {
             .path()
             .app_data_dir()
             .expect("failed to get app data dir");
+        let bundled_models = app_handle
+            .path()
+            .resource_dir()
+            .ok()
+            .map(|dir| dir.join("models"))
+            .filter(|dir| dir.is_dir());
+        Self::with_dirs(app_data_dir, bundled_models)
+    }
 
+    /// State persisted under `app_data_dir`, with bundled models (if any) first.
+    pub fn with_dirs(app_data_dir: PathBuf, bundled_models: Option<PathBuf>) -> Self {
         let store = Self::load_jobs(&app_data_dir).unwrap_or_default();
+        let tuning = TuningStore::load(&app_data_dir);
+        let cache_budget = CacheBudget::load(&app_data_dir);
+        let model_roots = ModelRoots::new(bundled_models, &app_data_dir);
 
         Self {
             store: Arc::new(Mutex::new(store)),
             running_jobs: Arc::new(Mutex::new(HashMap::new())),
-            model_cache: Arc::new(Mutex::new(None)),
+            model_cache: Arc::new(Mutex::new(ModelCache::new(cache_budget))),
             scale_cache: Arc::new(Mutex::new(HashMap::new())),
             gpu_info: Arc::new(Mutex::new(None)),
+            tuning: Arc::new(Mutex::new(tuning)),
+            hash_cache: Arc::new(Mutex::new(HashMap::new())),
             app_data_dir,
+            model_roots: Arc::new(Mutex::new(model_roots)),
+            model_scanner: Arc::new(Mutex::new(ModelScanner::default())),
         }
     }
 
@@ -96,86 +127,350 @@ impl AppState {
         self.save_jobs()
     }
 
+    /// Where a model id points, across all model folders.
+    pub fn resolve_model(&self, id: &str) -> AppResult<ModelLocation> {
+        self.model_roots.lock().unwrap().resolve(id)
+    }
+
+    pub fn scan_models(&self) -> ModelScan {
+        let roots = self.model_roots.lock().unwrap().roots().to_vec();
+        self.model_scanner.lock().unwrap().scan_roots(&roots)
+    }
+
+    /// Copies (or hard-links) a model into the user model folder once it has
+    /// passed validation: the graph parses, input and output are images, and a
+    /// test inference on CPU runs and yields the scale.
+    pub fn import_model(&self, source: &Path, link: bool) -> AppResult<ModelManifest> {
+        let reject =
+            |reason: String| AppError::ModelLoadError(format!("Import rejected: {}", reason));
+        let is_onnx = source
+            .extension()
+            .and_then(|s| s.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("onnx"));
+        if !source.is_file() || !is_onnx {
+            return Err(reject(format!("{:?} is not an .onnx file", source)));
+        }
+        let filename = source
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let user_root = self.model_roots.lock().unwrap().user_root().clone();
+        let target = user_root.path.join(&filename);
+        if target.exists() {
+            return Err(reject(format!(
+                "A model named {} is already installed",
+                filename
+            )));
+        }
+
+        // 1. Header: a valid ONNX graph with image-like input and output
+        let info = OnnxInfo::read(source).map_err(|e| reject(e.to_string()))?;
+        let format = info.image_format(None).map_err(|e| reject(e.to_string()))?;
+
+        // 2. A real run on CPU, which also measures the scale
+        let session = OrtSession::new(
+            source,
+            false,
+            Some("cpu".to_string()),
+            CpuPool {
+                sessions: Some(1),
+                threads: None,
+            },
+            SessionOptions::default(),
+            None,
+        )
+        .map_err(|e| reject(format!("ONNX Runtime can't load it: {}", e)))?;
+        let scale = session
+            .detect_scale(&session.format)
+            .map_err(|e| reject(format!("Test inference failed: {}", e)))?;
+        if scale < 1 {
+            return Err(reject("Output is smaller than the input".to_string()));
+        }
+        if let Some(declared) = info.static_scale(&format) {
+            if declared != scale {
+                return Err(reject(format!(
+                    "Graph declares {}x but the test run produced {}x",
+                    declared, scale
+                )));
+            }
+        }
+        drop(session);
+
+        // 3. Into the user folder. Copies land under a hidden name first, so
+        // the scanner never sees half a file.
+        let linked = link
+            && fs::hard_link(source, &target)
+                .map_err(|e| tracing::warn!("Can't link {:?}, copying instead: {}", source, e))
+                .is_ok();
+        if !linked {
+            let partial = user_root.path.join(format!(".{}.partial", filename));
+            if let Err(e) = fs::copy(source, &partial).and_then(|_| fs::rename(&partial, &target)) {
+                let _ = fs::remove_file(&partial);
+                return Err(e.into());
+            }
+        }
+        tracing::info!("Imported model {:?} ({}x) as {:?}", source, scale, target);
+
+        let location = self
+            .model_roots
+            .lock()
+            .unwrap()
+            .locate(&target)
+            .ok_or_else(|| reject("Imported file is outside the model folders".to_string()))?;
+        self.scale_cache
+            .lock()
+            .unwrap()
+            .insert(target.clone(), scale);
+        let user_config = ModelUserConfig::load(&location.config_dir().join(CONFIG_FILE));
+        let manifest = self
+            .model_scanner
+            .lock()
+            .unwrap()
+            .scan_file(&location, &user_config)?;
+        Ok(ModelManifest { scale, ..manifest })
+    }
+
+    /// Deletes a model file along with everything kept about it: its
+    /// `model_config.json` entry, cached sessions and scale, optimized copies
+    /// and tuned settings. Those last two are keyed by content, so they stay
+    /// while another model folder still holds an identical file. Bundled
+    /// models can't be removed.
+    pub fn remove_model(&self, id: &str) -> AppResult<()> {
+        let location = self.resolve_model(id)?;
+        if location.root.kind == RootKind::Bundled {
+            return Err(AppError::Unknown(
+                "Bundled models can't be removed".to_string(),
+            ));
+        }
+
+        // An unreadable config fails before anything is deleted
+        let config_path = location.config_dir().join(CONFIG_FILE);
+        let mut config = ModelUserConfig::load_strict(&config_path)?;
+
+        // Tuning and optimized copies are keyed by content: hash before deleting
+        let hash = self
+            .model_hash(&location.path)
+            .ok()
+            .filter(|hash| !self.has_copy(&location.path, hash));
+        fs::remove_file(&location.path)?;
+        self.invalidate_cache(&location.path);
+
+        let had_override = config.overrides.remove(&location.key).is_some();
+        if had_override | config.catalog.remove(&location.key).is_some() {
+            config.save(&config_path)?;
+        }
+
+        if let Some(hash) = hash {
+            let cleared = OptimizedCache::clear(&self.app_data_dir.join(OPTIMIZED_DIR), &hash);
+            let prefix = TuningStore::key(&hash, "");
+            let mut store = self.tuning.lock().unwrap();
+            let before = store.entries.len();
+            store.entries.retain(|key, _| !key.starts_with(&prefix));
+            if store.entries.len() != before {
+                store.save(&self.app_data_dir)?;
+            }
+            tracing::info!(
+                "Removed model {} ({} optimized copies, {} tuned settings)",
+                id,
+                cleared,
+                before - store.entries.len()
+            );
+        }
+        Ok(())
+    }
+
+    // Another model file, in any folder, with this content. Only files of the
+    // same size are hashed.
+    fn has_copy(&self, model_path: &Path, hash: &str) -> bool {
+        let Ok(size) = fs::metadata(model_path).map(|m| m.len()) else {
+            return false;
+        };
+        let roots = self.model_roots.lock().unwrap().roots().to_vec();
+        roots
+            .iter()
+            .flat_map(ModelRoots::model_files)
+            .filter(|other| other.path != model_path)
+            .filter(|other| fs::metadata(&other.path).is_ok_and(|m| m.len() == size))
+            .any(|other| self.model_hash(&other.path).is_ok_and(|h| h == hash))
+    }
+
     pub fn get_or_load_model(
         &self,
         model_path: &Path,
         prefer_npu: bool,
         execution_provider: Option<String>,
+        cpu_pool: CpuPool,
+        options: SessionOptions,
     ) -> AppResult<Arc<OrtSession>> {
-        // 1. Memory Safety Check
-        // We use a simple refresh to be safe and compatible
+        let requested_provider = execution_provider
+            .as_deref()
+            .unwrap_or("auto")
+            .to_lowercase();
+
+        // 1. Check Cache
+        // Keyed by content, so a renamed or copied model is still a hit. Without
+        // a hash (unreadable file) the session is loaded but not cached.
+        let model_hash = self.model_hash(model_path).ok();
+        if let Some(hash) = &model_hash {
+            // Strict Match: The cached session must have been created with the same intent.
+            // If I requested "auto", I want a session created with "auto".
+            // If I requested "openvino", I want a session created with "openvino".
+            // CPU sessions must also have been split into the same pool,
+            // and the session options must not have changed.
+            let cached = self.model_cache.lock().unwrap().get(hash, |session| {
+                session.provider_id == requested_provider
+                    && (session.execution_provider != "CPU" || session.cpu_pool == cpu_pool)
+                    && session.options == options
+            });
+            if let Some(session) = cached {
+                tracing::info!(
+                    "Cache Hit: {:?} (Provider: {})",
+                    model_path,
+                    session.execution_provider
+                );
+                return Ok(session);
+            }
+        }
+
+        // 2. Memory Safety Check
+        // If less than 2GB available, drop every idle cached session first
+        // to prevent system instability.
         let mut sys = System::new();
         sys.refresh_memory();
         let available_ram_gb = sys.available_memory() as f32 / 1024.0 / 1024.0 / 1024.0;
-
-        // If less than 2GB available, SKIP CACHING entirely to prevent system instability.
-        // We just load, use, and drop.
         if available_ram_gb < 2.0 {
+            let evicted = self.evict_idle_sessions();
             tracing::warn!(
-                "Low Memory ({:.2} GB free). Skipping model cache for safety.",
-                available_ram_gb
+                "Low Memory ({:.2} GB free). Evicted {} cached session(s).",
+                available_ram_gb,
+                evicted
             );
-            let session = OrtSession::new(model_path, prefer_npu, execution_provider)?;
-            return Ok(Arc::new(session));
         }
 
-        // 2. Check Cache
-        {
-            let cache = self.model_cache.lock().unwrap();
-            if let Some((cached_path, session)) = &*cache {
-                if cached_path == model_path {
-                    // Check if cached session matches requested provider?
-                    // Ideally yes, but for now let's assume if path matches it's okay.
-                    // If user switches provider, we should probably invalidate cache or check provider.
-                    // But OrtSession stores execution_provider string!
-                    // Let's check if we can access it.
-                    // session.execution_provider is public.
-
-                    let requested_provider = execution_provider
-                        .as_deref()
-                        .unwrap_or("auto")
-                        .to_lowercase();
-
-                    // Strict Match: The cached session must have been created with the same intent.
-                    // If I requested "auto", I want a session created with "auto".
-                    // If I requested "openvino", I want a session created with "openvino".
-                    let match_provider = session.provider_id == requested_provider;
-
-                    if match_provider {
-                        tracing::info!(
-                            "Cache Hit: {:?} (Provider: {})",
-                            model_path,
-                            session.execution_provider
-                        );
-                        return Ok(session.clone());
-                    } else {
-                        tracing::info!(
-                            "Cache Miss (Provider Mismatch): Requested {}, Cached {}",
-                            requested_provider,
-                            session.execution_provider
-                        );
-                    }
-                }
-            }
-        }
-
-        // 3. Load New Model (Heavy Operation)
+        // 3. Load New Model (Heavy Operation), measuring what it costs
         tracing::info!("Loading model from: {:?}", model_path);
-        let session = OrtSession::new(model_path, prefer_npu, execution_provider)?;
-        let session_arc = Arc::new(session);
+        let before = self.memory_in_use(requested_provider != "cpu");
+        let session = Arc::new(OrtSession::new(
+            model_path,
+            prefer_npu,
+            execution_provider,
+            cpu_pool,
+            options,
+            self.optimized_cache(model_path),
+        )?);
+        let after = self.memory_in_use(session.execution_provider != "CPU");
+        let footprint = Footprint {
+            ram_mb: after.ram_mb.saturating_sub(before.ram_mb),
+            vram_mb: after.vram_mb.saturating_sub(before.vram_mb),
+        };
 
-        // 4. Update Cache (Single Slot)
-        {
+        // 4. Update Cache, evicting least recently used sessions over the budget.
+        // An evicted session still in use by a running job is freed when it finishes.
+        if let Some(hash) = model_hash {
+            let limits = self.cache_limits();
             let mut cache = self.model_cache.lock().unwrap();
-            // This drops the previous model (if any), freeing its memory
-            // unless it's currently in use by a running job (Arc count > 1).
-            *cache = Some((model_path.to_path_buf(), session_arc.clone()));
+            let id = cache.insert(model_path, &hash, session.clone(), footprint);
+            let evicted = cache.evict_to_fit(&limits, id);
+            tracing::info!(
+                "Cached {:?} ({} MB RAM, {} MB VRAM), evicted {}",
+                model_path,
+                footprint.ram_mb,
+                footprint.vram_mb,
+                evicted
+            );
         }
 
-        Ok(session_arc)
+        Ok(session)
+    }
+
+    /// Process RAM and, for GPU providers, device VRAM in use, in MB.
+    ///
+    /// VRAM is read for the whole device: no vendor API we use reports it per
+    /// process. Another application allocating during a load shows up in that
+    /// session's footprint, so GPU footprints are estimates. Sampling re-runs GPU
+    /// detection, so it is skipped when no GPU with its own VRAM was detected.
+    fn memory_in_use(&self, on_gpu: bool) -> Footprint {
+        let mut sys = System::new();
+        let has_vram = self.gpu_info.lock().unwrap().as_ref().is_some_and(|g| {
+            // The sysinfo fallback reports system RAM, which is already counted
+            g.vendor != crate::gpu::GpuVendor::Unknown && g.vram_total_mb > 0
+        });
+        let vram_mb = if on_gpu && has_vram {
+            GpuInfo::detect()
+                .ok()
+                .filter(|g| g.vendor != crate::gpu::GpuVendor::Unknown)
+                .map_or(0, |g| g.vram_used_mb)
+        } else {
+            0
+        };
+        Footprint {
+            ram_mb: tuning::process_memory_mb(&mut sys),
+            vram_mb,
+        }
+    }
+
+    /// The cache budget resolved against this machine's RAM and VRAM.
+    pub fn cache_limits(&self) -> Footprint {
+        let mut sys = System::new();
+        sys.refresh_memory();
+        // None until background detection finishes, or if it failed
+        let total_vram_mb = self
+            .gpu_info
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|g| g.vendor != crate::gpu::GpuVendor::Unknown)
+            .map(|g| g.vram_total_mb)
+            .filter(|&mb| mb > 0);
+        let budget = self.model_cache.lock().unwrap().budget;
+        budget.limits(sys.total_memory() / 1024 / 1024, total_vram_mb)
+    }
+
+    pub fn set_cache_budget(&self, budget: CacheBudget) -> AppResult<()> {
+        budget.save(&self.app_data_dir)?;
+        self.model_cache.lock().unwrap().budget = budget;
+        let limits = self.cache_limits();
+        self.model_cache.lock().unwrap().evict_to_fit(&limits, 0);
+        Ok(())
     }
 
-    pub fn get_model_scale(&self, model_path: &Path, session: &OrtSession) -> AppResult<u32> {
+    /// Drops cached sessions that are neither pinned nor running a job.
+    pub fn evict_idle_sessions(&self) -> usize {
+        let mut cache = self.model_cache.lock().unwrap();
+        let idle: Vec<u64> = cache
+            .entries()
+            .iter()
+            .filter(|e| !e.pinned && !e.in_use())
+            .map(|e| e.id)
+            .collect();
+        idle.into_iter().filter(|&id| cache.evict(id)).count()
+    }
+
+    // Optimized copies of a model live under `<app data>/optimized`. Without a
+    // hash (unreadable file) sessions are built from the raw model.
+    fn optimized_cache(&self, model_path: &Path) -> Option<OptimizedCache> {
+        match self.model_hash(model_path) {
+            Ok(model_hash) => Some(OptimizedCache {
+                dir: self.app_data_dir.join(OPTIMIZED_DIR),
+                model_hash,
+                runtime: ort::info().to_string(),
+                hardware: tuning::machine_fingerprint(self.gpu_info.lock().unwrap().as_ref()),
+            }),
+            Err(e) => {
+                tracing::warn!("Not caching optimized model for {:?}: {}", model_path, e);
+                None
+            }
+        }
+    }
+
+    /// `format` is the effective tensor format `load_model` built for the session.
+    pub fn get_model_scale(
+        &self,
+        model_path: &Path,
+        session: &OrtSession,
+        format: &TensorFormat,
+    ) -> AppResult<u32> {
         // 1. Check Cache
         {
             let cache = self.scale_cache.lock().unwrap();
@@ -190,9 +485,17 @@ impl AppState {
             }
         }
 
-        // 2. Run Detection (Heavy)
-        tracing::info!("Detecting scale for: {:?}", model_path);
-        let result = session.detect_scale();
+        // 2. Fixed-size models declare it in the graph; otherwise run detection (heavy)
+        let declared = crate::onnx::OnnxInfo::read(model_path)
+            .ok()
+            .and_then(|info| info.static_scale(format));
+        let result = match declared {
+            Some(scale) => Ok(scale),
+            None => {
+                tracing::info!("Detecting scale for: {:?}", model_path);
+                session.detect_scale(format)
+            }
+        };
 
         // 3. Update Cache
         let mut cache = self.scale_cache.lock().unwrap();
@@ -218,5 +521,58 @@ impl AppState {
         if cache.remove(path).is_some() {
             tracing::info!("Invalidated scale cache for: {:?}", path);
         }
+        self.hash_cache.lock().unwrap().remove(path);
+        self.model_scanner.lock().unwrap().invalidate(path);
+        let evicted = self.model_cache.lock().unwrap().evict_path(path);
+        if evicted > 0 {
+            tracing::info!("Evicted {} cached session(s) for: {:?}", evicted, path);
+        }
+    }
+
+    pub fn model_hash(&self, model_path: &Path) -> AppResult<String> {
+        let modified = fs::metadata(model_path)?.modified()?;
+        {
+            let cache = self.hash_cache.lock().unwrap();
+            if let Some((cached_modified, hash)) = cache.get(model_path) {
+                if *cached_modified == modified {
+                    return Ok(hash.clone());
+                }
+            }
+        }
+
+        let hash = tuning::hash_model(model_path)?;
+        self.hash_cache
+            .lock()
+            .unwrap()
+            .insert(model_path.to_path_buf(), (modified, hash.clone()));
+        Ok(hash)
+    }
+
+    fn tuning_key(&self, model_path: &Path, execution_provider: &str) -> AppResult<String> {
+        let hash = self.model_hash(model_path)?;
+        let gpu = self.gpu_info.lock().unwrap().clone();
+        let hardware = tuning::hardware_fingerprint(gpu.as_ref(), execution_provider);
+        Ok(TuningStore::key(&hash, &hardware))
+    }
+
+    /// Benchmarked settings for this model on this machine and provider, if any.
+    pub fn tuned_settings(
+        &self,
+        model_path: &Path,
+        execution_provider: &str,
+    ) -> Option<TunedSettings> {
+        // Nothing tuned yet: skip hashing the model
+        if self.tuning.lock().unwrap().entries.is_empty() {
+            return None;
+        }
+        let key = self.tuning_key(model_path, execution_provider).ok()?;
+        self.tuning.lock().unwrap().entries.get(&key).cloned()
+    }
+
+    pub fn save_tuned_settings(&self, model_path: &Path, settings: TunedSettings) -> AppResult<()> {
+        let key = self.tuning_key(model_path, &settings.execution_provider)?;
+        let mut store = self.tuning.lock().unwrap();
+        store.entries.insert(key, settings);
+        store.save(&self.app_data_dir)
     }
 }
@@ -0,0 +1,427 @@
+use crate::error::{AppError, AppResult};
+use crate::image_processing::TileBatch;
+use serde::{Deserialize, Serialize};
+
+/// Memory layout of an image tensor.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TensorLayout {
+    #[default]
+    Nchw,
+    Nhwc,
+}
+
+impl TensorLayout {
+    /// Axes of (height, width) in a 4D tensor of this layout.
+    pub fn spatial_axes(self) -> (usize, usize) {
+        match self {
+            TensorLayout::Nchw => (2, 3),
+            TensorLayout::Nhwc => (1, 2),
+        }
+    }
+}
+
+/// Order of the color channels in the tensor.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelOrder {
+    #[default]
+    Rgb,
+    Bgr,
+}
+
+/// Affine quantization of a uint8/int8 tensor: `value = (q - zero_point) * scale`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Quantization {
+    pub scale: f32,
+    #[serde(default)]
+    pub zero_point: i32,
+}
+
+impl Quantization {
+    /// Used when the model does not expose its own: the full 0..1 range over
+    /// the 256 levels, i.e. plain pixel values for uint8.
+    pub fn for_signed(signed: bool) -> Self {
+        Self {
+            scale: 1.0 / 255.0,
+            zero_point: if signed { -128 } else { 0 },
+        }
+    }
+
+    /// Quantized level of `value`, clamped to `min..=max`.
+    pub fn quantize(&self, value: f32, min: f32, max: f32) -> f32 {
+        ((value / self.scale).round() + self.zero_point as f32).clamp(min, max)
+    }
+
+    pub fn dequantize(&self, level: f32) -> f32 {
+        (level - self.zero_point as f32) * self.scale
+    }
+}
+
+/// Channel counts a model may have: grayscale, RGB and RGBA.
+pub const SUPPORTED_CHANNELS: [usize; 3] = [1, 3, 4];
+
+// Rec. 709 weights, the same the `image` crate uses for its luma conversions
+const LUMA_WEIGHTS: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// I/O conventions of a model. The tiler always works in planar RGB 0..1; this
+/// describes how that maps onto the model's own tensors.
+///
+/// 1-channel models get luma in and their output is spread over R, G and B.
+/// 4-channel models get the tiled alpha plane (opaque for RGB tiles) and their
+/// output alpha becomes the tiler's fourth plane; see `carries_alpha`. Alpha is
+/// range scaled but not normalized.
+///
+/// The default is the common export: NCHW, RGB, 0..1 in and out, no normalization.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TensorFormat {
+    pub layout: TensorLayout,
+    pub channel_order: ChannelOrder,
+    pub input_channels: usize,
+    pub output_channels: usize,
+    pub input_range: [f32; 2],
+    pub output_range: [f32; 2],
+    // Applied to the input after range scaling, (x - mean) / std, in model channel order
+    pub mean: [f32; 3],
+    pub std: [f32; 3],
+    // Quantization of uint8/int8 tensors, applied after normalization (input)
+    // and before range scaling (output). Unset uses `Quantization::for_signed`.
+    pub input_quant: Option<Quantization>,
+    pub output_quant: Option<Quantization>,
+}
+
+impl Default for TensorFormat {
+    fn default() -> Self {
+        Self {
+            layout: TensorLayout::Nchw,
+            channel_order: ChannelOrder::Rgb,
+            input_channels: 3,
+            output_channels: 3,
+            input_range: [0.0, 1.0],
+            output_range: [0.0, 1.0],
+            mean: [0.0; 3],
+            std: [1.0; 3],
+            input_quant: None,
+            output_quant: None,
+        }
+    }
+}
+
+/// Partial `TensorFormat`, as declared in a model override entry or in ONNX
+/// metadata. Only the fields that are set replace the detected ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TensorFormatOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<TensorLayout>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_order: Option<ChannelOrder>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_channels: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_channels: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_range: Option<[f32; 2]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_range: Option<[f32; 2]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mean: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub std: Option<[f32; 3]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_quant: Option<Quantization>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_quant: Option<Quantization>,
+}
+
+impl TensorFormatOverride {
+    pub fn apply(&self, base: TensorFormat) -> TensorFormat {
+        TensorFormat {
+            layout: self.layout.unwrap_or(base.layout),
+            channel_order: self.channel_order.unwrap_or(base.channel_order),
+            input_channels: self.input_channels.unwrap_or(base.input_channels),
+            output_channels: self.output_channels.unwrap_or(base.output_channels),
+            input_range: self.input_range.unwrap_or(base.input_range),
+            output_range: self.output_range.unwrap_or(base.output_range),
+            mean: self.mean.unwrap_or(base.mean),
+            std: self.std.unwrap_or(base.std),
+            input_quant: self.input_quant.or(base.input_quant),
+            output_quant: self.output_quant.or(base.output_quant),
+        }
+    }
+
+    /// Reads the conventions from ONNX custom metadata, e.g. `layout: NHWC`,
+    /// `channel_order: BGR`, `input_channels: 1`, `input_range: -1,1`,
+    /// `mean: 0.4488,0.4371,0.4040`, `input_scale: 0.0039`, `input_zero_point: 0`.
+    /// Unknown or malformed values are ignored.
+    pub fn from_metadata(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let floats = |key: &str| -> Option<Vec<f32>> {
+            let value = lookup(key)?;
+            value
+                .split(|c: char| c == ',' || c.is_whitespace() || c == '[' || c == ']')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.parse().ok())
+                .collect()
+        };
+        let pair = |key: &str| floats(key).and_then(|v| <[f32; 2]>::try_from(v).ok());
+        let triple = |key: &str| floats(key).and_then(|v| <[f32; 3]>::try_from(v).ok());
+        let count = |key: &str| lookup(key).and_then(|v| v.trim().parse::<usize>().ok());
+        let quant = |prefix: &str| {
+            let scale = floats(&format!("{}_scale", prefix))?;
+            let zero_point = lookup(&format!("{}_zero_point", prefix))
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(0);
+            match scale[..] {
+                [scale] if scale > 0.0 => Some(Quantization { scale, zero_point }),
+                _ => None,
+            }
+        };
+
+        Self {
+            layout: lookup("layout").and_then(|v| match v.trim().to_lowercase().as_str() {
+                "nchw" => Some(TensorLayout::Nchw),
+                "nhwc" => Some(TensorLayout::Nhwc),
+                _ => None,
+            }),
+            channel_order: lookup("channel_order").and_then(|v| {
+                match v.trim().to_lowercase().as_str() {
+                    "rgb" => Some(ChannelOrder::Rgb),
+                    "bgr" => Some(ChannelOrder::Bgr),
+                    _ => None,
+                }
+            }),
+            input_channels: count("input_channels"),
+            output_channels: count("output_channels"),
+            input_range: pair("input_range"),
+            output_range: pair("output_range"),
+            mean: triple("mean"),
+            std: triple("std"),
+            input_quant: quant("input"),
+            output_quant: quant("output"),
+        }
+    }
+}
+
+impl TensorFormat {
+    /// Layout and channel counts from the model's tensor shapes. A channel axis
+    /// is one holding 1, 3 or 4; channels first wins when both could be. Dynamic
+    /// channel axes are assumed to be RGB, and a dynamic output follows the input.
+    pub fn detect(input_shape: &[i64], output_shape: &[i64]) -> Self {
+        let is_channels = |d: &i64| SUPPORTED_CHANNELS.contains(&(*d as usize));
+        let layout = match input_shape {
+            [_, c, _, _] if is_channels(c) => TensorLayout::Nchw,
+            [_, _, _, c] if is_channels(c) => TensorLayout::Nhwc,
+            _ => TensorLayout::Nchw,
+        };
+        let channel_axis = match layout {
+            TensorLayout::Nchw => 1,
+            TensorLayout::Nhwc => 3,
+        };
+        let channels = |shape: &[i64]| {
+            shape
+                .get(channel_axis)
+                .filter(|&&d| d > 0)
+                .map(|&d| d as usize)
+        };
+        let input_channels = channels(input_shape).unwrap_or(3);
+        Self {
+            layout,
+            input_channels,
+            output_channels: channels(output_shape).unwrap_or(input_channels),
+            ..Self::default()
+        }
+    }
+
+    /// Fails with a readable reason for channel counts the engine can't map,
+    /// and for ranges or std that would divide by zero in encode/decode.
+    pub fn validate(&self) -> AppResult<()> {
+        for (side, [lo, hi]) in [("input", self.input_range), ("output", self.output_range)] {
+            if !(lo.is_finite() && hi.is_finite()) || lo == hi {
+                return Err(AppError::ModelLoadError(format!(
+                    "Model {}_range [{}, {}] is empty; the two bounds must differ.",
+                    side, lo, hi
+                )));
+            }
+        }
+        if self.std.iter().any(|&s| !s.is_finite() || s == 0.0) {
+            return Err(AppError::ModelLoadError(format!(
+                "Model std {:?} has a zero entry.",
+                self.std
+            )));
+        }
+
+        for (side, channels) in [
+            ("input", self.input_channels),
+            ("output", self.output_channels),
+        ] {
+            if !SUPPORTED_CHANNELS.contains(&channels) {
+                return Err(AppError::ModelLoadError(format!(
+                    "Model {} has {} channels. Supported: 1 (grayscale), 3 (RGB) or 4 (RGBA).",
+                    side, channels
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// True if the input tensor is exactly the tiler's planar RGB 0..1.
+    pub fn is_canonical_input(&self) -> bool {
+        self.layout == TensorLayout::Nchw
+            && self.channel_order == ChannelOrder::Rgb
+            && self.input_channels == 3
+            && self.input_range == [0.0, 1.0]
+            && self.mean == [0.0; 3]
+            && self.std == [1.0; 3]
+    }
+
+    pub fn is_canonical_output(&self) -> bool {
+        self.layout == TensorLayout::Nchw
+            && self.channel_order == ChannelOrder::Rgb
+            && self.output_channels == 3
+            && self.output_range == [0.0, 1.0]
+    }
+
+    /// True if the model takes and returns alpha, so transparent inputs are
+    /// tiled with their alpha plane instead of upscaling it separately.
+    pub fn carries_alpha(&self) -> bool {
+        self.input_channels == 4 && self.output_channels == 4
+    }
+
+    // Planar channel feeding model channel `k` (and back, the swap is symmetric).
+    // Alpha stays last in either order.
+    fn source_channel(&self, k: usize) -> usize {
+        match (self.channel_order, k) {
+            (ChannelOrder::Bgr, 0..=2) => 2 - k,
+            _ => k,
+        }
+    }
+
+    pub fn input_shape(&self, batch: &TileBatch) -> Vec<i64> {
+        let (n, h, w) = (batch.len as i64, batch.height as i64, batch.width as i64);
+        let c = self.input_channels as i64;
+        match self.layout {
+            TensorLayout::Nchw => vec![n, c, h, w],
+            TensorLayout::Nhwc => vec![n, h, w, c],
+        }
+    }
+
+    /// Writes `batch` into `dest` in the model's input convention.
+    pub fn encode(&self, batch: &TileBatch, dest: &mut Vec<f32>) {
+        let plane = batch.width as usize * batch.height as usize;
+        let channels = self.input_channels;
+        let [lo, hi] = self.input_range;
+        // Range scaling and normalization folded into one multiply-add per channel.
+        // Alpha is not normalized.
+        let norm = |k: usize| -> (f32, f32) {
+            match k {
+                0..=2 => (self.mean[k], self.std[k]),
+                _ => (0.0, 1.0),
+            }
+        };
+
+        dest.clear();
+        dest.resize(batch.len * plane * channels, 0.0);
+        let mut scratch = Vec::new();
+        for (tile, out) in batch
+            .data
+            .chunks_exact(plane * batch.channels)
+            .zip(dest.chunks_exact_mut(plane * channels))
+        {
+            for k in 0..channels {
+                let src: &[f32] = match (channels, k) {
+                    (1, _) => {
+                        let [r, g, b] = [0, 1, 2].map(|c| &tile[c * plane..][..plane]);
+                        scratch.clear();
+                        scratch.extend((0..plane).map(|i| {
+                            r[i] * LUMA_WEIGHTS[0] + g[i] * LUMA_WEIGHTS[1] + b[i] * LUMA_WEIGHTS[2]
+                        }));
+                        &scratch
+                    }
+                    // RGB tiles are opaque
+                    (_, 3) if batch.channels == 3 => {
+                        scratch.clear();
+                        scratch.resize(plane, 1.0);
+                        &scratch
+                    }
+                    _ => &tile[self.source_channel(k) * plane..][..plane],
+                };
+                let (mean, std) = norm(k);
+                let (scale, offset) = ((hi - lo) / std, (lo - mean) / std);
+                match self.layout {
+                    TensorLayout::Nchw => {
+                        for (d, &v) in out[k * plane..][..plane].iter_mut().zip(src) {
+                            *d = v * scale + offset;
+                        }
+                    }
+                    TensorLayout::Nhwc => {
+                        for (d, &v) in out[k..].iter_mut().step_by(channels).zip(src) {
+                            *d = v * scale + offset;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Converts model output (`shape`, `data`) into planar RGB 0..1 in `dest`,
+    /// plus an alpha plane for 4-channel output. Returns the NCHW shape of the result.
+    pub fn decode(&self, shape: &[i64], data: &[f32], dest: &mut Vec<f32>) -> AppResult<Vec<i64>> {
+        let (n, c, h, w) = match (self.layout, shape) {
+            (TensorLayout::Nchw, &[n, c, h, w]) => (n, c as usize, h, w),
+            (TensorLayout::Nhwc, &[n, h, w, c]) => (n, c as usize, h, w),
+            _ => {
+                return Err(AppError::Unknown(format!(
+                    "Unexpected output tensor rank: {}",
+                    shape.len()
+                )))
+            }
+        };
+        if !SUPPORTED_CHANNELS.contains(&c) {
+            return Err(AppError::InferenceError(format!(
+                "Model output has {} channels. Supported: 1 (grayscale), 3 (RGB) or 4 (RGBA).",
+                c
+            )));
+        }
+        let plane = h as usize * w as usize;
+        if data.len() != n as usize * c * plane {
+            return Err(AppError::Unknown(format!(
+                "Output tensor {:?} does not match {} values",
+                shape,
+                data.len()
+            )));
+        }
+
+        let [lo, hi] = self.output_range;
+        let scale = 1.0 / (hi - lo);
+        let planes = c.max(3);
+        dest.clear();
+        dest.resize(n as usize * planes * plane, 0.0);
+        for (tile, out) in data
+            .chunks_exact(plane * c)
+            .zip(dest.chunks_exact_mut(plane * planes))
+        {
+            // Grayscale is spread over all three planes
+            for channel in 0..planes {
+                let k = if c == 1 {
+                    0
+                } else {
+                    self.source_channel(channel)
+                };
+                let out = &mut out[channel * plane..][..plane];
+                match self.layout {
+                    TensorLayout::Nchw => {
+                        for (d, &v) in out.iter_mut().zip(&tile[k * plane..][..plane]) {
+                            *d = (v - lo) * scale;
+                        }
+                    }
+                    TensorLayout::Nhwc => {
+                        for (d, &v) in out.iter_mut().zip(tile[k..].iter().step_by(c)) {
+                            *d = (v - lo) * scale;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(vec![n, planes as i64, h, w])
+    }
+}
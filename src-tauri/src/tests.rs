@@ -1,8 +1,12 @@
 #[cfg(test)]
 mod tests {
-    use crate::image_processing;
+    use crate::engine::plan::{FillMode, OutputSize, ScalePlan};
+    use crate::engine::tta::{self, Dihedral};
+    use crate::image_processing::{self, BlendMode, TileBatch, TilingConfig};
 
-    use image::{DynamicImage, ImageBuffer, Rgba};
+    use image::{DynamicImage, ImageBuffer, Rgb, Rgba};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
 
     fn create_dummy_image(width: u32, height: u32) -> DynamicImage {
         let buffer = ImageBuffer::from_fn(width, height, |x, y| {
@@ -19,4 +23,1351 @@ mod tests {
         assert_eq!(resized.width(), 50);
         assert_eq!(resized.height(), 50);
     }
+
+    #[test]
+    fn test_blend_weights_are_complementary() {
+        for mode in [BlendMode::Linear, BlendMode::Cosine] {
+            for i in 0..=10 {
+                let t = i as f32 / 10.0;
+                let sum = mode.weight(t) + mode.weight(1.0 - t);
+                assert!(
+                    (sum - 1.0).abs() < 1e-5,
+                    "{:?} at {} sums to {}",
+                    mode,
+                    t,
+                    sum
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_blended_tiling_identity() {
+        // An identity "model" must reproduce the source exactly, seams included.
+        let src = DynamicImage::ImageRgb8(create_dummy_image(150, 97).to_rgb8());
+        let config = TilingConfig {
+            tile_size: 64,
+            padding: 32,
+            batch_size: 2,
+            overlap: 16,
+            blend_mode: BlendMode::Cosine,
+            input_size: (None, None),
+            static_batch: None,
+            alignment: 1,
+            workers: 1,
+        };
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let out = image_processing::process_tiled(&src, config, 1, &cancel, |_| {}, Ok)
+            .expect("Tiled processing failed");
+
+        assert_eq!(out.to_rgb8().as_raw(), src.to_rgb8().as_raw());
+
+        // Concurrent workers finish out of order; stitching must not
+        let calls = std::sync::atomic::AtomicU64::new(0);
+        let jittered = |tiles: TileBatch| {
+            let n = calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            std::thread::sleep(std::time::Duration::from_millis(n * 7 % 5));
+            Ok(tiles)
+        };
+        let pooled = TilingConfig {
+            workers: 3,
+            ..config
+        };
+        let out = image_processing::process_tiled(&src, pooled, 1, &cancel, |_| {}, jittered)
+            .expect("Pooled processing failed");
+
+        assert_eq!(out.to_rgb8().as_raw(), src.to_rgb8().as_raw());
+    }
+
+    #[test]
+    fn test_fixed_shape_tiling() {
+        // Static batch 4 with a 96x80 input, then a dynamic model with alignment 16:
+        // every batch must be full and every tile the exact shape, output unchanged.
+        let src = DynamicImage::ImageRgb8(create_dummy_image(150, 97).to_rgb8());
+        let cancel = Arc::new(AtomicBool::new(false));
+        let check = |expected: (Option<u32>, Option<u32>), batch: Option<usize>| {
+            move |tiles: TileBatch| {
+                if let Some(n) = batch {
+                    assert_eq!(tiles.len, n);
+                }
+                let (w, h) = (tiles.width, tiles.height);
+                assert_eq!(w, expected.0.unwrap_or(w));
+                assert_eq!(h, expected.1.unwrap_or(h));
+                assert!(expected.0.is_some() || w % 16 == 0);
+                assert!(expected.1.is_some() || h % 16 == 0);
+                Ok(tiles)
+            }
+        };
+
+        let fixed = TilingConfig {
+            tile_size: 64,
+            padding: 16,
+            batch_size: 1,
+            overlap: 8,
+            blend_mode: BlendMode::Cosine,
+            input_size: (Some(96), Some(80)),
+            static_batch: Some(4),
+            alignment: 1,
+            workers: 1,
+        };
+        let out = image_processing::process_tiled(
+            &src,
+            fixed,
+            1,
+            &cancel,
+            |_| {},
+            check((Some(96), Some(80)), Some(4)),
+        )
+        .expect("Fixed-shape tiling failed");
+        assert_eq!(out.to_rgb8().as_raw(), src.to_rgb8().as_raw());
+
+        let aligned = TilingConfig {
+            tile_size: 50,
+            input_size: (None, None),
+            static_batch: None,
+            alignment: 16,
+            ..fixed
+        };
+        let out = image_processing::process_tiled(
+            &src,
+            aligned,
+            1,
+            &cancel,
+            |_| {},
+            check((None, None), None),
+        )
+        .expect("Aligned tiling failed");
+        assert_eq!(out.to_rgb8().as_raw(), src.to_rgb8().as_raw());
+    }
+
+    #[test]
+    fn test_high_precision_tiling_keeps_hdr_values() {
+        // Float inputs must come back as float, including values above 1.0.
+        let buffer = ImageBuffer::from_fn(100, 70, |x, y| {
+            Rgb([x as f32 / 10.0, y as f32 / 70.0, 0.25])
+        });
+        let src = DynamicImage::ImageRgb32F(buffer);
+        let config = TilingConfig {
+            tile_size: 32,
+            padding: 16,
+            batch_size: 1,
+            overlap: 8,
+            blend_mode: BlendMode::Linear,
+            input_size: (None, None),
+            static_batch: None,
+            alignment: 1,
+            workers: 1,
+        };
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        let out = image_processing::process_tiled(&src, config, 1, &cancel, |_| {}, Ok)
+            .expect("Tiled processing failed");
+
+        let out = out.as_rgb32f().expect("Output should stay float");
+        for (a, b) in out.as_raw().iter().zip(src.to_rgb32f().as_raw()) {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_memory_check_decides_streaming() {
+        const GIB: u64 = 1024 * 1024 * 1024;
+        let check = image_processing::check_memory_against;
+
+        // A 20k x 20k source at x4 needs ~19 GB of RGB8 canvas: streamed on 16 GB
+        assert!(check(16 * GIB, 80_000, 80_000, false, false).is_err());
+        assert!(check(32 * GIB, 80_000, 80_000, false, false).is_ok());
+        assert!(check(16 * GIB, 4096, 4096, false, false).is_ok());
+
+        // Alpha and f32 channels count towards the canvas
+        let rgb8 = image_processing::canvas_memory(4096, 4096, false, false);
+        let rgba8 = image_processing::canvas_memory(4096, 4096, false, true);
+        let rgb32f = image_processing::canvas_memory(4096, 4096, true, false);
+        let headroom = image_processing::canvas_memory(0, 0, false, false);
+        assert_eq!(rgba8 - headroom, (rgb8 - headroom) / 3 * 4);
+        assert_eq!(rgb32f - headroom, (rgb8 - headroom) * 4);
+        assert!(check(rgb8, 4096, 4096, false, false).is_ok());
+        assert!(check(rgb8, 4096, 4096, false, true).is_err());
+        assert!(check(rgb8, 4096, 4096, true, false).is_err());
+
+        // Both sizes are reported in MB
+        let err = check(16 * GIB, 80_000, 80_000, false, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Need ~18410 MB"), "{}", err);
+        assert!(err.contains("Available 16384 MB"), "{}", err);
+    }
+
+    #[test]
+    fn test_disk_canvas_matches_in_memory() {
+        use crate::metadata::SourceMetadata;
+        use img_parts::{ImageEXIF, ImageICC};
+
+        // Streaming through a disk canvas must produce the same pixels as in memory.
+        let src = DynamicImage::ImageRgb8(create_dummy_image(150, 97).to_rgb8());
+        let config = TilingConfig {
+            tile_size: 64,
+            padding: 32,
+            batch_size: 2,
+            overlap: 16,
+            blend_mode: BlendMode::Cosine,
+            input_size: (None, None),
+            static_batch: None,
+            alignment: 1,
+            workers: 1,
+        };
+        let cancel = Arc::new(AtomicBool::new(false));
+        let dir = std::env::temp_dir();
+
+        let mut canvas = image_processing::DiskImage::create(&dir, 150, 97, image::ColorType::Rgb8)
+            .expect("Failed to create disk canvas");
+        image_processing::process_tiled_to_disk(&src, config, 1, &cancel, |_| {}, Ok, &mut canvas)
+            .expect("Streamed processing failed");
+        assert_eq!(canvas.as_bytes(), src.to_rgb8().as_raw().as_slice());
+
+        // The source's profile and EXIF are written by the streaming encoder
+        let source_metadata = SourceMetadata {
+            icc_profile: Some(b"fake icc profile".to_vec()),
+            exif: Some(b"MM\0*\0\0\0\x08\0\0".to_vec()),
+        };
+        let out_path = dir.join(format!("rustscale-test-{}.png", std::process::id()));
+        image_processing::save_disk_image(
+            &canvas,
+            &out_path,
+            image::ImageFormat::Png,
+            "lossless",
+            &source_metadata,
+        )
+        .expect("Failed to encode disk canvas");
+        let written = img_parts::png::Png::from_bytes(std::fs::read(&out_path).unwrap().into())
+            .expect("Output should be a PNG");
+        assert_eq!(
+            written.icc_profile().as_deref(),
+            source_metadata.icc_profile.as_deref()
+        );
+        assert_eq!(written.exif().as_deref(), source_metadata.exif.as_deref());
+        let decoded = image::open(&out_path).expect("Failed to decode output");
+        let _ = std::fs::remove_file(&out_path);
+        assert_eq!(decoded.to_rgb8().as_raw(), src.to_rgb8().as_raw());
+
+        // Grayscale model output streams into a luma canvas, like `to_grayscale` in memory
+        let grey = DynamicImage::ImageRgb8(DynamicImage::ImageLuma8(src.to_luma8()).to_rgb8());
+        let color = image_processing::disk_color(image::ImageFormat::Png, false, true);
+        let mut canvas = image_processing::DiskImage::create(&dir, 150, 97, color)
+            .expect("Failed to create disk canvas");
+        image_processing::process_tiled_to_disk(&grey, config, 1, &cancel, |_| {}, Ok, &mut canvas)
+            .expect("Streamed processing failed");
+        assert_eq!(canvas.as_bytes(), src.to_luma8().as_raw().as_slice());
+        image_processing::save_disk_image(
+            &canvas,
+            &out_path,
+            image::ImageFormat::Png,
+            "lossless",
+            &SourceMetadata::default(),
+        )
+        .expect("Failed to encode disk canvas");
+        let decoded = image::open(&out_path).expect("Failed to decode output");
+        let _ = std::fs::remove_file(&out_path);
+        assert_eq!(decoded.color(), image::ColorType::L8);
+    }
+
+    // A 2x "model" that repeats every pixel, so passes can be chained
+    fn double_tiles(tiles: TileBatch) -> crate::error::AppResult<TileBatch> {
+        let (w, h) = (tiles.width as usize, tiles.height as usize);
+        let mut data = Vec::with_capacity(tiles.data.len() * 4);
+        for plane in tiles.data.chunks(w * h) {
+            for y in 0..h * 2 {
+                data.extend((0..w * 2).map(|x| plane[(y / 2) * w + x / 2]));
+            }
+        }
+        Ok(TileBatch {
+            data,
+            len: tiles.len,
+            channels: tiles.channels,
+            width: tiles.width * 2,
+            height: tiles.height * 2,
+        })
+    }
+
+    const STREAM_TILING: TilingConfig = TilingConfig {
+        tile_size: 48,
+        padding: 32,
+        batch_size: 2,
+        overlap: 16,
+        blend_mode: BlendMode::Cosine,
+        input_size: (None, None),
+        static_batch: None,
+        alignment: 1,
+        workers: 1,
+    };
+
+    fn double_pass(
+        input: image_processing::TileInput,
+        canvas: &mut image_processing::DiskImage,
+        progress: &mut dyn FnMut(f32),
+    ) -> crate::error::AppResult<()> {
+        let cancel = Arc::new(AtomicBool::new(false));
+        image_processing::process_tiled_to_disk(
+            input,
+            STREAM_TILING,
+            2,
+            &cancel,
+            progress,
+            double_tiles,
+            canvas,
+        )
+    }
+
+    #[test]
+    fn test_streamed_plans_match_in_memory() {
+        use crate::engine::stream::{self, DiskTarget};
+        use crate::metadata::SourceMetadata;
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let dir = std::env::temp_dir();
+        let in_memory = |image: &DynamicImage| {
+            image_processing::process_tiled(image, STREAM_TILING, 2, &cancel, |_| {}, double_tiles)
+                .expect("Tiled processing failed")
+        };
+
+        // Two chained passes: the first streams into an intermediate canvas
+        let src = DynamicImage::ImageRgb8(create_dummy_image(150, 97).to_rgb8());
+        let plan = ScalePlan::new((150, 97), 2, 4.0, None, false).unwrap();
+        assert_eq!(plan.passes, 2);
+        let target = DiskTarget {
+            dir: &dir,
+            color: image::ColorType::Rgb8,
+            inverse_tone_map: false,
+        };
+        let streamed = stream::stream_scaling(
+            &src,
+            None,
+            &plan,
+            target,
+            &mut |_| {},
+            &mut |_, input, canvas, progress| double_pass(input, canvas, progress),
+        )
+        .expect("Streamed passes failed");
+        let expected = in_memory(&in_memory(&src));
+        assert_eq!(streamed.dimensions(), (600, 388));
+        assert_eq!(streamed.as_bytes(), expected.to_rgb8().as_raw().as_slice());
+
+        // Transparent input with two passes, a resample and a crop to an exact size
+        let src = DynamicImage::ImageRgba8(ImageBuffer::from_fn(150, 97, |x, y| {
+            let alpha = if x < 40 { 0 } else { (x * 2).min(255) as u8 };
+            Rgba([(x % 255) as u8, (y % 255) as u8, 90, alpha])
+        }));
+        let plan = ScalePlan::new(
+            (150, 97),
+            2,
+            1.0,
+            Some(OutputSize::Exact {
+                width: 460,
+                height: 280,
+                fill: FillMode::Crop,
+            }),
+            false,
+        )
+        .unwrap();
+        assert_eq!(plan.passes, 2);
+        assert!(plan.resample.is_some() && plan.canvas.is_some());
+
+        let (rgb, alpha) = image_processing::split_alpha(&src).expect("Image has alpha");
+        let mut streamed = stream::stream_scaling(
+            &rgb,
+            None,
+            &plan,
+            target,
+            &mut |_| {},
+            &mut |_, input, canvas, progress| double_pass(input, canvas, progress),
+        )
+        .expect("Streamed passes failed");
+        let alpha_canvas =
+            stream::stream_alpha_resize(&alpha, &plan, (150, 97), &dir, image::ColorType::L8)
+                .expect("Streamed alpha resize failed");
+        streamed
+            .set_alpha(alpha_canvas)
+            .expect("Alpha canvas should fit");
+
+        // What the in-memory path does: passes, resample, merge, crop
+        let (w, h) = plan.resample.unwrap();
+        let color = image_processing::resize_image(&in_memory(&in_memory(&rgb)), w, h).unwrap();
+        let merged = image_processing::merge_alpha(
+            &color,
+            &image_processing::resize_image(&alpha, w, h).unwrap(),
+        )
+        .unwrap();
+        let expected = image_processing::crop_center(&merged, 460, 280).to_rgba8();
+
+        let out_path = dir.join(format!("rustscale-stream-{}.png", std::process::id()));
+        image_processing::save_disk_image(
+            &streamed,
+            &out_path,
+            image::ImageFormat::Png,
+            "lossless",
+            &SourceMetadata::default(),
+        )
+        .expect("Failed to encode disk canvas");
+        let decoded = image::open(&out_path).expect("Failed to decode output");
+        let _ = std::fs::remove_file(&out_path);
+
+        assert_eq!(decoded.color(), image::ColorType::Rgba8);
+        let decoded = decoded.to_rgba8();
+        assert_eq!(decoded.dimensions(), expected.dimensions());
+        // Resampling in bands may round a sample differently at band edges
+        for (a, b) in decoded.as_raw().iter().zip(expected.as_raw()) {
+            assert!(a.abs_diff(*b) <= 1, "{} vs {}", a, b);
+        }
+    }
+
+    #[test]
+    fn test_rgba_model_tiles_alpha() {
+        use crate::engine::stream::{self, DiskTarget};
+        use crate::tensor_format::TensorFormat;
+
+        // A 4-channel identity model, through the same encode/decode as a real one
+        let format = TensorFormat::detect(&[1, 4, -1, -1], &[1, 4, -1, -1]);
+        assert!(format.carries_alpha());
+        let rgba_model = |tiles: TileBatch| {
+            let mut tensor = Vec::new();
+            format.encode(&tiles, &mut tensor);
+            assert_eq!(
+                tensor.len(),
+                tiles.len * 4 * (tiles.width * tiles.height) as usize
+            );
+            let mut planes = Vec::new();
+            let shape = format.decode(&format.input_shape(&tiles), &tensor, &mut planes)?;
+            TileBatch::from_nchw(&shape, planes)
+        };
+
+        let src = DynamicImage::ImageRgba8(ImageBuffer::from_fn(150, 97, |x, y| {
+            let alpha = if y < 30 { 0 } else { (x + y) as u8 };
+            Rgba([(x % 255) as u8, (y % 255) as u8, 90, alpha])
+        }));
+        let (rgb, alpha) = image_processing::split_alpha(&src).expect("Image has alpha");
+        let cancel = Arc::new(AtomicBool::new(false));
+
+        // The source alpha is the fourth plane and comes back as the alpha plane
+        let (color, tiled_alpha) = image_processing::process_tiled_with_alpha(
+            &rgb,
+            &alpha,
+            STREAM_TILING,
+            1,
+            &cancel,
+            |_| {},
+            rgba_model,
+        )
+        .expect("Tiled processing failed");
+        assert_eq!(color.to_rgb8().as_raw(), rgb.to_rgb8().as_raw());
+        assert_eq!(tiled_alpha.color(), image::ColorType::L8);
+        assert_eq!(tiled_alpha.as_bytes(), alpha.as_bytes());
+
+        // Opaque inputs are fed an opaque alpha and keep RGB output
+        let opaque =
+            image_processing::process_tiled(&rgb, STREAM_TILING, 1, &cancel, |_| {}, rgba_model)
+                .expect("Tiled processing failed");
+        assert_eq!(opaque.to_rgb8().as_raw(), rgb.to_rgb8().as_raw());
+
+        // Streamed: the alpha goes through an alpha canvas for every pass
+        let in_memory = |color: &DynamicImage, alpha: &DynamicImage| {
+            image_processing::process_tiled_with_alpha(
+                color,
+                alpha,
+                STREAM_TILING,
+                2,
+                &cancel,
+                |_| {},
+                |tiles| double_tiles(rgba_model(tiles)?),
+            )
+            .expect("Tiled processing failed")
+        };
+        let (color, upscaled_alpha) = in_memory(&rgb, &alpha);
+        let (color, upscaled_alpha) = in_memory(&color, &upscaled_alpha);
+
+        let dir = std::env::temp_dir();
+        let plan = ScalePlan::new((150, 97), 2, 4.0, None, false).unwrap();
+        let streamed = stream::stream_scaling(
+            &rgb,
+            Some(&alpha),
+            &plan,
+            DiskTarget {
+                dir: &dir,
+                color: image::ColorType::Rgb8,
+                inverse_tone_map: false,
+            },
+            &mut |_| {},
+            &mut |_, input, canvas, progress| {
+                image_processing::process_tiled_to_disk(
+                    input,
+                    STREAM_TILING,
+                    2,
+                    &cancel,
+                    progress,
+                    |tiles| double_tiles(rgba_model(tiles)?),
+                    canvas,
+                )
+            },
+        )
+        .expect("Streamed passes failed");
+        assert_eq!(streamed.as_bytes(), color.to_rgb8().as_raw().as_slice());
+        let streamed_alpha = streamed.alpha().expect("Streamed output keeps its alpha");
+        assert_eq!(streamed_alpha.as_bytes(), upscaled_alpha.as_bytes());
+    }
+
+    #[test]
+    fn test_alpha_split_bleeds_and_merges() {
+        // Left half opaque red, right half fully transparent black.
+        let buffer = ImageBuffer::from_fn(8, 4, |x, _| {
+            if x < 4 {
+                Rgba([255u8, 0, 0, 255])
+            } else {
+                Rgba([0u8, 0, 0, 0])
+            }
+        });
+        let src = DynamicImage::ImageRgba8(buffer);
+
+        let (rgb, alpha) = image_processing::split_alpha(&src).expect("Image has alpha");
+        let rgb = rgb.to_rgb8();
+        // Transparent pixels inherit the opaque color instead of black (no halo)
+        assert_eq!(rgb.get_pixel(7, 2).0, [255, 0, 0]);
+        assert_eq!(alpha.to_luma8().get_pixel(7, 2).0, [0]);
+
+        let merged = image_processing::merge_alpha(&DynamicImage::ImageRgb8(rgb), &alpha)
+            .expect("Failed to merge alpha");
+        assert_eq!(merged.to_rgba8().get_pixel(1, 1).0, [255, 0, 0, 255]);
+        assert_eq!(merged.to_rgba8().get_pixel(6, 1).0[3], 0);
+
+        // Opaque RGBA inputs take the plain RGB path
+        assert!(image_processing::split_alpha(&create_dummy_image(4, 4)).is_none());
+    }
+
+    #[test]
+    fn test_scale_plans() {
+        // 4x model, 2x target: one pass, then downsample
+        let plan = ScalePlan::new((100, 50), 4, 2.0, None, false).unwrap();
+        assert_eq!(plan.passes, 1);
+        assert_eq!(plan.resample, Some((200, 100)));
+
+        // 2x model, 6x target: three passes (8x), then downsample
+        let plan = ScalePlan::new((100, 50), 2, 6.0, None, false).unwrap();
+        assert_eq!(plan.passes, 3);
+        assert_eq!(plan.output_size((100, 50)), (600, 300));
+
+        // Progress is weighted by output pixels (4 : 16 : 64)
+        let weights = plan.pass_weights((100, 50));
+        assert!((weights[2] - 64.0 / 84.0).abs() < 1e-6);
+        assert_eq!(plan.largest_intermediate((100, 50)), (800, 400));
+
+        // Same, but shrinking the input so the chain lands on the target
+        let plan = ScalePlan::new((100, 50), 2, 6.0, None, true).unwrap();
+        assert_eq!(plan.pre_resize, Some((75, 38)));
+        assert_eq!(plan.passes, 3);
+        assert_eq!(plan.output_size((100, 50)), (600, 300));
+
+        // Exact native scale needs nothing else
+        assert!(ScalePlan::new((100, 50), 4, 4.0, None, false)
+            .unwrap()
+            .is_single_pass());
+
+        // Fit to a width, and exact size with a crop
+        let fit = Some(OutputSize::Width { width: 150 });
+        let plan = ScalePlan::new((100, 50), 4, 1.0, fit, false).unwrap();
+        assert_eq!(plan.output_size((100, 50)), (150, 75));
+
+        let exact = Some(OutputSize::Exact {
+            width: 300,
+            height: 300,
+            fill: FillMode::Crop,
+        });
+        let plan = ScalePlan::new((100, 50), 4, 1.0, exact, false).unwrap();
+        assert_eq!(plan.resample, Some((600, 300)));
+        assert_eq!(plan.canvas, Some((300, 300, FillMode::Crop)));
+    }
+
+    #[test]
+    fn test_self_ensemble_inverts_transforms() {
+        // A nearest-neighbour 2x "model" commutes with flips and rotations,
+        // so the ensemble average must equal a plain run.
+        let upscale = |tiles: TileBatch| -> crate::error::AppResult<TileBatch> {
+            let (w, h) = (tiles.width as usize * 2, tiles.height as usize * 2);
+            let data = tiles
+                .data
+                .chunks_exact(tiles.width as usize * tiles.height as usize)
+                .flat_map(|plane| {
+                    (0..w * h).map(move |i| plane[(i / w / 2) * (w / 2) + (i % w) / 2])
+                })
+                .collect();
+            Ok(TileBatch {
+                data,
+                len: tiles.len,
+                channels: tiles.channels,
+                width: w as u32,
+                height: h as u32,
+            })
+        };
+        // Square tiles use all 8 transforms, non-square ones only the shape-keeping 4
+        for (w, h) in [(16, 16), (16, 12)] {
+            let tiles = TileBatch {
+                data: (0..2 * 3 * w * h)
+                    .map(|i| (i * 37 % 101) as f32 / 100.0)
+                    .collect(),
+                len: 2,
+                channels: 3,
+                width: w as u32,
+                height: h as u32,
+            };
+
+            let expected = upscale(tiles.clone()).unwrap();
+            let ensembled = tta::self_ensemble(tiles, Dihedral::set(8).unwrap(), upscale)
+                .expect("Ensemble failed");
+
+            assert_eq!(
+                (ensembled.width, ensembled.height),
+                (expected.width, expected.height)
+            );
+            for (a, b) in ensembled.data.iter().zip(&expected.data) {
+                assert!((a - b).abs() < 1e-6);
+            }
+        }
+        assert!(Dihedral::set(3).is_err());
+    }
+
+    #[test]
+    fn test_tuning_store_round_trip() {
+        use crate::tuning::{self, TunedSettings, TuningStore};
+
+        let dir = std::env::temp_dir().join(format!("rustscale_tuning_{}", uuid::Uuid::new_v4()));
+        let model = dir.join("model.onnx");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(&model, b"not really onnx").unwrap();
+
+        let hash = tuning::hash_model(&model).unwrap();
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, tuning::hash_model(&model).unwrap());
+
+        let key = TuningStore::key(&hash, "CPU | test");
+        let mut store = TuningStore::default();
+        store.entries.insert(
+            key.clone(),
+            TunedSettings {
+                tile_size: 384,
+                batch_size: 2,
+                megapixels_per_second: 1.5,
+                peak_memory_mb: 900,
+                execution_provider: "CPU".to_string(),
+                tuned_at: 0,
+            },
+        );
+        store.save(&dir).unwrap();
+
+        let loaded = TuningStore::load(&dir);
+        let entry = loaded
+            .entries
+            .get(&key)
+            .expect("Entry missing after reload");
+        assert_eq!((entry.tile_size, entry.batch_size), (384, 2));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tensor_format_round_trip() {
+        use crate::tensor_format::{
+            ChannelOrder, TensorFormat, TensorFormatOverride, TensorLayout,
+        };
+
+        // BGR, channels-last, -1..1 as declared in ONNX metadata
+        let declared = TensorFormatOverride::from_metadata(|key| match key {
+            "layout" => Some("NHWC".to_string()),
+            "channel_order" => Some("bgr".to_string()),
+            "input_range" => Some("[-1, 1]".to_string()),
+            "output_range" => Some("-1,1".to_string()),
+            "mean" => Some("0.5, nope, 0.5".to_string()),
+            _ => None,
+        });
+        assert_eq!(declared.layout, Some(TensorLayout::Nhwc));
+        assert_eq!(declared.channel_order, Some(ChannelOrder::Bgr));
+        assert_eq!(declared.mean, None);
+        let format = declared.apply(Default::default());
+
+        let batch = TileBatch {
+            data: (0..2 * 3 * 4 * 3).map(|i| i as f32 / 71.0).collect(),
+            len: 2,
+            channels: 3,
+            width: 4,
+            height: 3,
+        };
+        let mut encoded = Vec::new();
+        format.encode(&batch, &mut encoded);
+        assert_eq!(format.input_shape(&batch), vec![2, 3, 4, 3]);
+        // First pixel: B, G, R of the planar source, mapped to -1..1
+        let plane = 12;
+        for (k, c) in [2, 1, 0].into_iter().enumerate() {
+            assert!((encoded[k] - (batch.data[c * plane] * 2.0 - 1.0)).abs() < 1e-6);
+        }
+
+        let mut decoded = Vec::new();
+        let shape = format
+            .decode(&format.input_shape(&batch), &encoded, &mut decoded)
+            .expect("Decode failed");
+        assert_eq!(shape, batch.shape());
+        for (a, b) in decoded.iter().zip(&batch.data) {
+            assert!((a - b).abs() < 1e-6);
+        }
+
+        // Conventions that would divide by zero are rejected up front
+        let empty_range = TensorFormat {
+            output_range: [1.0, 1.0],
+            ..format
+        };
+        assert!(empty_range.validate().is_err());
+        let zero_std = TensorFormat {
+            std: [0.5, 0.0, 0.5],
+            ..format
+        };
+        assert!(zero_std.validate().is_err());
+    }
+
+    #[test]
+    fn test_grayscale_tensor_format() {
+        use crate::tensor_format::TensorFormat;
+
+        let format = TensorFormat::detect(&[1, 1, -1, -1], &[1, 1, -1, -1]);
+        assert_eq!((format.input_channels, format.output_channels), (1, 1));
+        assert!(format.validate().is_ok());
+        assert!(TensorFormat::detect(&[1, 2, 8, 8], &[1, 2, 8, 8])
+            .validate()
+            .is_err());
+        // RGBA is recognized (channels last here) and tiles its alpha
+        let rgba = TensorFormat::detect(&[1, 8, 8, 4], &[1, 32, 32, 4]);
+        assert_eq!(rgba.input_channels, 4);
+        assert!(rgba.validate().is_ok());
+        assert!(rgba.carries_alpha());
+
+        // A pure green tile becomes a single plane of its luma
+        let batch = TileBatch {
+            data: [vec![0.0; 4], vec![1.0; 4], vec![0.0; 4]].concat(),
+            len: 1,
+            channels: 3,
+            width: 2,
+            height: 2,
+        };
+        let mut encoded = Vec::new();
+        format.encode(&batch, &mut encoded);
+        assert_eq!(format.input_shape(&batch), vec![1, 1, 2, 2]);
+        assert!(encoded.iter().all(|&v| (v - 0.7152).abs() < 1e-6));
+
+        // And a single output plane is spread back over R, G and B
+        let mut decoded = Vec::new();
+        let shape = format
+            .decode(&[1, 1, 2, 2], &encoded, &mut decoded)
+            .expect("Decode failed");
+        assert_eq!(shape, vec![1, 3, 2, 2]);
+        assert!(decoded.iter().all(|&v| (v - 0.7152).abs() < 1e-6));
+    }
+
+    #[test]
+    fn test_model_params_from_config() {
+        use crate::models::ModelUserConfig;
+
+        let config: ModelUserConfig = serde_json::from_str(
+            r#"{"overrides": {"scunet_x1.onnx": {
+                "name": "SCUNet", "description": "Denoise", "batch_size": null,
+                "params": [{"name": "sigma", "label": "Denoise strength",
+                            "default": 15, "min": 0, "max": 50}]
+            }}}"#,
+        )
+        .expect("Config should parse");
+        let info = &config.overrides["scunet_x1.onnx"];
+        assert_eq!(info.tensor_format, None);
+        let sigma = &info.params[0];
+        assert_eq!(sigma.resolve(None), 15.0);
+        assert_eq!(sigma.resolve(Some(30.0)), 30.0);
+        assert_eq!(sigma.resolve(Some(80.0)), 50.0);
+        assert_eq!(sigma.resolve(Some(-1.0)), 0.0);
+    }
+
+    #[test]
+    fn test_model_alignment_precedence() {
+        use crate::model_roots::CONFIG_FILE;
+        use crate::models::{model_alignment, ModelMetadata};
+        use std::collections::HashMap;
+
+        let dir = std::env::temp_dir().join(format!("rustscale_align_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let declared = ModelMetadata::from_props(&HashMap::from([(
+            "alignment".to_string(),
+            "16".to_string(),
+        )]));
+
+        // Guessed from the architecture only when nothing is declared
+        let none = ModelMetadata::default();
+        assert_eq!(model_alignment(&dir, "a.onnx", &none, "4x-HAT-L"), 8);
+        assert_eq!(model_alignment(&dir, "a.onnx", &none, "SwinIR"), 1);
+        assert_eq!(model_alignment(&dir, "a.onnx", &declared, "SwinIR"), 16);
+
+        std::fs::write(
+            dir.join(CONFIG_FILE),
+            r#"{"overrides": {"a.onnx": {"name": "", "description": "", "alignment": 8}}}"#,
+        )
+        .unwrap();
+        assert_eq!(model_alignment(&dir, "a.onnx", &declared, "SwinIR"), 8);
+        assert_eq!(model_alignment(&dir, "b.onnx", &declared, "SwinIR"), 16);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_quantized_tensor_data() {
+        use crate::inference::TensorData;
+        use crate::tensor_format::{Quantization, TensorFormatOverride};
+        use ort::tensor::TensorElementType;
+
+        let data = [0.0, 0.2, 1.0, 1.5];
+        let mut typed = TensorData::empty(TensorElementType::Uint8).unwrap();
+        typed.fill_from_f32(&data, None);
+        assert!(matches!(&typed, TensorData::Uint8(v) if v == &[0, 51, 255, 255]));
+
+        typed = TensorData::empty(TensorElementType::Int8).unwrap();
+        typed.fill_from_f32(&data, None);
+        assert!(matches!(&typed, TensorData::Int8(v) if v == &[-128, -77, 127, 127]));
+
+        // The model's own scales from metadata replace the defaults
+        let declared = TensorFormatOverride::from_metadata(|key| match key {
+            "input_scale" => Some("0.02".to_string()),
+            "input_zero_point" => Some("10".to_string()),
+            _ => None,
+        });
+        let quant = declared.input_quant.expect("Quantization should parse");
+        assert_eq!(
+            quant,
+            Quantization {
+                scale: 0.02,
+                zero_point: 10
+            }
+        );
+        typed.fill_from_f32(&data, Some(quant));
+        assert!(matches!(&typed, TensorData::Int8(v) if v == &[10, 20, 60, 85]));
+        assert!((quant.dequantize(20.0) - 0.2).abs() < 1e-6);
+
+        assert!(TensorData::empty(TensorElementType::String).is_err());
+    }
+
+    #[test]
+    fn test_optimized_cache_keys() {
+        use crate::inference::{OptimizationLevel, OptimizedCache, SessionOptions};
+
+        let options: SessionOptions =
+            serde_json::from_str(r#"{"optimization_level": "extended", "inter_threads": 2}"#)
+                .expect("Options should parse");
+        assert_eq!(
+            options.optimization_level,
+            Some(OptimizationLevel::Extended)
+        );
+        assert_eq!(options.intra_threads, None);
+
+        let cache = OptimizedCache {
+            dir: std::path::PathBuf::from("optimized"),
+            model_hash: "ab".repeat(32),
+            runtime: "ORT Build Info: git-branch=rel-1.22.0".to_string(),
+            hardware: "NVIDIA GeForce RTX 3080 10240MB | AMD Ryzen 9 5900X x24".to_string(),
+        };
+        let path = cache.path("CPU", &options);
+        assert_eq!(path, cache.path("CPU", &options));
+        assert!(path.starts_with("optimized"));
+        assert_ne!(path, cache.path("CUDA (NVIDIA)", &options));
+        assert_ne!(path, cache.path("CPU", &SessionOptions::default()));
+        // A copied app data folder or a swapped GPU optimizes afresh
+        let moved = OptimizedCache {
+            hardware: "NVIDIA GeForce RTX 4090 24564MB | AMD Ryzen 9 5900X x24".to_string(),
+            ..cache.clone()
+        };
+        assert_ne!(path, moved.path("CPU", &options));
+
+        // Removing a model clears its optimized copies and markers, not others'
+        let dir =
+            std::env::temp_dir().join(format!("rustscale_optimized_{}", uuid::Uuid::new_v4()));
+        let cache = OptimizedCache {
+            dir: dir.clone(),
+            ..cache
+        };
+        let other = OptimizedCache {
+            model_hash: "cd".repeat(32),
+            ..cache.clone()
+        };
+        std::fs::create_dir_all(&dir).unwrap();
+        let kept = other.path("CPU", &options);
+        for file in [
+            cache.path("CPU", &options),
+            cache
+                .path("OpenVINO", &options)
+                .with_extension("unsupported"),
+            kept.clone(),
+        ] {
+            std::fs::write(file, b"").unwrap();
+        }
+        assert_eq!(OptimizedCache::clear(&dir, &cache.model_hash), 2);
+        assert!(kept.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_runtime_version_parsing() {
+        use crate::capabilities::{is_supported, parse_version};
+
+        let build =
+            "ORT Build Info: git-branch=rel-1.22.0, git-commit-id=f217402, build type=Release";
+        assert_eq!(parse_version(build).as_deref(), Some("1.22.0"));
+        assert_eq!(parse_version("git-branch=main").as_deref(), Some("main"));
+        assert_eq!(parse_version("ORT Build Info"), None);
+        assert!(!is_supported("tensorrt-but-misspelled"));
+    }
+
+    #[test]
+    fn test_runtime_error_classification() {
+        use crate::inference::{is_device_lost, is_out_of_memory};
+
+        let cuda = "CUDA failure 2: out of memory ; GPU=0 ; expr=cudaMalloc(&p, size);";
+        assert!(is_out_of_memory(cuda));
+        assert!(is_out_of_memory(
+            "Failed to allocate memory for requested buffer"
+        ));
+        // Mentioning memory is not an allocation failure
+        assert!(!is_out_of_memory(
+            "Non-zero status code returned while running MemcpyToHost"
+        ));
+        assert!(!is_out_of_memory("Invalid memory pattern setting"));
+
+        let removed = "DmlExecutionProvider failed with HRESULT 0x887A0005";
+        assert!(is_device_lost(removed));
+        assert!(!is_out_of_memory(removed));
+    }
+
+    // Hand-encoded protobuf: (field << 3 | wire type), then payload
+    fn varint(mut v: u64, out: &mut Vec<u8>) {
+        while v >= 0x80 {
+            out.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        out.push(v as u8);
+    }
+
+    fn int(field: u64, v: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        varint(field << 3, &mut out);
+        varint(v, &mut out);
+        out
+    }
+
+    fn bytes(field: u64, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        varint(field << 3 | 2, &mut out);
+        varint(payload.len() as u64, &mut out);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    // ONNX ValueInfoProto of a float tensor; Err is a symbolic dimension
+    fn value_info(name: &str, dims: &[Result<u64, &str>]) -> Vec<u8> {
+        let shape: Vec<u8> = dims
+            .iter()
+            .flat_map(|d| match d {
+                Ok(n) => bytes(1, &int(1, *n)),
+                Err(param) => bytes(1, &bytes(2, param.as_bytes())),
+            })
+            .collect();
+        let tensor = [int(1, 1), bytes(2, &shape)].concat();
+        [bytes(1, name.as_bytes()), bytes(2, &bytes(1, &tensor))].concat()
+    }
+
+    #[test]
+    fn test_onnx_header_scan() {
+        use crate::models::ModelMetadata;
+        use crate::onnx::{Dim, OnnxInfo};
+        use crate::tensor_format::Quantization;
+        use std::io::Cursor;
+
+        let weights = [bytes(8, b"w"), bytes(9, &[0u8; 4096])].concat();
+        let graph = [
+            bytes(5, &weights),
+            bytes(11, &value_info("input", &[Ok(1), Ok(3), Ok(64), Ok(64)])),
+            bytes(11, &value_info("w", &[Ok(4096)])),
+            bytes(
+                12,
+                &value_info("output", &[Err("batch"), Ok(3), Ok(128), Ok(128)]),
+            ),
+        ]
+        .concat();
+        let model = [
+            int(1, 8),
+            bytes(2, b"pytorch"),
+            bytes(3, b"2.1"),
+            bytes(7, &graph),
+            bytes(8, &int(2, 17)),
+            bytes(14, &[bytes(1, b"scale"), bytes(2, b"4x")].concat()),
+            bytes(
+                14,
+                &[bytes(1, b"license"), bytes(2, b" CC-BY-4.0 ")].concat(),
+            ),
+        ]
+        .concat();
+
+        let info = OnnxInfo::from_reader(Cursor::new(&model)).expect("Header should parse");
+        assert_eq!(info.opset, Some(17));
+        assert_eq!(info.producer.as_deref(), Some("pytorch 2.1"));
+        assert_eq!(info.inputs.len(), 1, "Initializers are not inputs");
+        assert_eq!(
+            info.outputs[0].shape[0],
+            Dim::Dynamic(Some("batch".to_string()))
+        );
+        let format = info.image_format(None).expect("Image model");
+        assert_eq!(info.static_scale(&format), Some(2));
+
+        let declared = ModelMetadata::from_props(&info.metadata);
+        assert_eq!(declared.scale, Some(4));
+        assert_eq!(declared.license.as_deref(), Some("CC-BY-4.0"));
+        assert_eq!(declared.author, None);
+
+        // Truncated files and non-image graphs are rejected with a reason
+        assert!(OnnxInfo::from_reader(Cursor::new(&model[..model.len() - 3])).is_err());
+        assert!(OnnxInfo::from_reader(Cursor::new(b"not an onnx file")).is_err());
+        let flat = [int(1, 8), bytes(7, &bytes(11, &value_info("x", &[Ok(10)])))].concat();
+        let info = OnnxInfo::from_reader(Cursor::new(&flat)).unwrap();
+        assert!(info.image_format(None).is_err());
+
+        // QDQ graph: scale and zero point come from the Q/DQ node initializers
+        let node = |op: &str, inputs: &[&str], output: &str| {
+            let inputs: Vec<u8> = inputs.iter().flat_map(|i| bytes(1, i.as_bytes())).collect();
+            bytes(
+                1,
+                &[inputs, bytes(2, output.as_bytes()), bytes(4, op.as_bytes())].concat(),
+            )
+        };
+        let in_scale = [
+            int(2, 1),
+            bytes(8, b"s"),
+            bytes(9, &(1.0f32 / 255.0).to_le_bytes()),
+        ];
+        let out_scale = [int(2, 1), bytes(4, &0.5f32.to_le_bytes()), bytes(8, b"s2")];
+        let graph = [
+            node("DequantizeLinear", &["input", "s", "zp"], "x"),
+            node("QuantizeLinear", &["x", "s2"], "output"),
+            bytes(5, &in_scale.concat()),
+            bytes(5, &[int(2, 2), bytes(8, b"zp"), bytes(9, &[128])].concat()),
+            bytes(5, &out_scale.concat()),
+            bytes(11, &value_info("input", &[Ok(1), Ok(3), Ok(64), Ok(64)])),
+            bytes(12, &value_info("output", &[Ok(1), Ok(3), Ok(128), Ok(128)])),
+        ]
+        .concat();
+        let qdq = [int(1, 8), bytes(7, &graph)].concat();
+        let info = OnnxInfo::from_reader(Cursor::new(&qdq)).unwrap();
+        let format = info.image_format(None).unwrap();
+        assert_eq!(
+            format.input_quant,
+            Some(Quantization {
+                scale: 1.0 / 255.0,
+                zero_point: 128
+            })
+        );
+        assert_eq!(
+            format.output_quant,
+            Some(Quantization {
+                scale: 0.5,
+                zero_point: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_model_cache_lru_budget() {
+        use crate::model_cache::{CacheBudget, Footprint, ModelCache};
+        use std::path::Path;
+
+        let mb = |ram_mb| Footprint { ram_mb, vram_mb: 0 };
+        let mut cache: ModelCache<&str> = ModelCache::new(CacheBudget::default());
+        let a = cache.insert(Path::new("a.onnx"), "aaa", Arc::new("a"), mb(400));
+        let b = cache.insert(Path::new("b.onnx"), "bbb", Arc::new("b"), mb(400));
+        cache.set_pinned(a, true);
+
+        // Hits are by content hash and request, and refresh recency
+        assert!(cache.get("bbb", |s| *s == "b").is_some());
+        assert!(cache.get("bbb", |s| *s == "other provider").is_none());
+
+        // Over a 1000 MB budget: the pinned entry stays, the LRU unpinned one goes
+        let c = cache.insert(Path::new("c.onnx"), "ccc", Arc::new("c"), mb(400));
+        let limits = CacheBudget {
+            ram_mb: Some(1000),
+            vram_mb: None,
+        }
+        .limits(16384, None);
+        assert_eq!(cache.evict_to_fit(&limits, c), 1);
+        let left: Vec<u64> = cache.entries().iter().map(|e| e.id).collect();
+        assert_eq!(left, vec![a, c]);
+        assert!(!left.contains(&b));
+
+        // A session still held by a job is not evicted for budget
+        let held = cache.get("ccc", |_| true).unwrap();
+        assert_eq!(cache.evict_to_fit(&mb(0), 0), 0);
+        drop(held);
+
+        // A changed file drops its sessions even when pinned
+        assert_eq!(cache.evict_path(Path::new("a.onnx")), 1);
+        assert_eq!(cache.usage(), mb(400));
+    }
+
+    #[test]
+    fn test_model_roots_resolution() {
+        use crate::model_roots::{ModelRoots, RootKind};
+
+        let base = std::env::temp_dir().join(format!("rustscale_roots_{}", uuid::Uuid::new_v4()));
+        let bundled = base.join("bundled");
+        let extra = base.join("extra");
+        std::fs::create_dir_all(bundled.join("nested")).unwrap();
+        std::fs::create_dir_all(extra.join("anime/.hidden")).unwrap();
+        for file in [
+            bundled.join("4x.onnx"),
+            bundled.join("nested/skipped.onnx"),
+            extra.join("4x.onnx"),
+            extra.join("anime/2x.onnx"),
+            extra.join("anime/.hidden/x.onnx"),
+        ] {
+            std::fs::write(file, b"").unwrap();
+        }
+
+        let data_dir = base.join("data");
+        let mut roots = ModelRoots::new(Some(bundled), &data_dir);
+        let added = roots.add(&extra, true).unwrap();
+        assert_eq!(added.kind, RootKind::Custom);
+        assert_eq!(roots.revision, 1);
+
+        // Custom folders persist across restarts
+        let roots = ModelRoots::new(None, &data_dir);
+        assert_eq!(roots.roots().len(), 2);
+        let mut roots = ModelRoots::new(Some(base.join("bundled")), &data_dir);
+
+        // Non-recursive roots ignore subfolders; hidden entries are skipped
+        let keys = |i: usize| -> Vec<String> {
+            ModelRoots::model_files(&roots.roots()[i])
+                .into_iter()
+                .map(|l| l.key)
+                .collect()
+        };
+        assert_eq!(keys(0), vec!["4x.onnx"]);
+        assert_eq!(keys(2), vec!["4x.onnx", "anime/2x.onnx"]);
+
+        // Ids are stable; bare filenames resolve to the first root that has them
+        let nested = roots
+            .resolve(&format!("{}/anime/2x.onnx", added.id))
+            .unwrap();
+        assert_eq!(nested.filename(), "2x.onnx");
+        assert_eq!(roots.resolve("4x").unwrap().id, "bundled/4x.onnx");
+        assert!(roots
+            .resolve(&format!("{}/../bundled/4x.onnx", added.id))
+            .is_err());
+        assert!(roots.resolve("user/4x.onnx").is_err());
+
+        // Folders inside a recursive root, or around any root, would list models twice
+        assert!(roots.add(&base.join("extra/anime"), false).is_err());
+        assert!(roots.add(&base, true).is_err());
+
+        roots.remove(&added.id).unwrap();
+        assert!(roots.remove("user").is_err());
+        assert_eq!(ModelRoots::new(None, &data_dir).roots().len(), 1);
+
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn test_catalog_resumable_download() {
+        use crate::catalog;
+        use sha2::{Digest, Sha256};
+        use std::sync::atomic::AtomicBool;
+
+        let base = std::env::temp_dir().join(format!("rustscale_catalog_{}", uuid::Uuid::new_v4()));
+        let (served, partial_dir, models) = (
+            base.join("served"),
+            base.join("downloads"),
+            base.join("models"),
+        );
+        std::fs::create_dir_all(&served).unwrap();
+
+        let model: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(served.join("4x test.onnx"), &model).unwrap();
+        let entry = |sha256: String| {
+            serde_json::json!({
+                "name": "Test", "filename": "4x-test.onnx", "url": "4x%20test.onnx",
+                "scale": 4, "size": model.len(), "sha256": sha256, "license": "CC0"
+            })
+        };
+        let index_path = served.join("index.json");
+        let good = format!("{:x}", Sha256::digest(&model));
+        let index = serde_json::json!({ "models": [entry(good.clone())] });
+        std::fs::write(&index_path, index.to_string()).unwrap();
+
+        // A file:// index; the relative model url resolves next to it
+        let source = format!(
+            "file://{}",
+            index_path.to_string_lossy().replace(' ', "%20")
+        );
+        let entries = catalog::fetch_index(&source).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].url.ends_with("/4x%20test.onnx"));
+
+        // An interrupted earlier attempt is resumed, not restarted
+        std::fs::create_dir_all(&partial_dir).unwrap();
+        std::fs::write(entries[0].partial_path(&partial_dir), &model[..40_000]).unwrap();
+        let mut first = None;
+        let cancel = AtomicBool::new(false);
+        let target = catalog::download(
+            &entries[0],
+            &partial_dir,
+            &models,
+            None,
+            &cancel,
+            |done, _| {
+                first.get_or_insert(done);
+            },
+        )
+        .unwrap();
+        assert_eq!(first, Some(40_000));
+        assert_eq!(std::fs::read(&target).unwrap(), model);
+        assert!(!entries[0].partial_path(&partial_dir).exists());
+
+        // Unsafe filenames and wrong checksums are refused
+        let mut bad = entries[0].clone();
+        bad.filename = "../escape.onnx".to_string();
+        assert!(catalog::download(&bad, &partial_dir, &models, None, &cancel, |_, _| {}).is_err());
+        let mut corrupt = entries[0].clone();
+        corrupt.filename = "4x-corrupt.onnx".to_string();
+        corrupt.sha256 = "0".repeat(64);
+        let err = catalog::download(&corrupt, &partial_dir, &models, None, &cancel, |_, _| {});
+        assert!(err.unwrap_err().to_string().contains("Checksum mismatch"));
+        assert!(!models.join("4x-corrupt.onnx").exists());
+
+        // A fixed-size 2x graph listed as 4x is not installed
+        let graph = [
+            bytes(11, &value_info("input", &[Ok(1), Ok(3), Ok(8), Ok(8)])),
+            bytes(12, &value_info("output", &[Ok(1), Ok(3), Ok(16), Ok(16)])),
+        ]
+        .concat();
+        let header = [int(1, 8), bytes(7, &graph)].concat();
+        std::fs::write(served.join("2x.onnx"), &header).unwrap();
+        let mislabeled = catalog::CatalogEntry {
+            filename: "2x.onnx".to_string(),
+            url: served.join("2x.onnx").to_string_lossy().to_string(),
+            size: header.len() as u64,
+            sha256: format!("{:x}", Sha256::digest(&header)),
+            ..entries[0].clone()
+        };
+        let err = catalog::download(&mislabeled, &partial_dir, &models, None, &cancel, |_, _| {});
+        assert!(err.unwrap_err().to_string().contains("graph is 2x"));
+        assert!(!models.join("2x.onnx").exists());
+
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn test_import_and_remove_model() {
+        use crate::model_roots::CONFIG_FILE;
+        use crate::models::{ModelUserConfig, UserModelInfo};
+        use crate::state::AppState;
+        use crate::tuning::{TunedSettings, TuningStore};
+
+        let base = std::env::temp_dir().join(format!("rustscale_import_{}", uuid::Uuid::new_v4()));
+        let (bundled, outside, data_dir) = (
+            base.join("bundled"),
+            base.join("outside"),
+            base.join("data"),
+        );
+        std::fs::create_dir_all(&bundled).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(bundled.join("shipped.onnx"), b"shipped").unwrap();
+        let state = AppState::with_dirs(data_dir.clone(), Some(bundled));
+        let user_dir = data_dir.join("models");
+
+        // Rejections name the failed check, before anything is copied
+        let reason =
+            |path: &std::path::Path| state.import_model(path, false).unwrap_err().to_string();
+        std::fs::write(outside.join("notes.txt"), b"text").unwrap();
+        assert!(reason(&outside.join("notes.txt")).contains("not an .onnx file"));
+        std::fs::write(user_dir.join("taken.onnx"), b"old").unwrap();
+        std::fs::write(outside.join("taken.onnx"), b"new").unwrap();
+        assert!(reason(&outside.join("taken.onnx")).contains("already installed"));
+        let flat = [
+            int(1, 8),
+            bytes(
+                7,
+                &[
+                    bytes(11, &value_info("x", &[Ok(10)])),
+                    bytes(12, &value_info("y", &[Ok(10)])),
+                ]
+                .concat(),
+            ),
+        ]
+        .concat();
+        std::fs::write(outside.join("flat.onnx"), flat).unwrap();
+        assert!(reason(&outside.join("flat.onnx")).contains("Not an image model"));
+        assert!(!user_dir.join("flat.onnx").exists());
+
+        // Removal drops the override, cached scale and tuning of that file
+        let model = user_dir.join("4x-test.onnx");
+        std::fs::write(&model, b"weights").unwrap();
+        let mut config = ModelUserConfig::default();
+        config.overrides.insert(
+            "4x-test.onnx".to_string(),
+            UserModelInfo {
+                name: "Mine".to_string(),
+                ..Default::default()
+            },
+        );
+        config.save(&user_dir.join(CONFIG_FILE)).unwrap();
+        state.scale_cache.lock().unwrap().insert(model.clone(), 4);
+        let hash = state.model_hash(&model).unwrap();
+        state.tuning.lock().unwrap().entries.insert(
+            TuningStore::key(&hash, "cpu"),
+            TunedSettings {
+                tile_size: 256,
+                batch_size: 2,
+                megapixels_per_second: 1.0,
+                peak_memory_mb: 0,
+                execution_provider: "CPU".to_string(),
+                tuned_at: 0,
+            },
+        );
+
+        // ...but tuning survives while an identical copy remains elsewhere
+        let copy = user_dir.join("copy.onnx");
+        std::fs::write(&copy, b"weights").unwrap();
+        state.remove_model("user/copy.onnx").unwrap();
+        assert!(!copy.exists());
+        assert_eq!(state.tuning.lock().unwrap().entries.len(), 1);
+
+        state.remove_model("user/4x-test.onnx").unwrap();
+        assert!(!model.exists());
+        assert!(ModelUserConfig::load(&user_dir.join(CONFIG_FILE))
+            .overrides
+            .is_empty());
+        assert!(state.scale_cache.lock().unwrap().is_empty());
+        assert!(state.tuning.lock().unwrap().entries.is_empty());
+
+        // A config that can't be parsed is neither rewritten nor half-applied
+        let config_path = user_dir.join(CONFIG_FILE);
+        std::fs::write(&model, b"weights").unwrap();
+        std::fs::write(&config_path, b"{ \"overrides\": ").unwrap();
+        assert!(ModelUserConfig::load_strict(&config_path).is_err());
+        assert!(state.remove_model("user/4x-test.onnx").is_err());
+        assert!(model.exists());
+        assert_eq!(std::fs::read(&config_path).unwrap(), b"{ \"overrides\": ");
+        std::fs::remove_file(&config_path).unwrap();
+        assert!(ModelUserConfig::load_strict(&config_path)
+            .unwrap()
+            .overrides
+            .is_empty());
+
+        assert!(state.remove_model("bundled/shipped.onnx").is_err());
+        assert!(base.join("bundled/shipped.onnx").exists());
+
+        let _ = std::fs::remove_dir_all(&base);
+    }
 }
@@ -0,0 +1,394 @@
+use crate::error::{AppError, AppResult};
+use crate::gpu::GpuInfo;
+use crate::image_processing::TileBatch;
+use crate::inference::{self, BoundParam, CancelToken, OrtSession};
+use crate::tensor_format::TensorFormat;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use sysinfo::{ProcessesToUpdate, System};
+
+/// Tile sizes (content, without padding) tried by the sweep.
+const TILE_CANDIDATES: [u32; 6] = [128, 192, 256, 384, 512, 768];
+const BATCH_CANDIDATES: [usize; 4] = [1, 2, 4, 8];
+
+/// Batches larger than this many input pixels are skipped to keep the sweep short.
+const MAX_BATCH_PIXELS: u64 = 4 * 1024 * 1024;
+
+/// Each candidate runs until it has been timed for this long (or MAX_RUNS).
+const MEASURE_TIME: Duration = Duration::from_millis(750);
+const MAX_RUNS: u32 = 8;
+
+/// Hard cap on the whole sweep. Slow providers (CPU) stop early with what they have.
+const SWEEP_BUDGET: Duration = Duration::from_secs(90);
+
+/// Candidates within this fraction of the best throughput count as equally fast;
+/// the one with the smallest batch footprint wins to leave memory headroom.
+const THROUGHPUT_TOLERANCE: f32 = 0.97;
+
+const TUNING_FILE: &str = "tuning.json";
+
+/// Best tile/batch setting found for one model on one machine and provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunedSettings {
+    pub tile_size: u32,
+    pub batch_size: usize,
+    pub megapixels_per_second: f32,
+    pub peak_memory_mb: u64,
+    pub execution_provider: String,
+    pub tuned_at: u64, // Unix seconds
+}
+
+/// One measured tile/batch combination.
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkSample {
+    pub tile_size: u32,
+    pub batch_size: usize,
+    pub megapixels_per_second: f32,
+    // High-water mark since the sweep started; ORT arenas don't shrink between runs
+    pub peak_memory_mb: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkReport {
+    pub model_hash: String,
+    pub hardware: String,
+    pub best: TunedSettings,
+    pub samples: Vec<BenchmarkSample>,
+}
+
+/// Persisted benchmark results, keyed by model hash and hardware fingerprint.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TuningStore {
+    pub entries: HashMap<String, TunedSettings>,
+}
+
+impl TuningStore {
+    fn path(app_data_dir: &Path) -> PathBuf {
+        app_data_dir.join(TUNING_FILE)
+    }
+
+    pub fn key(model_hash: &str, hardware: &str) -> String {
+        format!("{}@{}", model_hash, hardware)
+    }
+
+    pub fn load(app_data_dir: &Path) -> Self {
+        let path = Self::path(app_data_dir);
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring unreadable tuning file {:?}: {}", path, e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, app_data_dir: &Path) -> AppResult<()> {
+        std::fs::create_dir_all(app_data_dir)?;
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(Self::path(app_data_dir), content)?;
+        Ok(())
+    }
+}
+
+/// SHA-256 of the model file, hex encoded. Identifies a model independent of its filename.
+pub fn hash_model(path: &Path) -> AppResult<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Identifies the machine a tuning result is valid for: provider, GPU, CPU and OS.
+pub fn hardware_fingerprint(gpu: Option<&GpuInfo>, execution_provider: &str) -> String {
+    format!("{} | {}", execution_provider, machine_fingerprint(gpu))
+}
+
+/// GPU, CPU and OS part of `hardware_fingerprint`, for keys that hold the
+/// provider separately.
+pub fn machine_fingerprint(gpu: Option<&GpuInfo>) -> String {
+    let mut sys = System::new();
+    sys.refresh_cpu_all();
+    let cpu = sys
+        .cpus()
+        .first()
+        .map(|c| c.brand().trim().to_string())
+        .unwrap_or_default();
+
+    let gpu = gpu
+        .map(|g| format!("{} {}MB", g.name, g.vram_total_mb))
+        .unwrap_or_else(|| "no-gpu".to_string());
+
+    format!(
+        "{} | {} x{} | {}",
+        gpu,
+        cpu,
+        sys.cpus().len(),
+        System::long_os_version().unwrap_or_else(System::cpu_arch)
+    )
+}
+
+/// Memory in use by the provider's device, in MB: VRAM for GPU providers,
+/// process resident memory for CPU.
+fn memory_in_use_mb(on_gpu: bool, sys: &mut System) -> u64 {
+    if on_gpu {
+        if let Ok(info) = GpuInfo::detect() {
+            return info.vram_used_mb;
+        }
+    }
+    process_memory_mb(sys)
+}
+
+/// Resident memory of this process, in MB.
+pub fn process_memory_mb(sys: &mut System) -> u64 {
+    let Ok(pid) = sysinfo::get_current_pid() else {
+        return 0;
+    };
+    sys.refresh_processes(ProcessesToUpdate::Some(&[pid]), false);
+    sys.process(pid)
+        .map(|p| p.memory() / 1024 / 1024)
+        .unwrap_or(0)
+}
+
+/// Textured stand-in for real content, as a planar batch. Values don't affect
+/// speed much, but a constant tensor could hit shortcuts some providers take for zeros.
+fn synthetic_batch((width, height): (u32, u32), len: usize) -> TileBatch {
+    let plane = width as usize * height as usize;
+    let data = (0..len * 3 * plane)
+        .map(|i| {
+            let (x, y) = ((i % plane) as u32 % width, (i % plane) as u32 / width);
+            let tile = (i / plane / 3) as u32;
+            match i / plane % 3 {
+                0 => x as f32 / width as f32,
+                1 => y as f32 / height as f32,
+                _ => {
+                    let n = x.wrapping_mul(374761393) ^ y.wrapping_mul(668265263) ^ tile;
+                    (n >> 13) as u8 as f32 / 255.0
+                }
+            }
+        })
+        .collect();
+    TileBatch {
+        data,
+        len,
+        channels: 3,
+        width,
+        height,
+    }
+}
+
+/// Runs `len` synthetic tiles of `size` (model input pixels) once through every
+/// session, so later runs at that shape find their buffers and kernels ready.
+pub fn warm_up(
+    session: &OrtSession,
+    size: (u32, u32),
+    len: usize,
+    format: &TensorFormat,
+    params: &[BoundParam],
+) -> AppResult<()> {
+    let input = synthetic_batch(size, len);
+    let pool = inference::BufferPool::new(2);
+    let cancel = CancelToken::new()?;
+    let mut output = Vec::new();
+    // Sessions are handed out round-robin, so each gets one run
+    for _ in 0..session.pool_size() {
+        output = session
+            .run_planar(&input, &mut output, &pool, format, params, &cancel)?
+            .data;
+    }
+    Ok(())
+}
+
+/// Times `runs` inference calls on one batch, after a warm-up run. A CPU session
+/// pool runs one loop per session concurrently, as the tiler would.
+/// Returns content megapixels per second.
+fn measure(
+    session: &OrtSession,
+    (tile_w, tile_h): (u32, u32),
+    padding: u32,
+    batch_size: usize,
+    format: &TensorFormat,
+    params: &[BoundParam],
+    cancel: &CancelToken,
+) -> AppResult<f32> {
+    let input = synthetic_batch((tile_w + padding * 2, tile_h + padding * 2), batch_size);
+    let pool = inference::BufferPool::new(2 * session.pool_size());
+
+    let run_loop = |start: Option<Instant>| -> AppResult<u32> {
+        let mut output = Vec::new();
+        // First run pays for arena growth and kernel selection
+        let Some(start) = start else {
+            session.run_planar(&input, &mut output, &pool, format, params, cancel)?;
+            return Ok(0);
+        };
+        let mut runs = 0;
+        while runs < MAX_RUNS && (runs == 0 || start.elapsed() < MEASURE_TIME) {
+            output = session
+                .run_planar(&input, &mut output, &pool, format, params, cancel)?
+                .data;
+            runs += 1;
+        }
+        Ok(runs)
+    };
+
+    let loops = |start: Option<Instant>| -> AppResult<u32> {
+        std::thread::scope(|s| {
+            let handles: Vec<_> = (0..session.pool_size())
+                .map(|_| s.spawn(|| run_loop(start)))
+                .collect();
+            handles.into_iter().try_fold(0, |total, handle| {
+                let runs = handle
+                    .join()
+                    .map_err(|_| AppError::Unknown("Benchmark thread panicked".to_string()))??;
+                Ok(total + runs)
+            })
+        })
+    };
+
+    loops(None)?;
+    let start = Instant::now();
+    let runs = loops(Some(start))?;
+
+    let content_pixels = tile_w as f64 * tile_h as f64 * batch_size as f64 * runs as f64;
+    Ok((content_pixels / start.elapsed().as_secs_f64() / 1_000_000.0) as f32)
+}
+
+/// Sweeps tile and batch sizes on synthetic tiles and returns the fastest setting.
+///
+/// Batch sizes grow per tile size until throughput stops improving or memory runs
+/// out; a tile size that fails at batch 1 ends the sweep. Static model dimensions
+/// pin the corresponding axis.
+pub fn benchmark<F>(
+    session: &OrtSession,
+    format: &TensorFormat,
+    params: &[BoundParam],
+    padding: u32,
+    cancel: &CancelToken,
+    mut on_sample: F,
+) -> AppResult<(TunedSettings, Vec<BenchmarkSample>)>
+where
+    F: FnMut(f32, &BenchmarkSample),
+{
+    let dims = session.static_input_dims(format.layout)?;
+    let content = |fixed: Option<u32>, tile_size: u32| match fixed {
+        Some(size) if size <= padding * 2 => Err(AppError::Unknown(format!(
+            "Model input size {} is too small for padding {}",
+            size, padding
+        ))),
+        Some(size) => Ok(size - padding * 2),
+        None => Ok(tile_size),
+    };
+    // A fully fixed input leaves a single tile shape to measure
+    let candidates: &[u32] = match (dims.width, dims.height) {
+        (Some(_), Some(_)) => &TILE_CANDIDATES[..1],
+        _ => &TILE_CANDIDATES,
+    };
+    let tiles = candidates
+        .iter()
+        .map(|&t| Ok((t, (content(dims.width, t)?, content(dims.height, t)?))))
+        .collect::<AppResult<Vec<_>>>()?;
+    let batches: Vec<usize> = match dims.batch {
+        Some(n) => vec![n],
+        None => BATCH_CANDIDATES.to_vec(),
+    };
+
+    let on_gpu = session.execution_provider != "CPU";
+    let mut sys = System::new();
+    let mut peak_memory_mb = memory_in_use_mb(on_gpu, &mut sys);
+    let total = (tiles.len() * batches.len()) as f32;
+    let sweep_start = Instant::now();
+    let mut samples: Vec<BenchmarkSample> = Vec::new();
+
+    'tiles: for (ti, &(tile_size, tile_dims)) in tiles.iter().enumerate() {
+        let mut previous = 0.0;
+        for (bi, &batch_size) in batches.iter().enumerate() {
+            if cancel.is_cancelled() {
+                return Err(AppError::Cancelled);
+            }
+            if sweep_start.elapsed() > SWEEP_BUDGET {
+                tracing::info!("Benchmark time budget reached, stopping sweep");
+                break 'tiles;
+            }
+            let input_pixels =
+                (tile_dims.0 + padding * 2) as u64 * (tile_dims.1 + padding * 2) as u64;
+            if bi > 0 && input_pixels * batch_size as u64 > MAX_BATCH_PIXELS {
+                break;
+            }
+
+            let result = measure(
+                session, tile_dims, padding, batch_size, format, params, cancel,
+            );
+            if let Err(AppError::Cancelled) = result {
+                return Err(AppError::Cancelled);
+            }
+            peak_memory_mb = peak_memory_mb.max(memory_in_use_mb(on_gpu, &mut sys));
+            let sample = BenchmarkSample {
+                tile_size,
+                batch_size,
+                megapixels_per_second: *result.as_ref().unwrap_or(&0.0),
+                peak_memory_mb,
+                error: result.as_ref().err().map(|e| e.to_string()),
+            };
+            tracing::info!(
+                "Benchmark tile {} batch {}: {:.2} MP/s{}",
+                tile_size,
+                batch_size,
+                sample.megapixels_per_second,
+                sample
+                    .error
+                    .as_deref()
+                    .map(|e| format!(" ({})", e))
+                    .unwrap_or_default()
+            );
+
+            let progress = (ti * batches.len() + bi + 1) as f32 / total;
+            on_sample(progress, &sample);
+            let throughput = sample.megapixels_per_second;
+            let failed = sample.error.is_some();
+            samples.push(sample);
+
+            if failed && bi == 0 {
+                break 'tiles;
+            }
+            // Bigger batches past the knee (or past a failure) only cost memory
+            if failed || throughput < previous {
+                break;
+            }
+            previous = throughput;
+        }
+    }
+
+    let fastest = samples
+        .iter()
+        .filter(|s| s.error.is_none())
+        .map(|s| s.megapixels_per_second)
+        .fold(0.0f32, f32::max);
+    let best = samples
+        .iter()
+        .filter(|s| s.error.is_none() && s.megapixels_per_second >= fastest * THROUGHPUT_TOLERANCE)
+        .min_by_key(|s| s.tile_size as u64 * s.tile_size as u64 * s.batch_size as u64)
+        .ok_or_else(|| match samples.first().and_then(|s| s.error.clone()) {
+            Some(e) => AppError::InferenceError(format!("Benchmark failed: {}", e)),
+            None => AppError::Unknown("Benchmark produced no results".to_string()),
+        })?;
+
+    let tuned_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    Ok((
+        TunedSettings {
+            tile_size: best.tile_size,
+            batch_size: best.batch_size,
+            megapixels_per_second: best.megapixels_per_second,
+            peak_memory_mb: best.peak_memory_mb,
+            execution_provider: session.execution_provider.clone(),
+            tuned_at,
+        },
+        samples,
+    ))
+}
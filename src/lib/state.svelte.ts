@@ -14,9 +14,125 @@ export interface ModelManifest {
     name: string;
     description: string;
     filename: string;
+    // Id of the model folder it was found in
+    root: string;
     scale: number;
     alignment: number;
     batch_size?: number;
+    params?: ModelParam[];
+    onnx?: OnnxInfo;
+    // From the model's ONNX metadata_props unless overridden in model_config.json
+    architecture?: string;
+    author?: string;
+    license?: string;
+    content?: string;
+    tile_size?: number;
+}
+
+// Graph header read at scan time; dims are numbers, or names/null when dynamic
+export interface OnnxTensor {
+    name: string;
+    dtype: string;
+    shape: (number | string | null)[];
+}
+
+export interface OnnxInfo {
+    ir_version: number;
+    opset?: number;
+    producer?: string;
+    inputs: OnnxTensor[];
+    outputs: OnnxTensor[];
+    metadata: Record<string, string>;
+}
+
+// A model in the shared catalog index and its state on this machine
+export interface CatalogModel {
+    name: string;
+    description: string;
+    filename: string;
+    url: string;
+    scale: number;
+    size: number;
+    sha256: string;
+    license?: string;
+    installed: boolean;
+    // Bytes of an interrupted download, resumed by the next attempt
+    downloaded: number;
+}
+
+export interface ModelCatalog {
+    source: string | null;
+    models: CatalogModel[];
+}
+
+// A folder models are loaded from, in lookup order
+export interface ModelRoot {
+    id: string;
+    kind: "bundled" | "user" | "custom";
+    path: string;
+    recursive: boolean;
+}
+
+interface ModelScan {
+    models: ModelManifest[];
+    rejected: { id: string; filename: string; reason: string }[];
+    roots: ModelRoot[];
+}
+
+// Extra input of a multi-input model (e.g. denoise strength)
+export interface ModelParam {
+    name: string;
+    label?: string;
+    default: number;
+    min?: number;
+    max?: number;
+}
+
+// Execution provider as reported by get_capabilities
+export interface ProviderInfo {
+    id: string;
+    label: string;
+    ort_name: string;
+    compiled: boolean;
+    available: boolean;
+}
+
+export interface Capabilities {
+    runtime: { version?: string; build: string; library: string };
+    providers: ProviderInfo[];
+    cpu: {
+        brand: string;
+        arch: string;
+        physical_cores?: number;
+        logical_cores: number;
+        simd: string[];
+    };
+    memory: { total_mb: number; available_mb: number };
+    sessions: {
+        model: string;
+        requested: string;
+        execution_provider: string;
+        sessions: number;
+    }[];
+}
+
+// Session held by the backend model cache (list_cached_models)
+export interface CachedModel {
+    id: number;
+    model: string;
+    path: string;
+    requested: string;
+    execution_provider: string;
+    footprint: { ram_mb: number; vram_mb: number };
+    pinned: boolean;
+    in_use: boolean;
+}
+
+export interface ModelCacheReport {
+    budget: { ram_mb?: number; vram_mb?: number };
+    limits: { ram_mb: number; vram_mb: number };
+    usage: { ram_mb: number; vram_mb: number };
+    sessions: CachedModel[];
 }
 
 interface UpscaleConfig {
@@ -27,7 +143,10 @@ interface UpscaleConfig {
     format: "png" | "webp" | "jpeg";
     compression: "lossy" | "lossless";
     preferNpu: boolean;
-    executionProvider: "auto" | "directml" | "openvino" | "cpu";
+    // "auto" or a provider id from get_capabilities
+    executionProvider: string;
+    // Values for model extra inputs, per model id then input name
+    modelParams: Record<string, Record<string, number>>;
 }
 
 
@@ -40,6 +159,12 @@ interface ProgressPayload {
     current_file?: string;
 }
 
+interface StagePayload {
+    job_id: string;
+    stage: string;
+    current_file?: string;
+}
+
 interface ImageMetadata {
     width: number;
     height: number;
@@ -68,6 +193,7 @@ class AppState {
     // Processing State
     status = $state<"idle" | "processing" | "cancelling" | "done" | "error">("idle");
     progress = $state(0);
+    stage = $state<string>(""); // e.g. "Pass 2/3" for chained jobs
     executionProvider = $state<string>("");
     currentJobId = $state<string | null>(null);
     isModelLoading = $state(false);
@@ -84,7 +210,8 @@ class AppState {
         format: "jpeg",
         compression: "lossy",
         preferNpu: false,
-        executionProvider: "auto"
+        executionProvider: "auto",
+        modelParams: {}
     });
 
     // Theme (persisted)
@@ -107,7 +234,12 @@ class AppState {
 
     // Models
     models = $state<ModelManifest[]>([]);
+    modelRoots = $state<ModelRoot[]>([]);
+    // Benchmarked batch size per model id, as reported by preload (not persisted)
+    tunedBatchSizes = $state<Record<string, number>>({});
     private lastSaveTime = 0;
+    // Rejected model files already shown in a toast
+    private reportedRejections = new Set<string>();
     private loadTimeout: any = null; // For debouncing model loads
 
     constructor() {
@@ -128,7 +260,17 @@ class AppState {
         // console.log("[DIAGNOSTIC] [AppState] loadModels START");
         try {
             const { invoke } = await import("@tauri-apps/api/core");
-            const fetchedModels = await invoke<ModelManifest[]>("get_models");
+            const scan = await invoke<ModelScan>("get_models");
+            const fetchedModels = scan.models;
+            this.modelRoots = scan.roots;
+
+            // Tell the user once per file why a model is not listed
+            for (const { id, filename, reason } of scan.rejected) {
+                if (this.reportedRejections.has(id)) continue;
+                this.reportedRejections.add(id);
+                console.warn(`[AppState] Rejected model ${filename}: ${reason}`);
+                this.addToast(`${filename} skipped: ${reason}`, "error");
+            }
             // console.log(`[DIAGNOSTIC] [AppState] Fetched ${fetchedModels.length} models.`);
 
             // Log the batch size of the currently active model from the fetch
@@ -143,6 +285,17 @@ class AppState {
 
             this.models = fetchedModels;
 
+            // Saved configs from before model folders hold a bare filename
+            const legacy = this.models.find(m => m.filename === this.config.model);
+            if (!this.models.find(m => m.id === this.config.model) && legacy) {
+                const params = this.config.modelParams[legacy.filename];
+                if (params) {
+                    this.config.modelParams[legacy.id] = params;
+                    delete this.config.modelParams[legacy.filename];
+                }
+                this.updateConfig({ model: legacy.id });
+            }
+
             // Ensure selected model is valid
             if (this.models.length > 0 && !this.models.find(m => m.id === this.config.model)) {
                 // console.log("[DIAGNOSTIC] [AppState] Active model invalid, switching to first available.");
@@ -159,6 +312,33 @@ class AppState {
         }
     }
 
+    // Validate a model file and copy (or link) it into the user model folder
+    async importModel(path: string, link = false) {
+        try {
+            const { invoke } = await import("@tauri-apps/api/core");
+            const manifest = await invoke<ModelManifest>("import_model", { path, link });
+            await this.loadModels();
+            this.addToast(`Imported ${manifest.name} (${manifest.scale}x)`, "success");
+            this.setModel(manifest.id);
+        } catch (e: any) {
+            console.error("Failed to import model:", e);
+            this.addToast(e?.message ?? String(e), "error");
+        }
+    }
+
+    async removeModel(id: string) {
+        try {
+            const { invoke } = await import("@tauri-apps/api/core");
+            await invoke("remove_model", { id });
+            delete this.config.modelParams[id];
+            await this.loadModels();
+            this.addToast("Model removed", "success");
+        } catch (e: any) {
+            console.error("Failed to remove model:", e);
+            this.addToast(e?.message ?? String(e), "error");
+        }
+    }
+
     // ===== Toast Management =====
     addToast(message: string, type: ToastType = "info") {
         const id = Date.now();
@@ -173,6 +353,7 @@ class AppState {
         this.status = isProcessing ? "processing" : "idle";
         if (!isProcessing) {
             this.executionProvider = "";
+            this.stage = "";
         }
     }
 
@@ -264,6 +445,19 @@ class AppState {
         this.persistConfig();
     }
 
+    // Value for an extra input of the active model
+    setModelParam(name: string, value: number) {
+        const model = this.config.model;
+        this.config.modelParams[model] = { ...this.config.modelParams[model], [name]: value };
+        this.persistConfig();
+    }
+
+    // Batch size for jobs: the model's saved value, then its benchmark, then the global setting
+    batchSizeFor(modelId: string): number {
+        const manifest = this.models.find(m => m.id === modelId);
+        return manifest?.batch_size || this.tunedBatchSizes[modelId] || this.config.batchSize;
+    }
+
     // Explicitly switch model
     async setModel(modelId: string) {
         const newModel = this.models.find(m => m.id === modelId);
@@ -291,7 +485,7 @@ class AppState {
 
                 // Call backend
                 const response = await invoke<{ scale: number; batch_size?: number }>("preload_model", {
-                    modelFilename: newModel.filename,
+                    modelId: newModel.id,
                     preferNpu: this.config.preferNpu,
                     executionProvider: this.config.executionProvider,
                 });
@@ -310,6 +504,11 @@ class AppState {
                     this.addToast(`Model Scale Detected: ${response.scale}x`, "info");
                 }
 
+                // Kept per model; a batch size the user saved for the model still wins
+                if (response.batch_size) {
+                    this.tunedBatchSizes[requestedModelId] = response.batch_size;
+                }
+
                 // Show toast for provider switch if it was a reload
                 // (Optional: could add logic to detect if this was a provider switch vs model switch)
 
@@ -407,10 +606,11 @@ class AppState {
                 config: {
                     model: this.config.model,
                     scale: this.config.scale,
-                    batch_size: this.config.batchSize,
+                    batch_size: this.batchSizeFor(this.config.model),
 
                     prefer_npu: this.config.preferNpu,
                     execution_provider: this.config.executionProvider,
+                    params: this.config.modelParams[this.config.model],
                 },
                 id: this.currentJobId // Pass the ID to backend
             });
@@ -422,7 +622,7 @@ class AppState {
         } catch (e: any) {
             console.error(e);
             const errStr = typeof e === 'string' ? e : JSON.stringify(e);
-            if (errStr.toLowerCase().includes("cancelled")) {
+            if (e?.type === "Cancelled") {
                 this.addToast("Job cancelled", "info");
             } else {
                 this.addToast(`Error: ${errStr}`, "error");
@@ -464,11 +664,12 @@ class AppState {
                 config: {
                     model: this.config.model,
                     scale: this.config.scale,
-                    batch_size: this.config.batchSize,
+                    batch_size: this.batchSizeFor(this.config.model),
                     format: this.config.format,
                     compression: this.config.compression,
                     prefer_npu: this.config.preferNpu,
                     execution_provider: this.config.executionProvider,
+                    params: this.config.modelParams[this.config.model],
                 },
                 id: this.currentJobId
             });
@@ -489,6 +690,7 @@ class AppState {
         this.resultImage = null;
         this.status = "idle";
         this.progress = 0;
+        this.stage = "";
         this.executionProvider = "";
         this.currentJobId = null;
     }
@@ -530,6 +732,11 @@ class AppState {
                 }
             });
 
+            // Pipeline stage changes (multi-pass jobs)
+            await listen<StagePayload>('upscale-stage', (event) => {
+                this.stage = event.payload.stage;
+            });
+
             // Batch Progress Events
             await listen<any>('batch-progress', (event) => {
                 const { current } = event.payload;